@@ -0,0 +1,295 @@
+use crate::runlist::Runlist;
+use anyhow::{bail, Result};
+use byteorder::{ByteOrder, LittleEndian};
+
+// Define constants for the attribute type codes
+pub const ATTRIBUTE_TYPE_STANDARD_INFORMATION: u32 = 0x10;
+pub const ATTRIBUTE_TYPE_ATTRIBUTE_LIST: u32 = 0x20;
+pub const ATTRIBUTE_TYPE_FILE_NAME: u32 = 0x30;
+pub const ATTRIBUTE_TYPE_OBJECT_ID: u32 = 0x40;
+pub const ATTRIBUTE_TYPE_SECURITY_DESCRIPTOR: u32 = 0x50;
+pub const ATTRIBUTE_TYPE_VOLUME_NAME: u32 = 0x60;
+pub const ATTRIBUTE_TYPE_VOLUME_INFORMATION: u32 = 0x70;
+pub const ATTRIBUTE_TYPE_DATA: u32 = 0x80;
+pub const ATTRIBUTE_TYPE_INDEX_ROOT: u32 = 0x90;
+pub const ATTRIBUTE_TYPE_INDEX_ALLOCATION: u32 = 0xA0;
+pub const ATTRIBUTE_TYPE_BITMAP: u32 = 0xB0;
+pub const ATTRIBUTE_TYPE_REPARSE_POINT: u32 = 0xC0;
+pub const ATTRIBUTE_TYPE_EA_INFORMATION: u32 = 0xD0;
+pub const ATTRIBUTE_TYPE_EA: u32 = 0xE0;
+pub const ATTRIBUTE_TYPE_LOGGED_UTILITY_STREAM: u32 = 0x100;
+pub const ATTRIBUTE_TYPE_END: u32 = 0xFFFF_FFFF;
+
+// Define constants for the attribute header flags
+pub const ATTRIBUTE_FLAG_COMPRESSED: u16 = 0x0001;
+pub const ATTRIBUTE_FLAG_ENCRYPTED: u16 = 0x4000;
+pub const ATTRIBUTE_FLAG_SPARSE: u16 = 0x8000;
+
+// Define constants for the attribute header offsets and sizes
+pub(crate) const ATTRIBUTE_TYPE_OFFSET: usize = 0x00;
+pub(crate) const ATTRIBUTE_LENGTH_OFFSET: usize = 0x04;
+pub(crate) const ATTRIBUTE_NON_RESIDENT_OFFSET: usize = 0x08;
+pub(crate) const ATTRIBUTE_NAME_LENGTH_OFFSET: usize = 0x09;
+pub(crate) const ATTRIBUTE_NAME_OFFSET_OFFSET: usize = 0x0A;
+const ATTRIBUTE_FLAGS_OFFSET: usize = 0x0C;
+const ATTRIBUTE_ID_OFFSET: usize = 0x0E;
+pub(crate) const RESIDENT_VALUE_LENGTH_OFFSET: usize = 0x10;
+pub(crate) const RESIDENT_VALUE_OFFSET_OFFSET: usize = 0x14;
+const RESIDENT_INDEXED_FLAG_OFFSET: usize = 0x16;
+pub(crate) const RESIDENT_HEADER_SIZE: usize = 0x18;
+const NON_RESIDENT_STARTING_VCN_OFFSET: usize = 0x10;
+const NON_RESIDENT_LAST_VCN_OFFSET: usize = 0x18;
+pub(crate) const NON_RESIDENT_RUNLIST_OFFSET_OFFSET: usize = 0x20;
+const NON_RESIDENT_COMPRESSION_UNIT_OFFSET: usize = 0x22;
+pub(crate) const NON_RESIDENT_ALLOCATED_SIZE_OFFSET: usize = 0x28;
+pub(crate) const NON_RESIDENT_REAL_SIZE_OFFSET: usize = 0x30;
+pub(crate) const NON_RESIDENT_INITIALIZED_SIZE_OFFSET: usize = 0x38;
+pub(crate) const NON_RESIDENT_HEADER_SIZE: usize = 0x40;
+
+// Define the resident and non-resident forms of an attribute
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AttributeForm {
+    Resident {
+        value_offset: u16,
+        value_length: u32,
+        indexed: bool,
+        value: Vec<u8>,
+    },
+    NonResident {
+        starting_vcn: u64,
+        last_vcn: u64,
+        runlist_offset: u16,
+        // Compression unit size as a power of two number of clusters, zero if uncompressed
+        compression_unit: u16,
+        allocated_size: u64,
+        real_size: u64,
+        initialized_size: u64,
+        runlist: Vec<u8>,
+    },
+}
+
+// Define a struct to hold a decoded attribute
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Attribute {
+    pub type_code: u32,
+    pub length: u32,
+    pub name: String,
+    pub flags: u16,
+    pub attribute_id: u16,
+    pub form: AttributeForm,
+}
+
+impl Attribute {
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if data.len() < RESIDENT_HEADER_SIZE {
+            bail!("Attribute of {} bytes is too short to hold a header", data.len());
+        }
+
+        let type_code = LittleEndian::read_u32(&data[ATTRIBUTE_TYPE_OFFSET..]);
+        let length = LittleEndian::read_u32(&data[ATTRIBUTE_LENGTH_OFFSET..]);
+        if (length as usize) < RESIDENT_HEADER_SIZE || length as usize > data.len() {
+            bail!("Attribute {:#x} has an invalid length {}", type_code, length);
+        }
+        let data = &data[..length as usize];
+
+        let non_resident = data[ATTRIBUTE_NON_RESIDENT_OFFSET] != 0;
+        let flags = LittleEndian::read_u16(&data[ATTRIBUTE_FLAGS_OFFSET..]);
+        let attribute_id = LittleEndian::read_u16(&data[ATTRIBUTE_ID_OFFSET..]);
+
+        // Decode the UTF-16 name, if any
+        let name_length = usize::from(data[ATTRIBUTE_NAME_LENGTH_OFFSET]);
+        let name_offset = usize::from(LittleEndian::read_u16(&data[ATTRIBUTE_NAME_OFFSET_OFFSET..]));
+        let name = if name_length == 0 {
+            String::new()
+        } else {
+            if name_offset + name_length * 2 > data.len() {
+                bail!("Name of attribute {:#x} runs past the end of the attribute", type_code);
+            }
+            decode_utf16(&data[name_offset..name_offset + name_length * 2])
+        };
+
+        let form = if non_resident {
+            if data.len() < NON_RESIDENT_HEADER_SIZE {
+                bail!("Non-resident attribute {:#x} is too short ({} bytes)", type_code, length);
+            }
+
+            let runlist_offset = LittleEndian::read_u16(&data[NON_RESIDENT_RUNLIST_OFFSET_OFFSET..]);
+            if usize::from(runlist_offset) > data.len() {
+                bail!("Runlist of attribute {:#x} starts past the end of the attribute", type_code);
+            }
+
+            AttributeForm::NonResident {
+                starting_vcn: LittleEndian::read_u64(&data[NON_RESIDENT_STARTING_VCN_OFFSET..]),
+                last_vcn: LittleEndian::read_u64(&data[NON_RESIDENT_LAST_VCN_OFFSET..]),
+                runlist_offset,
+                compression_unit: LittleEndian::read_u16(&data[NON_RESIDENT_COMPRESSION_UNIT_OFFSET..]),
+                allocated_size: LittleEndian::read_u64(&data[NON_RESIDENT_ALLOCATED_SIZE_OFFSET..]),
+                real_size: LittleEndian::read_u64(&data[NON_RESIDENT_REAL_SIZE_OFFSET..]),
+                initialized_size: LittleEndian::read_u64(&data[NON_RESIDENT_INITIALIZED_SIZE_OFFSET..]),
+                runlist: data[usize::from(runlist_offset)..].to_vec(),
+            }
+        } else {
+            let value_length = LittleEndian::read_u32(&data[RESIDENT_VALUE_LENGTH_OFFSET..]);
+            let value_offset = LittleEndian::read_u16(&data[RESIDENT_VALUE_OFFSET_OFFSET..]);
+            let value_end = usize::from(value_offset) + value_length as usize;
+            if value_end > data.len() {
+                bail!("Value of attribute {:#x} runs past the end of the attribute", type_code);
+            }
+
+            AttributeForm::Resident {
+                value_offset,
+                value_length,
+                indexed: data[RESIDENT_INDEXED_FLAG_OFFSET] != 0,
+                value: data[usize::from(value_offset)..value_end].to_vec(),
+            }
+        };
+
+        Ok(Attribute {
+            type_code,
+            length,
+            name,
+            flags,
+            attribute_id,
+            form,
+        })
+    }
+
+    pub fn is_resident(&self) -> bool {
+        matches!(self.form, AttributeForm::Resident { .. })
+    }
+
+    pub fn is_compressed(&self) -> bool {
+        self.flags & ATTRIBUTE_FLAG_COMPRESSED != 0
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.flags & ATTRIBUTE_FLAG_ENCRYPTED != 0
+    }
+
+    pub fn is_sparse(&self) -> bool {
+        self.flags & ATTRIBUTE_FLAG_SPARSE != 0
+    }
+
+    // First VCN described by this attribute fragment, always zero for resident attributes
+    pub fn starting_vcn(&self) -> u64 {
+        match &self.form {
+            AttributeForm::NonResident { starting_vcn, .. } => *starting_vcn,
+            AttributeForm::Resident { .. } => 0,
+        }
+    }
+
+    // Return the value of a resident attribute, or None for a non-resident one
+    pub fn resident_value(&self) -> Option<&[u8]> {
+        match &self.form {
+            AttributeForm::Resident { value, .. } => Some(value),
+            AttributeForm::NonResident { .. } => None,
+        }
+    }
+
+    // Decode the runlist of a non-resident attribute, or None for a resident one
+    pub fn runlist(&self) -> Result<Option<Runlist>> {
+        match &self.form {
+            AttributeForm::NonResident { starting_vcn, runlist, .. } => Ok(Some(Runlist::decode(runlist, *starting_vcn)?)),
+            AttributeForm::Resident { .. } => Ok(None),
+        }
+    }
+}
+
+// Define an iterator over the attributes of a FILE record
+pub struct AttributeIter<'a> {
+    data: &'a [u8],
+    offset: usize,
+    finished: bool,
+}
+
+impl<'a> AttributeIter<'a> {
+    // `data` should hold the fixed-up record up to its used size
+    pub fn new(data: &'a [u8], first_attribute_offset: usize) -> Self {
+        AttributeIter {
+            data,
+            offset: first_attribute_offset,
+            finished: false,
+        }
+    }
+}
+
+impl<'a> Iterator for AttributeIter<'a> {
+    type Item = Result<Attribute>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+
+        // A record without an end marker is treated as ending at its used size
+        if self.offset + 4 > self.data.len() {
+            self.finished = true;
+            return None;
+        }
+
+        if LittleEndian::read_u32(&self.data[self.offset..]) == ATTRIBUTE_TYPE_END {
+            self.finished = true;
+            return None;
+        }
+
+        match Attribute::parse(&self.data[self.offset..]) {
+            Ok(attribute) => {
+                self.offset += attribute.length as usize;
+                Some(Ok(attribute))
+            }
+            Err(e) => {
+                // Stop at the first malformed attribute, as its length can't be trusted
+                self.finished = true;
+                Some(Err(e.context(format!("Failed to parse attribute at offset {}", self.offset))))
+            }
+        }
+    }
+}
+
+pub fn decode_utf16(bytes: &[u8]) -> String {
+    let units: Vec<u16> = bytes.chunks_exact(2).map(LittleEndian::read_u16).collect();
+    String::from_utf16_lossy(&units)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{fake_non_resident_attribute, fake_resident_attribute};
+
+    #[test]
+    fn test_attribute_iteration() {
+        let mut data = fake_resident_attribute(ATTRIBUTE_TYPE_DATA, "Zone.Identifier", b"[ZoneTransfer]");
+        data.extend(fake_non_resident_attribute(ATTRIBUTE_TYPE_DATA, "", &[0x11, 0x04, 0x20], 16384));
+        data.extend(&ATTRIBUTE_TYPE_END.to_le_bytes());
+
+        let attributes: Vec<Attribute> = AttributeIter::new(&data, 0).collect::<Result<_>>().unwrap();
+        assert_eq!(attributes.len(), 2);
+
+        assert_eq!(attributes[0].type_code, ATTRIBUTE_TYPE_DATA);
+        assert_eq!(attributes[0].name, "Zone.Identifier");
+        assert_eq!(attributes[0].resident_value(), Some(&b"[ZoneTransfer]"[..]));
+
+        assert!(!attributes[1].is_resident());
+        match &attributes[1].form {
+            AttributeForm::NonResident { real_size, runlist, .. } => {
+                assert_eq!(*real_size, 16384);
+                assert_eq!(&runlist[..3], &[0x11, 0x04, 0x20]);
+            }
+            AttributeForm::Resident { .. } => panic!("expected a non-resident attribute"),
+        }
+        let runlist = attributes[1].runlist().unwrap().unwrap();
+        assert_eq!(runlist.allocated_clusters(), 4);
+        assert_eq!(runlist.fragment_count(), 1);
+        assert!(attributes[0].runlist().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_attribute_running_past_record_rejected() {
+        let mut data = fake_resident_attribute(ATTRIBUTE_TYPE_STANDARD_INFORMATION, "", &[0; 0x48]);
+        LittleEndian::write_u32(&mut data[ATTRIBUTE_LENGTH_OFFSET..], 0x1000);
+
+        let mut attributes = AttributeIter::new(&data, 0);
+        assert!(attributes.next().unwrap().is_err());
+        assert!(attributes.next().is_none());
+    }
+}
@@ -0,0 +1,110 @@
+use crate::data_structurer::{DbEntry, StructuredData};
+use crate::standard_information::FILE_ATTRIBUTE_READONLY;
+use anyhow::{Context, Result};
+use chrono::DateTime;
+use std::io::Write;
+
+// Suffixes The Sleuth Kit appends to names, so mactime output reads the same as for fls
+const FILE_NAME_SUFFIX: &str = " ($FILE_NAME)";
+const DELETED_SUFFIX: &str = " (deleted)";
+
+// Write every entry as lines of the Sleuth Kit bodyfile 3.x format read by mactime:
+// MD5|name|inode|mode_as_string|UID|GID|size|atime|mtime|ctime|crtime
+pub fn write_bodyfile<W: Write>(data: &StructuredData, mut writer: W) -> Result<()> {
+    for entry in &data.entries {
+        for line in bodyfile_lines(entry) {
+            writeln!(writer, "{}", line).with_context(|| "Failed to write bodyfile")?;
+        }
+    }
+    writer.flush().with_context(|| "Failed to write bodyfile")
+}
+
+// One line for the $STANDARD_INFORMATION times of a file, then one for the $FILE_NAME times
+// of each of its hard links
+pub fn bodyfile_lines(entry: &DbEntry) -> Vec<String> {
+    let deleted = if entry.in_use { "" } else { DELETED_SUFFIX };
+    let mut lines = vec![bodyfile_line(
+        entry,
+        &format!("{}{}", entry.full_path, deleted),
+        [&entry.access_time, &entry.modification_time, &entry.mft_modified_time, &entry.creation_time],
+    )];
+
+    for name in &entry.names {
+        lines.push(bodyfile_line(
+            entry,
+            &format!("{}{}{}", name.full_path, FILE_NAME_SUFFIX, deleted),
+            [&name.access_time, &name.modification_time, &name.mft_modified_time, &name.creation_time],
+        ));
+    }
+
+    lines
+}
+
+// `times` are the access, modification, MFT modification and creation times, in bodyfile order
+fn bodyfile_line(entry: &DbEntry, name: &str, times: [&String; 4]) -> String {
+    let [accessed, modified, changed, created] = times.map(|timestamp| unix_time(timestamp));
+    // The inode is given as record-sequence, so reused records stay apart on the timeline
+    format!(
+        "0|{}|{}-{}|{}|0|0|{}|{}|{}|{}|{}",
+        name, entry.record_number, entry.sequence_number, mode(entry), entry.file_size, accessed, modified, changed, created,
+    )
+}
+
+// NTFS has no permission bits, so like The Sleuth Kit only the read-only attribute takes away
+// the write bits
+fn mode(entry: &DbEntry) -> String {
+    let file_type = if entry.is_directory { 'd' } else { 'r' };
+    let permissions = if entry.file_attributes & FILE_ATTRIBUTE_READONLY != 0 { "r-xr-xr-x" } else { "rwxrwxrwx" };
+    format!("{}/{}{}", file_type, file_type, permissions)
+}
+
+// Times before 1970, including ones never set, are written as 0 as The Sleuth Kit does
+fn unix_time(timestamp: &str) -> i64 {
+    DateTime::parse_from_rfc3339(timestamp)
+        .map(|timestamp| timestamp.timestamp().max(0))
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::attribute::{ATTRIBUTE_TYPE_FILE_NAME, ATTRIBUTE_TYPE_STANDARD_INFORMATION};
+    use crate::mft_parser::{FileReference, MftEntry, FILE_RECORD_IN_USE, FILE_RECORD_IS_DIRECTORY};
+    use crate::test_support::{fake_file_name_value, fake_file_record, fake_resident_attribute, fake_standard_information_value};
+
+    #[test]
+    fn test_bodyfile_lines() {
+        let root = FileReference { record_number: 5, sequence_number: 5 };
+        let mut name = fake_file_name_value(root, "report.docx", 1);
+        // 2021-06-01T00:00:00Z as the $FILE_NAME creation time, and a real size of 1024
+        name[0x08..0x10].copy_from_slice(&132_669_792_000_000_000u64.to_le_bytes());
+        name[0x30..0x38].copy_from_slice(&1024u64.to_le_bytes());
+        let attributes = [
+            // 2022-01-01T00:00:00Z as a FILETIME
+            fake_resident_attribute(ATTRIBUTE_TYPE_STANDARD_INFORMATION, "", &fake_standard_information_value(132_854_688_000_000_000, FILE_ATTRIBUTE_READONLY)),
+            fake_resident_attribute(ATTRIBUTE_TYPE_FILE_NAME, "", &name),
+        ].concat();
+        let mft_entries = vec![(64, MftEntry::parse(&fake_file_record(64, FILE_RECORD_IN_USE, &attributes)).unwrap())];
+        let structured_data = StructuredData::from_mft_entries(mft_entries, "C:").unwrap();
+
+        let mut output = Vec::new();
+        write_bodyfile(&structured_data, &mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), [
+            "0|C:\\report.docx|64-1|r/rr-xr-xr-x|0|0|1024|1640995200|1640995200|1640995200|1640995200\n",
+            "0|C:\\report.docx ($FILE_NAME)|64-1|r/rr-xr-xr-x|0|0|1024|0|0|0|1622505600\n",
+        ].concat());
+    }
+
+    #[test]
+    fn test_deleted_directory_lines() {
+        let root = FileReference { record_number: 5, sequence_number: 5 };
+        let name = fake_resident_attribute(ATTRIBUTE_TYPE_FILE_NAME, "", &fake_file_name_value(root, "old", 1));
+        let mft_entries = vec![(80, MftEntry::parse(&fake_file_record(80, FILE_RECORD_IS_DIRECTORY, &name)).unwrap())];
+        let structured_data = StructuredData::from_mft_entries(mft_entries, "C:").unwrap();
+
+        let lines = bodyfile_lines(&structured_data.entries[0]);
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("0|C:\\old (deleted)|80-1|d/drwxrwxrwx|"));
+        assert!(lines[1].starts_with("0|C:\\old ($FILE_NAME) (deleted)|80-1|"));
+    }
+}
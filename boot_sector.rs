@@ -0,0 +1,234 @@
+use crate::utils::read_bytes;
+use anyhow::{Context, Result};
+use byteorder::{ByteOrder, LittleEndian};
+use std::fs::File;
+use thiserror::Error;
+
+// Define constants for NTFS boot sector offsets and sizes
+pub const BOOT_SECTOR_SIZE: usize = 512;
+const OEM_ID_OFFSET: usize = 0x03;
+const OEM_ID_SIZE: usize = 8;
+const BYTES_PER_SECTOR_OFFSET: usize = 0x0B;
+const SECTORS_PER_CLUSTER_OFFSET: usize = 0x0D;
+const TOTAL_SECTORS_OFFSET: usize = 0x28;
+const MFT_LCN_OFFSET: usize = 0x30;
+const MFT_MIRR_LCN_OFFSET: usize = 0x38;
+const CLUSTERS_PER_MFT_RECORD_OFFSET: usize = 0x40;
+const CLUSTERS_PER_INDEX_RECORD_OFFSET: usize = 0x44;
+const VOLUME_SERIAL_NUMBER_OFFSET: usize = 0x48;
+const END_MARKER_OFFSET: usize = 0x1FE;
+
+const NTFS_OEM_ID: &[u8; OEM_ID_SIZE] = b"NTFS    ";
+const END_MARKER: u16 = 0xAA55;
+
+// Define the errors that can be raised while validating a boot sector
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum BootSectorError {
+    #[error("boot sector is {0} bytes long, expected at least {BOOT_SECTOR_SIZE}")]
+    TooShort(usize),
+    #[error("invalid OEM ID {0:?}, expected \"NTFS    \"")]
+    InvalidOemId(String),
+    #[error("invalid bytes per sector value {0}")]
+    InvalidBytesPerSector(u16),
+    #[error("invalid sectors per cluster value {0:#x}")]
+    InvalidSectorsPerCluster(u8),
+    #[error("volume reports zero total sectors")]
+    InvalidTotalSectors,
+    #[error("{name} LCN {lcn} lies outside the volume ({total_clusters} clusters)")]
+    InvalidLcn { name: &'static str, lcn: u64, total_clusters: u64 },
+    #[error("invalid clusters per MFT record value {0}")]
+    InvalidClustersPerMftRecord(i8),
+    #[error("invalid clusters per index record value {0}")]
+    InvalidClustersPerIndexRecord(i8),
+    #[error("invalid end of sector marker {0:#06x}, expected 0xaa55")]
+    InvalidEndMarker(u16),
+}
+
+// Define a struct to hold the parsed and validated NTFS boot sector
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NtfsBootSector {
+    pub oem_id: String,
+    pub bytes_per_sector: u16,
+    pub sectors_per_cluster: u32,
+    pub total_sectors: u64,
+    pub mft_lcn: u64,
+    pub mft_mirr_lcn: u64,
+    pub clusters_per_mft_record: i8,
+    pub clusters_per_index_record: i8,
+    pub volume_serial_number: u64,
+    pub end_marker: u16,
+}
+
+impl NtfsBootSector {
+    pub fn read(file: &mut File) -> Result<Self> {
+        let sector = read_bytes(file, 0, BOOT_SECTOR_SIZE)
+            .with_context(|| "Failed to read NTFS boot sector")?;
+        let boot_sector = NtfsBootSector::parse(&sector)
+            .with_context(|| "Failed to validate NTFS boot sector")?;
+        Ok(boot_sector)
+    }
+
+    pub fn parse(sector: &[u8]) -> Result<Self, BootSectorError> {
+        if sector.len() < BOOT_SECTOR_SIZE {
+            return Err(BootSectorError::TooShort(sector.len()));
+        }
+
+        let oem_id_bytes = &sector[OEM_ID_OFFSET..OEM_ID_OFFSET + OEM_ID_SIZE];
+        let oem_id = String::from_utf8_lossy(oem_id_bytes).to_string();
+        if oem_id_bytes != NTFS_OEM_ID {
+            return Err(BootSectorError::InvalidOemId(oem_id));
+        }
+
+        // Sector sizes are powers of two between 256 and 4096 bytes
+        let bytes_per_sector = LittleEndian::read_u16(&sector[BYTES_PER_SECTOR_OFFSET..]);
+        if !bytes_per_sector.is_power_of_two() || !(256..=4096).contains(&bytes_per_sector) {
+            return Err(BootSectorError::InvalidBytesPerSector(bytes_per_sector));
+        }
+
+        let raw_sectors_per_cluster = sector[SECTORS_PER_CLUSTER_OFFSET];
+        let sectors_per_cluster = decode_sectors_per_cluster(raw_sectors_per_cluster)
+            .ok_or(BootSectorError::InvalidSectorsPerCluster(raw_sectors_per_cluster))?;
+
+        let total_sectors = LittleEndian::read_u64(&sector[TOTAL_SECTORS_OFFSET..]);
+        if total_sectors == 0 {
+            return Err(BootSectorError::InvalidTotalSectors);
+        }
+        let total_clusters = total_sectors / u64::from(sectors_per_cluster);
+
+        let mft_lcn = LittleEndian::read_u64(&sector[MFT_LCN_OFFSET..]);
+        if mft_lcn == 0 || mft_lcn >= total_clusters {
+            return Err(BootSectorError::InvalidLcn { name: "$MFT", lcn: mft_lcn, total_clusters });
+        }
+
+        let mft_mirr_lcn = LittleEndian::read_u64(&sector[MFT_MIRR_LCN_OFFSET..]);
+        if mft_mirr_lcn == 0 || mft_mirr_lcn >= total_clusters {
+            return Err(BootSectorError::InvalidLcn { name: "$MFTMirr", lcn: mft_mirr_lcn, total_clusters });
+        }
+
+        let cluster_size = u64::from(bytes_per_sector) * u64::from(sectors_per_cluster);
+
+        let clusters_per_mft_record = sector[CLUSTERS_PER_MFT_RECORD_OFFSET] as i8;
+        if decode_record_size(clusters_per_mft_record, cluster_size).is_none() {
+            return Err(BootSectorError::InvalidClustersPerMftRecord(clusters_per_mft_record));
+        }
+
+        let clusters_per_index_record = sector[CLUSTERS_PER_INDEX_RECORD_OFFSET] as i8;
+        if decode_record_size(clusters_per_index_record, cluster_size).is_none() {
+            return Err(BootSectorError::InvalidClustersPerIndexRecord(clusters_per_index_record));
+        }
+
+        let volume_serial_number = LittleEndian::read_u64(&sector[VOLUME_SERIAL_NUMBER_OFFSET..]);
+
+        let end_marker = LittleEndian::read_u16(&sector[END_MARKER_OFFSET..]);
+        if end_marker != END_MARKER {
+            return Err(BootSectorError::InvalidEndMarker(end_marker));
+        }
+
+        Ok(NtfsBootSector {
+            oem_id,
+            bytes_per_sector,
+            sectors_per_cluster,
+            total_sectors,
+            mft_lcn,
+            mft_mirr_lcn,
+            clusters_per_mft_record,
+            clusters_per_index_record,
+            volume_serial_number,
+            end_marker,
+        })
+    }
+
+    pub fn cluster_size(&self) -> u64 {
+        u64::from(self.bytes_per_sector) * u64::from(self.sectors_per_cluster)
+    }
+
+    pub fn total_clusters(&self) -> u64 {
+        self.total_sectors / u64::from(self.sectors_per_cluster)
+    }
+
+    pub fn mft_record_size(&self) -> u64 {
+        // Validated in parse, so the encoding is known to be well formed
+        decode_record_size(self.clusters_per_mft_record, self.cluster_size()).unwrap_or(1024)
+    }
+
+    pub fn index_record_size(&self) -> u64 {
+        decode_record_size(self.clusters_per_index_record, self.cluster_size()).unwrap_or(4096)
+    }
+}
+
+// Values up to 0x80 are a plain power-of-two count; larger values encode 2^(256 - n)
+// sectors, which Windows uses for clusters bigger than 64 KiB.
+fn decode_sectors_per_cluster(raw: u8) -> Option<u32> {
+    match raw {
+        0 => None,
+        1..=0x80 if raw.is_power_of_two() => Some(u32::from(raw)),
+        1..=0x80 => None,
+        _ => {
+            let shift = 256 - u32::from(raw);
+            if shift > 24 {
+                None
+            } else {
+                Some(1 << shift)
+            }
+        }
+    }
+}
+
+// A positive value is a number of clusters, a negative value -n means 2^n bytes.
+fn decode_record_size(raw: i8, cluster_size: u64) -> Option<u64> {
+    if raw > 0 {
+        Some(raw as u64 * cluster_size)
+    } else if (-31..=-9).contains(&raw) {
+        Some(1u64 << -raw)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fake_boot_sector() -> Vec<u8> {
+        let mut sector = vec![0; BOOT_SECTOR_SIZE];
+        sector[OEM_ID_OFFSET..OEM_ID_OFFSET + OEM_ID_SIZE].copy_from_slice(NTFS_OEM_ID);
+        LittleEndian::write_u16(&mut sector[BYTES_PER_SECTOR_OFFSET..], 512);
+        sector[SECTORS_PER_CLUSTER_OFFSET] = 8;
+        LittleEndian::write_u64(&mut sector[TOTAL_SECTORS_OFFSET..], 1_000_000);
+        LittleEndian::write_u64(&mut sector[MFT_LCN_OFFSET..], 786_432 / 8);
+        LittleEndian::write_u64(&mut sector[MFT_MIRR_LCN_OFFSET..], 2);
+        sector[CLUSTERS_PER_MFT_RECORD_OFFSET] = (-10i8) as u8;
+        sector[CLUSTERS_PER_INDEX_RECORD_OFFSET] = 1;
+        LittleEndian::write_u64(&mut sector[VOLUME_SERIAL_NUMBER_OFFSET..], 0x1234_5678_9ABC_DEF0);
+        LittleEndian::write_u16(&mut sector[END_MARKER_OFFSET..], END_MARKER);
+        sector
+    }
+
+    #[test]
+    fn test_boot_sector_parsing() {
+        let boot_sector = NtfsBootSector::parse(&fake_boot_sector()).unwrap();
+
+        assert_eq!(boot_sector.oem_id, "NTFS    ");
+        assert_eq!(boot_sector.cluster_size(), 4096);
+        assert_eq!(boot_sector.mft_lcn, 98_304);
+        assert_eq!(boot_sector.mft_mirr_lcn, 2);
+        assert_eq!(boot_sector.mft_record_size(), 1024);
+        assert_eq!(boot_sector.index_record_size(), 4096);
+        assert_eq!(boot_sector.volume_serial_number, 0x1234_5678_9ABC_DEF0);
+    }
+
+    #[test]
+    fn test_invalid_boot_sector_rejected() {
+        let mut sector = fake_boot_sector();
+        sector[OEM_ID_OFFSET..OEM_ID_OFFSET + OEM_ID_SIZE].copy_from_slice(b"MSDOS5.0");
+        assert!(matches!(NtfsBootSector::parse(&sector), Err(BootSectorError::InvalidOemId(_))));
+
+        let mut sector = fake_boot_sector();
+        sector[END_MARKER_OFFSET] = 0;
+        assert_eq!(NtfsBootSector::parse(&sector), Err(BootSectorError::InvalidEndMarker(0xAA00)));
+
+        let mut sector = fake_boot_sector();
+        sector[CLUSTERS_PER_MFT_RECORD_OFFSET] = 0;
+        assert_eq!(NtfsBootSector::parse(&sector), Err(BootSectorError::InvalidClustersPerMftRecord(0)));
+    }
+}
@@ -0,0 +1,185 @@
+use crate::mft_parser::{MftEntry, RecordSignature, MAX_FILE_RECORD_SIZE};
+use crate::mft_reader::MftReader;
+use anyhow::{Context, Result};
+use std::collections::HashSet;
+use std::io::Read;
+
+// FILE records start on a sector boundary wherever they are written
+const SECTOR_SIZE: usize = 512;
+const FILE_SIGNATURE: &[u8] = b"FILE";
+
+// Amount read from the source at a time
+const READ_SIZE: usize = 1 << 20;
+
+// Define a struct to hold a FILE record found by scanning raw data
+#[derive(Debug, Clone)]
+pub struct CarvedRecord {
+    // Offset of the record from the start of the scanned source
+    pub physical_offset: u64,
+    pub entry: MftEntry,
+}
+
+// Define a key telling copies of the same version of a record apart from other versions. The
+// log sequence number changes with every update written to the record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RecordKey {
+    // Carved records have no slot, so live records are keyed by their header as well
+    pub record_number: Option<u64>,
+    pub sequence_number: u16,
+    pub logfile_sequence_number: u64,
+}
+
+impl RecordKey {
+    pub fn of(entry: &MftEntry) -> Self {
+        RecordKey {
+            record_number: entry.record_number,
+            sequence_number: entry.sequence_number,
+            logfile_sequence_number: entry.logfile_sequence_number,
+        }
+    }
+}
+
+// Collect the keys of every readable record of the live MFT, so carved copies of them can
+// be told apart from records only found by carving
+pub fn live_record_keys(reader: &mut MftReader) -> HashSet<RecordKey> {
+    (0..reader.record_count())
+        .filter_map(|record_number| reader.read_mft_entry(record_number).ok())
+        .filter_map(|entry_data| MftEntry::parse(&entry_data).ok())
+        .filter(|entry| entry.signature == RecordSignature::File)
+        .map(|entry| RecordKey::of(&entry))
+        .collect()
+}
+
+// Define an iterator over the FILE records of any byte source, such as a disk image or
+// unallocated space. Each sector is checked for the FILE signature, and candidates are kept
+// when their header and fixups pass MftEntry::parse.
+pub struct RecordCarver<R: Read> {
+    source: R,
+    buffer: Vec<u8>,
+    // Offset within the source of the start of the buffer, and position of the next candidate
+    buffer_offset: u64,
+    position: usize,
+    end_of_source: bool,
+}
+
+impl<R: Read> RecordCarver<R> {
+    pub fn new(source: R) -> Self {
+        RecordCarver {
+            source,
+            buffer: Vec::new(),
+            buffer_offset: 0,
+            position: 0,
+            end_of_source: false,
+        }
+    }
+
+    // Keep the largest possible record buffered past the current position, unless the
+    // source ends first
+    fn fill_buffer(&mut self) -> Result<()> {
+        if self.end_of_source || self.position + MAX_FILE_RECORD_SIZE as usize <= self.buffer.len() {
+            return Ok(());
+        }
+
+        self.buffer.drain(..self.position);
+        self.buffer_offset += self.position as u64;
+        self.position = 0;
+
+        while self.buffer.len() < READ_SIZE && !self.end_of_source {
+            let start = self.buffer.len();
+            self.buffer.resize(start + READ_SIZE, 0);
+            let read = self.source.read(&mut self.buffer[start..])
+                .with_context(|| format!("Failed to read the carving source at offset {}", self.buffer_offset + start as u64));
+            let read = match read {
+                Ok(read) => read,
+                Err(e) => {
+                    self.buffer.truncate(start);
+                    return Err(e);
+                }
+            };
+            self.buffer.truncate(start + read);
+            self.end_of_source = read == 0;
+        }
+
+        Ok(())
+    }
+
+    // Parse the record at the current position, if there is a valid one
+    fn parse_candidate(&self) -> Option<MftEntry> {
+        let candidate = &self.buffer[self.position..];
+        if !candidate.starts_with(FILE_SIGNATURE) {
+            return None;
+        }
+
+        let record_size = MftEntry::read_allocated_size(candidate)? as usize;
+        let entry = MftEntry::parse(candidate.get(..record_size)?).ok()?;
+        Some(entry)
+    }
+}
+
+impl<R: Read> Iterator for RecordCarver<R> {
+    type Item = Result<CarvedRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Err(e) = self.fill_buffer() {
+                // Stop at the first read error rather than skipping over unread data
+                self.end_of_source = true;
+                self.position = self.buffer.len();
+                return Some(Err(e));
+            }
+            if self.position >= self.buffer.len() {
+                return None;
+            }
+
+            let physical_offset = self.buffer_offset + self.position as u64;
+            match self.parse_candidate() {
+                Some(entry) => {
+                    // Records never overlap, so scanning resumes after this one
+                    self.position += entry.record_data.len();
+                    return Some(Ok(CarvedRecord { physical_offset, entry }));
+                }
+                None => self.position += SECTOR_SIZE,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mft_parser::FILE_RECORD_IN_USE;
+    use crate::test_support::fake_file_record;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_record_carving() {
+        let mut image = vec![0xEE; 3 * SECTOR_SIZE];
+        image.extend(fake_file_record(64, FILE_RECORD_IN_USE, &[]));
+        // A torn record, whose second sector was overwritten
+        let mut torn = fake_file_record(65, FILE_RECORD_IN_USE, &[]);
+        torn[1022] = 0xFF;
+        image.extend(torn);
+        // A record not aligned to a sector is not looked for
+        image.extend(vec![0; 100]);
+        image.extend(fake_file_record(66, FILE_RECORD_IN_USE, &[]));
+        image.extend(vec![0; SECTOR_SIZE - 100]);
+        image.extend(fake_file_record(67, 0, &[]));
+
+        let records: Vec<CarvedRecord> = RecordCarver::new(Cursor::new(image)).collect::<Result<_>>().unwrap();
+        let found: Vec<(u64, Option<u64>)> = records.iter()
+            .map(|record| (record.physical_offset, record.entry.record_number))
+            .collect();
+        assert_eq!(found, vec![(1536, Some(64)), (5120, Some(67))]);
+        assert!(!records[1].entry.is_in_use());
+    }
+
+    #[test]
+    fn test_record_keys() {
+        let first = MftEntry::parse(&fake_file_record(64, FILE_RECORD_IN_USE, &[])).unwrap();
+        let mut updated = first.clone();
+        updated.logfile_sequence_number += 1;
+
+        let keys: HashSet<RecordKey> = [&first, &first.clone(), &updated].into_iter().map(RecordKey::of).collect();
+        assert_eq!(keys.len(), 2);
+    }
+}
@@ -1,10 +1,99 @@
+use crate::timeline::{parse_time, TimelineFilter, TimelineOutput};
+use anyhow::{bail, Context};
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 
+// Define what the program should do, as selected on the command line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mode {
+    // Read the whole MFT from the volume and store it in the database
+    Index,
+    // Write the resident content of the given records (or of all records if none are given)
+    // from a $MFT dump to an output directory
+    ExtractResident {
+        mft_dump_path: PathBuf,
+        output_directory: PathBuf,
+        records: Vec<u64>,
+    },
+    // Scan a disk image or any other raw data for FILE records and store the ones the live
+    // MFT does not hold
+    Carve {
+        source_path: PathBuf,
+    },
+    // Read the whole MFT from the volume and write its timeline as a Sleuth Kit bodyfile
+    Bodyfile {
+        output_path: PathBuf,
+    },
+    // Read the whole MFT from the volume and export the events matching the filter in time order
+    Timeline {
+        output: TimelineOutput,
+        filter: TimelineFilter,
+    },
+}
+
+impl Mode {
+    // Parse the command line arguments, excluding the program name
+    pub fn from_args<I: IntoIterator<Item = String>>(args: I) -> anyhow::Result<Self> {
+        let mut args = args.into_iter();
+        match args.next().as_deref() {
+            None => Ok(Mode::Index),
+            Some("extract-resident") => {
+                let (mft_dump_path, output_directory) = match (args.next(), args.next()) {
+                    (Some(mft_dump_path), Some(output_directory)) => (mft_dump_path, output_directory),
+                    _ => bail!("Usage: extract-resident <mft dump> <output directory> [record...]"),
+                };
+                let records = args
+                    .map(|record| record.parse().with_context(|| format!("Invalid record number {:?}", record)))
+                    .collect::<anyhow::Result<_>>()?;
+
+                Ok(Mode::ExtractResident {
+                    mft_dump_path: PathBuf::from(mft_dump_path),
+                    output_directory: PathBuf::from(output_directory),
+                    records,
+                })
+            }
+            Some("carve") => match args.next() {
+                Some(source_path) => Ok(Mode::Carve { source_path: PathBuf::from(source_path) }),
+                None => bail!("Usage: carve <image>"),
+            },
+            Some("bodyfile") => match args.next() {
+                Some(output_path) => Ok(Mode::Bodyfile { output_path: PathBuf::from(output_path) }),
+                None => bail!("Usage: bodyfile <output>"),
+            },
+            Some("timeline") => {
+                let usage = "Usage: timeline <csv <output>|jsonl <output>|sqlite> [--from <time>] [--to <time>] [--path <prefix>]";
+                let args: Vec<String> = args.collect();
+                // The database to write to comes from the configuration, so sqlite takes no output path
+                let (output, options) = match args.as_slice() {
+                    [format, options @ ..] if format == "sqlite" => (TimelineOutput::Sqlite, options),
+                    [format, output_path, options @ ..] if format == "csv" => (TimelineOutput::Csv(PathBuf::from(output_path)), options),
+                    [format, output_path, options @ ..] if format == "jsonl" => (TimelineOutput::JsonLines(PathBuf::from(output_path)), options),
+                    _ => bail!(usage),
+                };
+
+                let mut filter = TimelineFilter::default();
+                for option in options.chunks(2) {
+                    match option {
+                        [name, value] if name == "--from" => filter.from = Some(parse_time(value)?),
+                        [name, value] if name == "--to" => filter.to = Some(parse_time(value)?),
+                        [name, value] if name == "--path" => filter.path_prefix = Some(value.clone()),
+                        _ => bail!(usage),
+                    }
+                }
+
+                Ok(Mode::Timeline { output, filter })
+            }
+            Some(mode) => bail!("Unknown mode {:?}", mode),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Config {
     pub database_url: String,
     pub mft_file_path: PathBuf,
+    // Prefix for reconstructed paths, such as the drive letter the volume was mounted as
+    pub volume_root: String,
 }
 
 impl Config {
@@ -14,6 +103,7 @@ impl Config {
         // Example hardcoded configuration:
         let database_url = "sqlite:mft_data.db".to_string(); // This should be replaced with actual configuration retrieval logic
         let mft_file_path = PathBuf::from("C:\\path\\to\\MFT"); // This should be replaced with actual configuration retrieval logic
+        let volume_root = "C:".to_string(); // This should be replaced with actual configuration retrieval logic
 
         // Validate configuration settings
         if database_url.is_empty() || mft_file_path.as_os_str().is_empty() {
@@ -23,6 +113,40 @@ impl Config {
         Ok(Config {
             database_url,
             mft_file_path,
+            volume_root,
         })
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mode_parsing() {
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(Mode::from_args(args(&[])).unwrap(), Mode::Index);
+        assert_eq!(Mode::from_args(args(&["extract-resident", "MFT", "out", "64", "65"])).unwrap(), Mode::ExtractResident {
+            mft_dump_path: PathBuf::from("MFT"),
+            output_directory: PathBuf::from("out"),
+            records: vec![64, 65],
+        });
+        assert!(Mode::from_args(args(&["extract-resident", "MFT"])).is_err());
+        assert!(Mode::from_args(args(&["extract-resident", "MFT", "out", "x"])).is_err());
+        assert_eq!(Mode::from_args(args(&["carve", "disk.img"])).unwrap(), Mode::Carve { source_path: PathBuf::from("disk.img") });
+        assert!(Mode::from_args(args(&["carve"])).is_err());
+        assert_eq!(Mode::from_args(args(&["bodyfile", "body.txt"])).unwrap(), Mode::Bodyfile { output_path: PathBuf::from("body.txt") });
+        assert_eq!(Mode::from_args(args(&["timeline", "sqlite", "--path", "C:\\Users", "--to", "2024-01-01"])).unwrap(), Mode::Timeline {
+            output: TimelineOutput::Sqlite,
+            filter: TimelineFilter {
+                from: None,
+                to: Some(parse_time("2024-01-01T00:00:00Z").unwrap()),
+                path_prefix: Some("C:\\Users".to_string()),
+            },
+        });
+        assert!(Mode::from_args(args(&["timeline", "csv"])).is_err());
+        assert!(Mode::from_args(args(&["timeline", "xml", "out.xml"])).is_err());
+        assert!(Mode::from_args(args(&["timeline", "jsonl", "out.jsonl", "--from"])).is_err());
+    }
+}
@@ -0,0 +1,420 @@
+use crate::attribute::{AttributeForm, ATTRIBUTE_TYPE_DATA};
+use crate::logical_file::LogicalFile;
+use crate::lznt1;
+use crate::runlist::{CompressionUnit, Runlist};
+use crate::wof::{self, WofAlgorithm, WOF_COMPRESSED_DATA_STREAM};
+use anyhow::{bail, Context, Result};
+use std::io::{self, Read, Seek, SeekFrom};
+
+// Define where the content of a stream comes from
+#[derive(Debug)]
+enum StreamData {
+    // Held in the MFT record itself
+    Resident(Vec<u8>),
+    // Held in clusters of the volume. Bytes past the initialized size were never written
+    // and read as zeros, even if the clusters behind them hold stale data.
+    NonResident {
+        runlist: Runlist,
+        cluster_size: u64,
+        initialized_size: u64,
+    },
+    // Held in clusters as LZNT1 compression units, decompressed one unit at a time
+    Compressed {
+        runlist: Runlist,
+        cluster_size: u64,
+        unit_clusters: u64,
+        initialized_size: u64,
+        // The most recently decompressed unit and its index
+        cached_unit: Option<(u64, Vec<u8>)>,
+    },
+    // Held in the WofCompressedData stream as independently compressed chunks, found through
+    // the chunk offsets at the start of that stream
+    Wof {
+        compressed: Box<StreamData>,
+        algorithm: WofAlgorithm,
+        size: u64,
+        chunk_offsets: Vec<u64>,
+        // The most recently decompressed chunk and its index
+        cached_chunk: Option<(u64, Vec<u8>)>,
+    },
+}
+
+// Define a reader over the content of one $DATA stream, reading clusters from the volume image
+// on demand so large files can be streamed or hashed without loading them into memory
+#[derive(Debug)]
+pub struct ContentReader<R> {
+    source: R,
+    data: StreamData,
+    size: u64,
+    position: u64,
+}
+
+impl<R: Read + Seek> ContentReader<R> {
+    // Open a stream of a logical file, using an empty name for the default stream. The default
+    // stream of a WOF compressed file is read from its WofCompressedData stream.
+    pub fn new(mut source: R, file: &LogicalFile, stream_name: &str, cluster_size: u64) -> Result<Self> {
+        let (mut data, size) = open_stream_data(file, stream_name, cluster_size)?;
+
+        let wof_compression = file.wof_compression()
+            .with_context(|| format!("Failed to read the reparse point of MFT entry {}", file.record_number()))?;
+        if let (true, Some(compression)) = (stream_name.is_empty(), wof_compression) {
+            let algorithm = compression.algorithm()
+                .with_context(|| format!("Failed to read the WOF compressed data of MFT entry {}", file.record_number()))?;
+            let (mut compressed, compressed_size) = open_stream_data(file, WOF_COMPRESSED_DATA_STREAM, cluster_size)?;
+            // The table size follows from the size on record, which a damaged record can inflate
+            let table_size = wof::chunk_table_size(size, algorithm);
+            if table_size > compressed_size {
+                bail!("WofCompressedData stream of MFT entry {} is too short for its chunk table of {} bytes", file.record_number(), table_size);
+            }
+            let mut table = vec![0; table_size as usize];
+            read_fully(&mut source, &mut compressed, 0, &mut table)
+                .with_context(|| format!("Failed to read the WOF chunk table of MFT entry {}", file.record_number()))?;
+            let chunk_offsets = wof::read_chunk_table(&table, size, compressed_size, algorithm)?;
+
+            data = StreamData::Wof {
+                compressed: Box::new(compressed),
+                algorithm,
+                size,
+                chunk_offsets,
+                cached_chunk: None,
+            };
+        }
+
+        Ok(ContentReader {
+            source,
+            data,
+            size,
+            position: 0,
+        })
+    }
+
+    // Logical size of the stream in bytes
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+impl<R: Read + Seek> Read for ContentReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position >= self.size || buf.is_empty() {
+            return Ok(0);
+        }
+        let wanted = (buf.len() as u64).min(self.size - self.position) as usize;
+
+        let read = read_at(&mut self.source, &mut self.data, self.position, &mut buf[..wanted])?;
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+// Find the $DATA attribute holding a stream and describe where its content lives, returning it
+// along with the size of the stream
+fn open_stream_data(file: &LogicalFile, stream_name: &str, cluster_size: u64) -> Result<(StreamData, u64)> {
+    // Only the first fragment of a non-resident attribute carries its sizes
+    let attribute = match file.attribute_fragments(ATTRIBUTE_TYPE_DATA, stream_name).find(|fragment| fragment.starting_vcn() == 0) {
+        Some(attribute) => attribute,
+        None => bail!("MFT entry {} has no $DATA stream named {:?}", file.record_number(), stream_name),
+    };
+
+    match &attribute.form {
+        AttributeForm::Resident { value, .. } => Ok((StreamData::Resident(value.clone()), value.len() as u64)),
+        AttributeForm::NonResident { real_size, initialized_size, compression_unit, .. } => {
+            let runlist = file.runlist(ATTRIBUTE_TYPE_DATA, stream_name)
+                .with_context(|| format!("Failed to read the runlist of MFT entry {}", file.record_number()))?
+                .unwrap_or_default();
+            let initialized_size = (*initialized_size).min(*real_size);
+
+            let data = if attribute.is_compressed() {
+                // The unit size is stored as a power of two number of clusters
+                if *compression_unit == 0 || *compression_unit > 16 {
+                    bail!("$DATA stream of MFT entry {} has an invalid compression unit {}", file.record_number(), compression_unit);
+                }
+                StreamData::Compressed {
+                    runlist,
+                    cluster_size,
+                    unit_clusters: 1 << compression_unit,
+                    initialized_size,
+                    cached_unit: None,
+                }
+            } else {
+                StreamData::NonResident { runlist, cluster_size, initialized_size }
+            };
+            Ok((data, *real_size))
+        }
+    }
+}
+
+// Read stream content at `position`, returning how many bytes were read. Callers keep reads
+// within the size of the stream.
+fn read_at<R: Read + Seek>(source: &mut R, data: &mut StreamData, position: u64, buf: &mut [u8]) -> io::Result<usize> {
+    let wanted = buf.len() as u64;
+
+    let read = match data {
+        StreamData::Resident(value) => {
+            let start = position as usize;
+            buf.copy_from_slice(&value[start..start + buf.len()]);
+            buf.len()
+        }
+        StreamData::NonResident { initialized_size, .. } | StreamData::Compressed { initialized_size, .. }
+            if position >= *initialized_size =>
+        {
+            buf.fill(0);
+            buf.len()
+        }
+        StreamData::NonResident { runlist, cluster_size, initialized_size } => {
+            let wanted = wanted.min(*initialized_size - position) as usize;
+            read_clusters(source, runlist, *cluster_size, position, &mut buf[..wanted])?
+        }
+        StreamData::Compressed { runlist, cluster_size, unit_clusters, initialized_size, cached_unit } => {
+            let unit_size = *unit_clusters * *cluster_size;
+            let unit_index = position / unit_size;
+
+            if cached_unit.as_ref().map(|(index, _)| *index) != Some(unit_index) {
+                let unit = read_compression_unit(source, runlist, *cluster_size, *unit_clusters, unit_index)?;
+                *cached_unit = Some((unit_index, unit));
+            }
+            let unit = &cached_unit.as_ref().unwrap().1;
+
+            let unit_offset = (position - unit_index * unit_size) as usize;
+            let read = wanted.min(*initialized_size - position).min(unit_size - unit_offset as u64) as usize;
+            buf[..read].copy_from_slice(&unit[unit_offset..unit_offset + read]);
+            read
+        }
+        StreamData::Wof { compressed, algorithm, size, chunk_offsets, cached_chunk } => {
+            let chunk_size = algorithm.chunk_size();
+            let chunk_index = position / chunk_size;
+
+            if cached_chunk.as_ref().map(|(index, _)| *index) != Some(chunk_index) {
+                let (start, end) = match (chunk_offsets.get(chunk_index as usize), chunk_offsets.get(chunk_index as usize + 1)) {
+                    (Some(&start), Some(&end)) => (start, end),
+                    _ => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("WOF chunk {} is missing from the chunk table", chunk_index))),
+                };
+                let mut stored = vec![0; (end - start) as usize];
+                read_fully(source, compressed, start, &mut stored)?;
+
+                let chunk_length = chunk_size.min(*size - chunk_index * chunk_size) as usize;
+                let chunk = algorithm.decompress_chunk(&stored, chunk_length)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{} chunk {}: {:#}", algorithm, chunk_index, e)))?;
+                *cached_chunk = Some((chunk_index, chunk));
+            }
+            let chunk = &cached_chunk.as_ref().unwrap().1;
+
+            let chunk_offset = (position - chunk_index * chunk_size) as usize;
+            let read = wanted.min((chunk.len() - chunk_offset) as u64) as usize;
+            buf[..read].copy_from_slice(&chunk[chunk_offset..chunk_offset + read]);
+            read
+        }
+    };
+
+    Ok(read)
+}
+
+// Fill `buf` with stream content starting at `position`
+fn read_fully<R: Read + Seek>(source: &mut R, data: &mut StreamData, position: u64, buf: &mut [u8]) -> io::Result<()> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match read_at(source, data, position + filled as u64, &mut buf[filled..])? {
+            0 => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Stream ended before the requested range")),
+            read => filled += read,
+        }
+    }
+    Ok(())
+}
+
+// Read bytes of a non-resident attribute from the first mapped piece at `byte_offset`,
+// returning how many were read. Sparse runs read as zeros, while clusters the runlist doesn't
+// map at all are an error rather than silently empty content.
+fn read_clusters<R: Read + Seek>(source: &mut R, runlist: &Runlist, cluster_size: u64, byte_offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+    if let Some((physical_offset, contiguous)) = runlist.map_offset(cluster_size, byte_offset) {
+        let read = (buf.len() as u64).min(contiguous) as usize;
+        source.seek(SeekFrom::Start(physical_offset))?;
+        source.read_exact(&mut buf[..read])?;
+        return Ok(read);
+    }
+
+    match runlist.run_at(byte_offset / cluster_size) {
+        Some(run) => {
+            let read = (buf.len() as u64).min(run.end_vcn() * cluster_size - byte_offset) as usize;
+            buf[..read].fill(0);
+            Ok(read)
+        }
+        None => Err(unmapped_error(byte_offset / cluster_size)),
+    }
+}
+
+fn unmapped_error(vcn: u64) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("VCN {} is not mapped by the runlist", vcn))
+}
+
+// Read and decompress one compression unit. A unit whose clusters are all allocated is stored
+// as is, one with no allocated cluster is a hole, and any other holds LZNT1 data in its
+// allocated clusters followed by sparse padding.
+fn read_compression_unit<R: Read + Seek>(source: &mut R, runlist: &Runlist, cluster_size: u64, unit_clusters: u64, unit_index: u64) -> io::Result<Vec<u8>> {
+    let unit_vcn = unit_index * unit_clusters;
+    let unit_size = (unit_clusters * cluster_size) as usize;
+    if runlist.run_at(unit_vcn).is_none() {
+        return Err(unmapped_error(unit_vcn));
+    }
+    let stored_clusters = match runlist.compression_unit(unit_vcn, unit_clusters) {
+        CompressionUnit::Uncompressed { .. } => unit_clusters,
+        CompressionUnit::Compressed { allocated_clusters, .. } => allocated_clusters,
+        CompressionUnit::Sparse { .. } => return Ok(vec![0; unit_size]),
+    };
+
+    let mut stored = vec![0; (stored_clusters * cluster_size) as usize];
+    let mut filled = 0;
+    while filled < stored.len() {
+        let byte_offset = unit_vcn * cluster_size + filled as u64;
+        filled += read_clusters(source, runlist, cluster_size, byte_offset, &mut stored[filled..])?;
+    }
+
+    if stored_clusters == unit_clusters {
+        return Ok(stored);
+    }
+
+    let mut unit = lznt1::decompress(&stored, unit_size)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("Compression unit at VCN {}: {:#}", unit_vcn, e)))?;
+    unit.resize(unit_size, 0);
+    Ok(unit)
+}
+
+impl<R: Read + Seek> Seek for ContentReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.size.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+
+        match position {
+            Some(position) => {
+                self.position = position;
+                Ok(position)
+            }
+            None => Err(io::Error::new(io::ErrorKind::InvalidInput, "Seek to a negative or overflowing position")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::attribute::{ATTRIBUTE_FLAG_COMPRESSED, ATTRIBUTE_TYPE_REPARSE_POINT};
+    use crate::mft_parser::{MftEntry, FILE_RECORD_IN_USE};
+    use crate::test_support::{fake_file_record, fake_logical_file, fake_non_resident_attribute, fake_resident_attribute, fake_wof_reparse_data};
+    use byteorder::{ByteOrder, LittleEndian};
+    use std::io::Cursor;
+
+    const CLUSTER_SIZE: u64 = 512;
+
+    // A four-cluster image with "B" in cluster 1 and "A" in cluster 2
+    fn fake_image() -> Cursor<Vec<u8>> {
+        let mut image = vec![0; 4 * CLUSTER_SIZE as usize];
+        image[512..1024].fill(b'B');
+        image[1024..1536].fill(b'A');
+        Cursor::new(image)
+    }
+
+    #[test]
+    fn test_sparse_and_uninitialized_reading() {
+        // Cluster 2, then a sparse cluster, then cluster 1 (relative offset -1)
+        let mut attribute = fake_non_resident_attribute(ATTRIBUTE_TYPE_DATA, "", &[0x11, 0x01, 0x02, 0x01, 0x01, 0x11, 0x01, 0xFF], 1436);
+        // Only the first 1200 bytes were ever written
+        LittleEndian::write_u64(&mut attribute[0x38..], 1200);
+        let entry = MftEntry::parse(&fake_file_record(40, FILE_RECORD_IN_USE, &attribute)).unwrap();
+        let file = LogicalFile::from_records(40, entry, Vec::new()).unwrap();
+
+        let mut reader = ContentReader::new(fake_image(), &file, "", CLUSTER_SIZE).unwrap();
+        let mut content = Vec::new();
+        reader.read_to_end(&mut content).unwrap();
+
+        let mut expected = vec![b'A'; 512];
+        expected.extend(vec![0; 512]);
+        expected.extend(vec![b'B'; 176]);
+        expected.extend(vec![0; 236]);
+        assert_eq!(content, expected);
+
+        let mut buffer = [0xFF; 8];
+        reader.seek(SeekFrom::Start(1020)).unwrap();
+        reader.read_exact(&mut buffer).unwrap();
+        assert_eq!(buffer, [0, 0, 0, 0, b'B', b'B', b'B', b'B']);
+        assert_eq!(reader.seek(SeekFrom::End(-10)).unwrap(), 1426);
+        assert!(reader.seek(SeekFrom::Current(-2000)).is_err());
+
+        // A runlist that stops short of the initialized size, as a truncated record would leave
+        let attribute = fake_non_resident_attribute(ATTRIBUTE_TYPE_DATA, "", &[0x11, 0x01, 0x02], 1024);
+        let file = fake_logical_file(41, FILE_RECORD_IN_USE, &attribute);
+        let mut reader = ContentReader::new(fake_image(), &file, "", CLUSTER_SIZE).unwrap();
+        let error = reader.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_compressed_stream_reading() {
+        // Unit 0 is one cluster of LZNT1 data at LCN 3 padded by 15 sparse clusters, unit 1 is a hole
+        let mut attribute = fake_non_resident_attribute(ATTRIBUTE_TYPE_DATA, "", &[0x11, 0x01, 0x03, 0x01, 0x1F], 8192 + 100);
+        LittleEndian::write_u16(&mut attribute[0x0C..], ATTRIBUTE_FLAG_COMPRESSED);
+        LittleEndian::write_u16(&mut attribute[0x22..], 4);
+        let entry = MftEntry::parse(&fake_file_record(40, FILE_RECORD_IN_USE, &attribute)).unwrap();
+        let file = LogicalFile::from_records(40, entry, Vec::new()).unwrap();
+
+        let mut image = fake_image().into_inner();
+        image[1536..1544].copy_from_slice(&[0x05, 0xB0, 0x08, b'a', b'b', b'c', 0x0C, 0x20]);
+        let mut reader = ContentReader::new(Cursor::new(image), &file, "", CLUSTER_SIZE).unwrap();
+        let mut content = Vec::new();
+        reader.read_to_end(&mut content).unwrap();
+
+        assert_eq!(content.len(), 8192 + 100);
+        assert_eq!(&content[..18], b"abcabcabcabcabcabc");
+        assert!(content[18..].iter().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    fn test_wof_compressed_stream_reading() {
+        // Two XPRESS4K chunks: 4096 bytes of 'a' as a literal plus a match with an extended length
+        // of 4092 + 3, then a 4 byte chunk stored as is
+        let mut chunk = vec![0; 256];
+        chunk[usize::from(b'a') / 2] = 0x10;
+        chunk[0x10F / 2] = 0x10;
+        chunk.extend([0x00, 0x40, 0x00, 0x00, 0xFF, 0xFC, 0x0F]);
+        let mut stream = (chunk.len() as u32).to_le_bytes().to_vec();
+        stream.extend(chunk);
+        stream.extend(b"tail");
+
+        let attributes = [
+            fake_non_resident_attribute(ATTRIBUTE_TYPE_DATA, "", &[0x01, 0x09], 4100),
+            fake_resident_attribute(ATTRIBUTE_TYPE_DATA, WOF_COMPRESSED_DATA_STREAM, &stream),
+            fake_resident_attribute(ATTRIBUTE_TYPE_REPARSE_POINT, "", &fake_wof_reparse_data(0)),
+        ].concat();
+        let entry = MftEntry::parse(&fake_file_record(40, FILE_RECORD_IN_USE, &attributes)).unwrap();
+        let file = LogicalFile::from_records(40, entry, Vec::new()).unwrap();
+
+        let mut reader = ContentReader::new(fake_image(), &file, "", CLUSTER_SIZE).unwrap();
+        let mut content = Vec::new();
+        reader.read_to_end(&mut content).unwrap();
+        assert_eq!(content.len(), 4100);
+        assert!(content[..4096].iter().all(|&byte| byte == b'a'));
+        assert_eq!(&content[4096..], b"tail");
+
+        // An algorithm this reader doesn't know fails once the content is opened
+        let attributes = [
+            fake_non_resident_attribute(ATTRIBUTE_TYPE_DATA, "", &[0x01, 0x09], 4100),
+            fake_resident_attribute(ATTRIBUTE_TYPE_REPARSE_POINT, "", &fake_wof_reparse_data(7)),
+        ].concat();
+        let file = fake_logical_file(40, FILE_RECORD_IN_USE, &attributes);
+        assert!(ContentReader::new(fake_image(), &file, "", CLUSTER_SIZE).is_err());
+    }
+
+    #[test]
+    fn test_resident_and_missing_streams() {
+        let attribute = fake_resident_attribute(ATTRIBUTE_TYPE_DATA, "Zone.Identifier", b"ZoneId=3");
+        let entry = MftEntry::parse(&fake_file_record(40, FILE_RECORD_IN_USE, &attribute)).unwrap();
+        let file = LogicalFile::from_records(40, entry, Vec::new()).unwrap();
+
+        let mut reader = ContentReader::new(fake_image(), &file, "Zone.Identifier", CLUSTER_SIZE).unwrap();
+        let mut content = String::new();
+        reader.read_to_string(&mut content).unwrap();
+        assert_eq!(content, "ZoneId=3");
+        assert!(ContentReader::new(fake_image(), &file, "", CLUSTER_SIZE).is_err());
+    }
+}
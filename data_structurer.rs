@@ -1,5 +1,14 @@
-use crate::mft_parser::MftEntry;
+use crate::file_name::{hard_links, preferred_name};
+use crate::index::IndexEntry;
+use crate::index_slack::SlackEntry;
+use crate::logical_file::LogicalFile;
+use crate::mft_parser::{FileReference, MftEntry};
+use crate::path_resolver::PathResolver;
+use crate::recovery::{RecoveryAssessment, VolumeBitmap};
+use crate::reparse_point::ReparsePoint;
+use crate::timestamp_anomaly::TimestampAnomaly;
 use anyhow::{Result, Context};
+use chrono::{DateTime, SecondsFormat, Utc};
 use serde::{Serialize, Deserialize};
 use std::collections::HashMap;
 
@@ -13,13 +22,116 @@ pub struct StructuredData {
 #[derive(Serialize, Deserialize, Debug)]
 pub struct DbEntry {
     pub record_number: u64,
+    pub sequence_number: u16,
+    // Where a record found by carving was read from, None for records of the live MFT
+    pub source_path: Option<String>,
+    pub physical_offset: Option<u64>,
     pub file_name: String,
+    pub full_path: String,
     pub file_size: u64,
     pub creation_time: String,
+    pub modification_time: String,
+    pub mft_modified_time: String,
+    pub access_time: String,
+    pub file_attributes: u32,
+    pub is_directory: bool,
+    // Whether the record is in use, and for deleted records how much of the file is left
+    pub in_use: bool,
+    pub recovery: Option<RecoveryAssessment>,
+    // Every hard link of the file, including the one behind file_name and full_path
+    pub names: Vec<DbName>,
+    // Every $DATA stream of the file, and whether any of them is an alternate data stream
+    pub streams: Vec<DbStream>,
+    pub has_ads: bool,
+    // Compression algorithm of files compressed by the Windows Overlay Filter (CompactOS)
+    pub wof_algorithm: Option<String>,
+    // Tag of the reparse point and, for junctions and symbolic links, where it points
+    pub reparse_tag: Option<String>,
+    pub reparse_target: Option<String>,
+    // Files the $I30 index of a directory lists, to cross-check against the parent pointers
+    pub index_entries: Vec<DbIndexEntry>,
+    // Entries of removed files carved from the slack of the directory's INDX blocks
+    pub slack_entries: Vec<DbSlackEntry>,
+    // Signs that the timestamps of the file were tampered with
+    pub timestamp_anomalies: Vec<TimestampAnomaly>,
     // Add more fields as necessary to represent the database entry
     // For example, file name, file size, creation time, etc.
 }
 
+// Define a struct that represents one hard link of a file
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DbName {
+    pub file_name: String,
+    pub full_path: String,
+    pub parent_record_number: u64,
+    pub parent_sequence_number: u16,
+    // Timestamps of the $FILE_NAME attribute, which only change on rename or move
+    pub creation_time: String,
+    pub modification_time: String,
+    pub mft_modified_time: String,
+    pub access_time: String,
+}
+
+// Define a struct that represents one $DATA stream of a file
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DbStream {
+    pub stream_name: String,
+    pub size: u64,
+    pub resident: bool,
+    pub allocated_clusters: u64,
+}
+
+// Define a struct that represents one entry of a directory's $I30 index
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DbIndexEntry {
+    pub file_name: String,
+    pub record_number: u64,
+    pub sequence_number: u16,
+}
+
+impl From<&IndexEntry> for DbIndexEntry {
+    fn from(entry: &IndexEntry) -> Self {
+        DbIndexEntry {
+            file_name: entry.file_name.name.clone(),
+            record_number: entry.file_reference.record_number,
+            sequence_number: entry.file_reference.sequence_number,
+        }
+    }
+}
+
+// Define a struct that represents an index entry recovered from INDX slack
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DbSlackEntry {
+    pub file_name: String,
+    // The file the entry pointed at, unknown when only the key survived
+    pub record_number: Option<u64>,
+    pub sequence_number: Option<u16>,
+    pub file_size: u64,
+    pub creation_time: String,
+    pub modification_time: String,
+    pub mft_modified_time: String,
+    pub access_time: String,
+    pub block_number: u64,
+    pub block_offset: u64,
+}
+
+impl From<&SlackEntry> for DbSlackEntry {
+    fn from(entry: &SlackEntry) -> Self {
+        DbSlackEntry {
+            file_name: entry.file_name.name.clone(),
+            record_number: entry.file_reference.map(|reference| reference.record_number),
+            sequence_number: entry.file_reference.map(|reference| reference.sequence_number),
+            file_size: entry.file_name.real_size,
+            creation_time: format_timestamp(&entry.file_name.created),
+            modification_time: format_timestamp(&entry.file_name.modified),
+            mft_modified_time: format_timestamp(&entry.file_name.mft_modified),
+            access_time: format_timestamp(&entry.file_name.accessed),
+            block_number: entry.block_number,
+            block_offset: entry.offset as u64,
+        }
+    }
+}
+
 impl StructuredData {
     pub fn new() -> Self {
         StructuredData {
@@ -27,27 +139,190 @@ impl StructuredData {
         }
     }
 
-    pub fn from_mft_entries(mft_entries: Vec<MftEntry>) -> Result<Self> {
+    // Structure MFT entries, each paired with the slot it was read from
+    pub fn from_mft_entries(mft_entries: Vec<(u64, MftEntry)>, volume_root: &str) -> Result<Self> {
         let mut structured_data = StructuredData::new();
 
-        for entry in mft_entries {
-            // Here you would extract the necessary information from the MftEntry
-            // and create a DbEntry with the structured data for the database.
-            // For simplicity, we are only using the record number in this example.
-
-            let db_entry = DbEntry {
-                record_number: entry.record_number,
-                file_name: entry.file_name,
-                file_size: entry.file_size,
-                creation_time: entry.creation_time,
-                // Populate other fields as necessary
-            };
-
-            structured_data.entries.push(db_entry);
+        // Extension records are folded into their base record rather than treated as files
+        let files = LogicalFile::group_entries(mft_entries)?;
+        for file in &files {
+            structured_data.entries.push(DbEntry::from_logical_file(file, None)?);
         }
 
+        let mut resolver = PathResolver::from_files(&files, volume_root)?;
+        structured_data.resolve_paths(&mut resolver);
+        structured_data.resolve_sequence_references();
+
         Ok(structured_data)
     }
+
+    // Fill in the full path of every entry once the whole MFT is known to the resolver
+    pub fn resolve_paths(&mut self, resolver: &mut PathResolver) {
+        for entry in &mut self.entries {
+            entry.resolve_paths(resolver);
+        }
+    }
+
+    // Record the newest sequence number each deleted record is referred to by, once every
+    // directory index and parent reference is known
+    pub fn resolve_sequence_references(&mut self) {
+        let mut references: HashMap<u64, u16> = HashMap::new();
+        let mut add_reference = |record_number: u64, sequence_number: u16| {
+            let newest = references.entry(record_number).or_insert(sequence_number);
+            *newest = (*newest).max(sequence_number);
+        };
+
+        for entry in &self.entries {
+            for name in &entry.names {
+                add_reference(name.parent_record_number, name.parent_sequence_number);
+            }
+            for index_entry in &entry.index_entries {
+                add_reference(index_entry.record_number, index_entry.sequence_number);
+            }
+            for slack_entry in &entry.slack_entries {
+                if let (Some(record_number), Some(sequence_number)) = (slack_entry.record_number, slack_entry.sequence_number) {
+                    add_reference(record_number, sequence_number);
+                }
+            }
+        }
+
+        for entry in &mut self.entries {
+            if let Some(recovery) = entry.recovery.as_mut() {
+                recovery.referenced_sequence_number = references.get(&entry.record_number).copied();
+            }
+        }
+    }
+}
+
+impl DbEntry {
+    // Fill in the full path of the entry and of each of its names
+    pub fn resolve_paths(&mut self, resolver: &mut PathResolver) {
+        for name in &mut self.names {
+            let parent = FileReference {
+                record_number: name.parent_record_number,
+                sequence_number: name.parent_sequence_number,
+            };
+            name.full_path = resolver.resolve_in_directory(parent, &name.file_name);
+        }
+        // Taken from the entry's own names rather than looked up by record number, as carved
+        // data may hold several versions of the same record under different names
+        self.full_path = match self.names.iter().find(|name| name.file_name == self.file_name) {
+            Some(name) => name.full_path.clone(),
+            None => resolver.resolve(self.record_number),
+        };
+    }
+
+    // Assess deleted records against the volume bitmap when one is given
+    pub fn from_logical_file(file: &LogicalFile, bitmap: Option<&VolumeBitmap>) -> Result<Self> {
+        // Here you would extract the necessary information from the LogicalFile
+        // and create a DbEntry with the structured data for the database.
+
+        // Timestamps and DOS attributes come from $STANDARD_INFORMATION
+        let standard_information = file.standard_information()
+            .with_context(|| format!("Failed to read $STANDARD_INFORMATION of MFT entry {}", file.record_number()))?;
+        let (creation_time, modification_time, mft_modified_time, access_time, file_attributes) = match standard_information {
+            Some(si) => (
+                format_timestamp(&si.created),
+                format_timestamp(&si.modified),
+                format_timestamp(&si.mft_modified),
+                format_timestamp(&si.accessed),
+                si.file_attributes,
+            ),
+            None => Default::default(),
+        };
+
+        // The display name comes from the preferred $FILE_NAME
+        let file_names = file.file_names()
+            .with_context(|| format!("Failed to read $FILE_NAME of MFT entry {}", file.record_number()))?;
+        let preferred = preferred_name(&file_names);
+        let file_name = preferred.map(|file_name| file_name.name.clone()).unwrap_or_default();
+        let names = hard_links(&file_names).into_iter()
+            .map(|link| DbName {
+                file_name: link.name.clone(),
+                full_path: String::new(),
+                parent_record_number: link.parent.record_number,
+                parent_sequence_number: link.parent.sequence_number,
+                creation_time: format_timestamp(&link.created),
+                modification_time: format_timestamp(&link.modified),
+                mft_modified_time: format_timestamp(&link.mft_modified),
+                access_time: format_timestamp(&link.accessed),
+            })
+            .collect();
+
+        let data_streams = file.data_streams()
+            .with_context(|| format!("Failed to read $DATA streams of MFT entry {}", file.record_number()))?;
+        let has_ads = data_streams.iter().any(|stream| stream.is_alternate());
+        // The size comes from the unnamed $DATA stream, as the size in $FILE_NAME is only
+        // updated when the name is. Directories and other files without one fall back to it.
+        let file_size = match data_streams.iter().find(|stream| !stream.is_alternate()) {
+            Some(stream) => stream.size,
+            None => preferred.map_or(0, |file_name| file_name.real_size),
+        };
+        let streams = data_streams.into_iter()
+            .map(|stream| DbStream {
+                stream_name: stream.name,
+                size: stream.size,
+                resident: stream.resident,
+                allocated_clusters: stream.allocated_clusters,
+            })
+            .collect();
+
+        // A damaged reparse point doesn't cost the file its entry. Its tag is kept, while the
+        // algorithm and target it held are left out, and reading the content reports the damage.
+        let wof_algorithm = file.wof_compression().ok().flatten()
+            .map(|compression| compression.to_string());
+
+        let in_use = file.base.is_in_use();
+        let recovery = if in_use {
+            None
+        } else {
+            Some(RecoveryAssessment::assess(file, bitmap)
+                .with_context(|| format!("Failed to assess deleted MFT entry {}", file.record_number()))?)
+        };
+
+        let reparse_point = file.reparse_point()
+            .unwrap_or_else(|_| file.reparse_data().and_then(ReparsePoint::from_tag));
+        let (reparse_tag, reparse_target) = match reparse_point {
+            Some(reparse_point) => (Some(reparse_point.tag_name()), reparse_point.target()),
+            None => (None, None),
+        };
+
+        Ok(DbEntry {
+            record_number: file.record_number(),
+            sequence_number: file.base.sequence_number,
+            source_path: None,
+            physical_offset: None,
+            file_name,
+            // Resolved once every directory has been seen, see StructuredData::resolve_paths
+            full_path: String::new(),
+            file_size,
+            creation_time,
+            modification_time,
+            mft_modified_time,
+            access_time,
+            file_attributes,
+            is_directory: file.base.is_directory(),
+            in_use,
+            recovery,
+            names,
+            streams,
+            has_ads,
+            wof_algorithm,
+            reparse_tag,
+            reparse_target,
+            // Read from the volume by the caller, as the index may live outside the MFT
+            index_entries: Vec::new(),
+            slack_entries: Vec::new(),
+            // Found by the caller, which knows when the volume was created and the scan started
+            timestamp_anomalies: Vec::new(),
+            // Populate other fields as necessary
+        })
+    }
+}
+
+// Format a timestamp as RFC 3339 in UTC, keeping the sub-second precision NTFS stores
+pub fn format_timestamp(timestamp: &DateTime<Utc>) -> String {
+    timestamp.to_rfc3339_opts(SecondsFormat::AutoSi, true)
 }
 
 // Add more methods and logic as needed for your project.
@@ -55,31 +330,123 @@ impl StructuredData {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::attribute::{ATTRIBUTE_TYPE_DATA, ATTRIBUTE_TYPE_FILE_NAME, ATTRIBUTE_TYPE_REPARSE_POINT, ATTRIBUTE_TYPE_STANDARD_INFORMATION};
+    use crate::mft_parser::{FileReference, FILE_RECORD_IN_USE};
+    use crate::recovery::RecoveryVerdict;
+    use crate::reparse_point::{IO_REPARSE_TAG_SYMLINK, PRINT_NAME_LENGTH_OFFSET};
+    use crate::standard_information::FILE_ATTRIBUTE_ARCHIVE;
+    use crate::test_support::{fake_file_name_value, fake_file_record, fake_link_reparse_data, fake_logical_file, fake_resident_attribute, fake_standard_information_value, fake_wof_reparse_data};
 
     #[test]
     fn test_structuring_data() {
+        // Build a fake MFT entry holding a $STANDARD_INFORMATION and a DOS and Win32 $FILE_NAME
+        let parent = FileReference { record_number: 5, sequence_number: 5 };
+        let mut dos_name = fake_file_name_value(parent, "TEST_F~1", 2);
+        // Set the real size of both names
+        dos_name[0x30..0x38].copy_from_slice(&1024u64.to_le_bytes());
+        let mut attributes = fake_resident_attribute(
+            ATTRIBUTE_TYPE_STANDARD_INFORMATION,
+            "",
+            // 2022-01-01T00:00:00Z as a FILETIME
+            &fake_standard_information_value(132_854_688_000_000_000, FILE_ATTRIBUTE_ARCHIVE),
+        );
+        attributes.extend(fake_resident_attribute(ATTRIBUTE_TYPE_FILE_NAME, "", &dos_name));
+        let mut win32_name = fake_file_name_value(parent, "test_file", 1);
+        win32_name[0x30..0x38].copy_from_slice(&1024u64.to_le_bytes());
+        attributes.extend(fake_resident_attribute(ATTRIBUTE_TYPE_FILE_NAME, "", &win32_name));
+
         // Create a vector of fake MFT entries for testing purposes
         let mft_entries = vec![
-            MftEntry {
-                signature: "FILE".to_string(),
-                record_number: 12345,
-                file_name: "test_file".to_string(),
-                file_size: 1024,
-                creation_time: "2022-01-01T00:00:00Z".to_string(),
-                // Initialize other fields as necessary
-            },
-            // Add more fake MFT entries if needed
+            (12345, MftEntry::parse(&fake_file_record(12345, FILE_RECORD_IN_USE, &attributes)).unwrap()),
         ];
 
         // Structure the data from the MFT entries
-        let structured_data = StructuredData::from_mft_entries(mft_entries).unwrap();
+        let structured_data = StructuredData::from_mft_entries(mft_entries, "C:").unwrap();
 
         // Check the structured data
         assert_eq!(structured_data.entries.len(), 1);
         assert_eq!(structured_data.entries[0].record_number, 12345);
+        assert_eq!(structured_data.entries[0].sequence_number, 1);
+        assert_eq!(structured_data.entries[0].physical_offset, None);
         assert_eq!(structured_data.entries[0].file_name, "test_file");
+        assert_eq!(structured_data.entries[0].full_path, "C:\\test_file");
         assert_eq!(structured_data.entries[0].file_size, 1024);
         assert_eq!(structured_data.entries[0].creation_time, "2022-01-01T00:00:00Z");
+        assert_eq!(structured_data.entries[0].file_attributes, FILE_ATTRIBUTE_ARCHIVE);
+        assert!(structured_data.entries[0].streams.is_empty());
+        assert!(!structured_data.entries[0].has_ads);
+        assert_eq!(structured_data.entries[0].wof_algorithm, None);
+        assert_eq!(structured_data.entries[0].reparse_target, None);
+        assert!(structured_data.entries[0].index_entries.is_empty());
+        assert!(structured_data.entries[0].slack_entries.is_empty());
+        assert!(structured_data.entries[0].timestamp_anomalies.is_empty());
+        assert!(structured_data.entries[0].in_use);
+        assert_eq!(structured_data.entries[0].recovery, None);
+        // The DOS name is an alias of the Win32 name rather than a separate hard link
+        assert_eq!(structured_data.entries[0].names.len(), 1);
+        assert_eq!(structured_data.entries[0].names[0].full_path, "C:\\test_file");
+        assert_eq!(structured_data.entries[0].names[0].creation_time, "1601-01-01T00:00:00Z");
+        assert!(!structured_data.entries[0].is_directory);
         // Add more assertions as necessary
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_deleted_record_assessment() {
+        // A deleted directory, still named as the parent of a file it held before its delete
+        let root = FileReference { record_number: 5, sequence_number: 5 };
+        let directory = fake_resident_attribute(ATTRIBUTE_TYPE_FILE_NAME, "", &fake_file_name_value(root, "old", 1));
+        let child_parent = FileReference { record_number: 80, sequence_number: 0 };
+        let child = [
+            fake_resident_attribute(ATTRIBUTE_TYPE_FILE_NAME, "", &fake_file_name_value(child_parent, "notes.txt", 1)),
+            fake_resident_attribute(ATTRIBUTE_TYPE_DATA, "", b"meeting notes"),
+        ].concat();
+        let mft_entries = vec![
+            (80, MftEntry::parse(&fake_file_record(80, 0, &directory)).unwrap()),
+            (81, MftEntry::parse(&fake_file_record(81, FILE_RECORD_IN_USE, &child)).unwrap()),
+        ];
+
+        let structured_data = StructuredData::from_mft_entries(mft_entries, "C:").unwrap();
+        assert!(!structured_data.entries[0].in_use);
+        let recovery = structured_data.entries[0].recovery.as_ref().unwrap();
+        assert_eq!(recovery.referenced_sequence_number, Some(0));
+        assert_eq!(recovery.sequence_bumped(), Some(true));
+        assert_eq!(recovery.verdict(), RecoveryVerdict::Recoverable);
+        assert_eq!(structured_data.entries[1].recovery, None);
+        // Sized by its $DATA stream, not by the stale size in $FILE_NAME
+        assert_eq!(structured_data.entries[1].file_size, 13);
+    }
+
+    #[test]
+    fn test_record_versions_keep_their_own_paths() {
+        // Two versions of record 64, as carving may find, each under its own name
+        let root = FileReference { record_number: 5, sequence_number: 5 };
+        let mft_entries = ["old.txt", "new.txt"].into_iter()
+            .map(|name| {
+                let name = fake_resident_attribute(ATTRIBUTE_TYPE_FILE_NAME, "", &fake_file_name_value(root, name, 1));
+                (64, MftEntry::parse(&fake_file_record(64, 0, &name)).unwrap())
+            })
+            .collect();
+
+        let structured_data = StructuredData::from_mft_entries(mft_entries, "C:").unwrap();
+        let paths: Vec<&str> = structured_data.entries.iter().map(|entry| entry.full_path.as_str()).collect();
+        assert_eq!(paths, vec!["C:\\old.txt", "C:\\new.txt"]);
+    }
+
+    #[test]
+    fn test_unsupported_wof_algorithm_listed() {
+        let attributes = fake_resident_attribute(ATTRIBUTE_TYPE_REPARSE_POINT, "", &fake_wof_reparse_data(7));
+        let entry = DbEntry::from_logical_file(&fake_logical_file(64, FILE_RECORD_IN_USE, &attributes), None).unwrap();
+        assert_eq!(entry.wof_algorithm.as_deref(), Some("UNSUPPORTED (version 1, algorithm 7)"));
+    }
+
+    #[test]
+    fn test_damaged_reparse_point_listed() {
+        let mut value = fake_link_reparse_data(IO_REPARSE_TAG_SYMLINK, "target", "target", 0);
+        // A print name running past the reparse data
+        value[PRINT_NAME_LENGTH_OFFSET..PRINT_NAME_LENGTH_OFFSET + 2].copy_from_slice(&0x100u16.to_le_bytes());
+        let attributes = fake_resident_attribute(ATTRIBUTE_TYPE_REPARSE_POINT, "", &value);
+        let entry = DbEntry::from_logical_file(&fake_logical_file(64, FILE_RECORD_IN_USE, &attributes), None).unwrap();
+        assert_eq!(entry.reparse_tag.as_deref(), Some("SYMLINK"));
+        assert_eq!(entry.reparse_target, None);
+    }
+}
@@ -1,5 +1,6 @@
 use crate::config::Config;
 use crate::data_structurer::StructuredData;
+use crate::timeline::{format_event_timestamp, TimelineEvent};
 use anyhow::{Result, Context};
 use sqlx::{sqlite::SqlitePoolOptions, Pool, Sqlite, Transaction};
 
@@ -22,6 +23,21 @@ impl DatabaseInterface {
             r#"
             CREATE TABLE IF NOT EXISTS files (
                 record_number INTEGER PRIMARY KEY,
+                sequence_number INTEGER NOT NULL DEFAULT 0,
+                file_name TEXT NOT NULL,
+                full_path TEXT NOT NULL,
+                file_size INTEGER NOT NULL,
+                creation_time TEXT,
+                modification_time TEXT,
+                mft_modified_time TEXT,
+                access_time TEXT,
+                file_attributes INTEGER NOT NULL DEFAULT 0,
+                is_directory BOOLEAN NOT NULL DEFAULT FALSE,
+                in_use BOOLEAN NOT NULL DEFAULT TRUE,
+                has_ads BOOLEAN NOT NULL DEFAULT FALSE,
+                wof_algorithm TEXT,
+                reparse_tag TEXT,
+                reparse_target TEXT
                 -- Add more columns as necessary to store the file information
             )
             "#,
@@ -30,6 +46,170 @@ impl DatabaseInterface {
         .await
         .context("Failed to create tables")?;
 
+        // One row per hard link, so a file can be found under any of its paths
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS names (
+                record_number INTEGER NOT NULL REFERENCES files (record_number),
+                file_name TEXT NOT NULL,
+                full_path TEXT NOT NULL,
+                parent_record_number INTEGER NOT NULL,
+                parent_sequence_number INTEGER NOT NULL,
+                creation_time TEXT,
+                modification_time TEXT,
+                mft_modified_time TEXT,
+                access_time TEXT
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to create tables")?;
+
+        sqlx::query("CREATE INDEX IF NOT EXISTS names_full_path ON names (full_path)")
+            .execute(&self.pool)
+            .await
+            .context("Failed to create tables")?;
+
+        // One row per $DATA stream; the unnamed stream has an empty stream_name
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS streams (
+                record_number INTEGER NOT NULL REFERENCES files (record_number),
+                stream_name TEXT NOT NULL,
+                size INTEGER NOT NULL,
+                resident BOOLEAN NOT NULL,
+                allocated_clusters INTEGER NOT NULL
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to create tables")?;
+
+        // One row per entry of a directory's $I30 index. Comparing it with names shows files
+        // missing from their directory index, or index entries no longer backed by a file.
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS index_entries (
+                directory_record_number INTEGER NOT NULL REFERENCES files (record_number),
+                file_name TEXT NOT NULL,
+                record_number INTEGER NOT NULL,
+                sequence_number INTEGER NOT NULL
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to create tables")?;
+
+        // Entries carved from INDX slack are kept apart from the live index entries, as they
+        // name files that were removed from the directory
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS index_slack_entries (
+                directory_record_number INTEGER NOT NULL REFERENCES files (record_number),
+                file_name TEXT NOT NULL,
+                record_number INTEGER,
+                sequence_number INTEGER,
+                file_size INTEGER NOT NULL,
+                creation_time TEXT,
+                modification_time TEXT,
+                mft_modified_time TEXT,
+                access_time TEXT,
+                block_number INTEGER NOT NULL,
+                block_offset INTEGER NOT NULL
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to create tables")?;
+
+        // One row per deleted record, with what is left of the file to triage recovery requests
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS deleted_records (
+                record_number INTEGER NOT NULL REFERENCES files (record_number),
+                sequence_number INTEGER NOT NULL,
+                referenced_sequence_number INTEGER,
+                sequence_bumped BOOLEAN,
+                resident_data_intact BOOLEAN,
+                free_clusters INTEGER,
+                reallocated_clusters INTEGER,
+                verdict TEXT NOT NULL
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to create tables")?;
+
+        // Findings of the timestamp analyzer, one row per rule a file breaks
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS timestamp_anomalies (
+                record_number INTEGER NOT NULL REFERENCES files (record_number),
+                rule_id TEXT NOT NULL,
+                explanation TEXT NOT NULL
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to create tables")?;
+
+        // Events of the volume timeline, inserted in time order. Timestamps are RFC 3339 with all
+        // nine fractional digits, so they compare as text in time order.
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS timeline (
+                timestamp TEXT NOT NULL,
+                event_type TEXT NOT NULL,
+                record_number INTEGER NOT NULL,
+                sequence_number INTEGER NOT NULL,
+                in_use BOOLEAN NOT NULL,
+                full_path TEXT NOT NULL,
+                usn_reasons TEXT
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to create tables")?;
+
+        sqlx::query("CREATE INDEX IF NOT EXISTS timeline_timestamp ON timeline (timestamp)")
+            .execute(&self.pool)
+            .await
+            .context("Failed to create tables")?;
+
+        // Records found by carving raw data. Several versions of a record may be carved, and
+        // they may share their record number with live records, so they are kept apart from
+        // the files table and identified by the source and offset they were found at.
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS carved_records (
+                source_path TEXT NOT NULL,
+                physical_offset INTEGER NOT NULL,
+                record_number INTEGER NOT NULL,
+                sequence_number INTEGER NOT NULL,
+                in_use BOOLEAN NOT NULL,
+                file_name TEXT NOT NULL,
+                full_path TEXT NOT NULL,
+                file_size INTEGER NOT NULL,
+                creation_time TEXT,
+                modification_time TEXT,
+                mft_modified_time TEXT,
+                access_time TEXT,
+                file_attributes INTEGER NOT NULL DEFAULT 0,
+                PRIMARY KEY (source_path, physical_offset)
+            )
+            "#,
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to create tables")?;
+
         Ok(())
     }
 
@@ -58,14 +238,203 @@ impl DatabaseInterface {
 
     pub async fn store_data(&self, data: &StructuredData, transaction: &mut Transaction<Sqlite>) -> Result<()> {
         for entry in &data.entries {
-            sqlx::query("INSERT INTO files (record_number, file_name, file_size, creation_time) VALUES (?, ?, ?, ?)")
-                .bind(entry.record_number)
-                .bind(entry.file_name)
-                .bind(entry.file_size)
-                .bind(entry.creation_time)
-                .execute(transaction)
+            if let (Some(source_path), Some(physical_offset)) = (&entry.source_path, entry.physical_offset) {
+                // Carving the same source again finds the same records, which are kept once
+                sqlx::query(
+                    r#"
+                    INSERT OR IGNORE INTO carved_records (source_path, physical_offset, record_number, sequence_number,
+                                                          in_use, file_name, full_path, file_size, creation_time,
+                                                          modification_time, mft_modified_time, access_time,
+                                                          file_attributes)
+                    VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+                    "#,
+                )
+                .bind(source_path)
+                .bind(physical_offset as i64)
+                .bind(entry.record_number as i64)
+                .bind(entry.sequence_number)
+                .bind(entry.in_use)
+                .bind(&entry.file_name)
+                .bind(&entry.full_path)
+                .bind(entry.file_size as i64)
+                .bind(&entry.creation_time)
+                .bind(&entry.modification_time)
+                .bind(&entry.mft_modified_time)
+                .bind(&entry.access_time)
+                .bind(entry.file_attributes)
+                .execute(&mut *transaction)
                 .await
-                .context("Failed to insert entry into the database")?;
+                .context("Failed to insert carved record into the database")?;
+                continue;
+            }
+
+            sqlx::query(
+                r#"
+                INSERT INTO files (record_number, sequence_number, file_name, full_path, file_size, creation_time,
+                                   modification_time, mft_modified_time, access_time, file_attributes, is_directory,
+                                   in_use, has_ads, wof_algorithm, reparse_tag, reparse_target)
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+                "#,
+            )
+            .bind(entry.record_number as i64)
+            .bind(entry.sequence_number)
+            .bind(&entry.file_name)
+            .bind(&entry.full_path)
+            .bind(entry.file_size as i64)
+            .bind(&entry.creation_time)
+            .bind(&entry.modification_time)
+            .bind(&entry.mft_modified_time)
+            .bind(&entry.access_time)
+            .bind(entry.file_attributes)
+            .bind(entry.is_directory)
+            .bind(entry.in_use)
+            .bind(entry.has_ads)
+            .bind(&entry.wof_algorithm)
+            .bind(&entry.reparse_tag)
+            .bind(&entry.reparse_target)
+            .execute(&mut *transaction)
+            .await
+            .context("Failed to insert entry into the database")?;
+
+            if let Some(recovery) = &entry.recovery {
+                sqlx::query(
+                    r#"
+                    INSERT INTO deleted_records (record_number, sequence_number, referenced_sequence_number, sequence_bumped,
+                                                 resident_data_intact, free_clusters, reallocated_clusters, verdict)
+                    VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+                    "#,
+                )
+                .bind(entry.record_number as i64)
+                .bind(recovery.sequence_number)
+                .bind(recovery.referenced_sequence_number)
+                .bind(recovery.sequence_bumped())
+                .bind(recovery.resident_data_intact)
+                .bind(recovery.cluster_usage.map(|usage| usage.free as i64))
+                .bind(recovery.cluster_usage.map(|usage| usage.reallocated as i64))
+                .bind(recovery.verdict().to_string())
+                .execute(&mut *transaction)
+                .await
+                .context("Failed to insert deleted record into the database")?;
+            }
+
+            for anomaly in &entry.timestamp_anomalies {
+                sqlx::query(
+                    r#"
+                    INSERT INTO timestamp_anomalies (record_number, rule_id, explanation)
+                    VALUES (?, ?, ?)
+                    "#,
+                )
+                .bind(entry.record_number as i64)
+                .bind(anomaly.rule.id())
+                .bind(&anomaly.explanation)
+                .execute(&mut *transaction)
+                .await
+                .context("Failed to insert timestamp anomaly into the database")?;
+            }
+
+            for name in &entry.names {
+                sqlx::query(
+                    r#"
+                    INSERT INTO names (record_number, file_name, full_path, parent_record_number, parent_sequence_number,
+                                       creation_time, modification_time, mft_modified_time, access_time)
+                    VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+                    "#,
+                )
+                .bind(entry.record_number as i64)
+                .bind(&name.file_name)
+                .bind(&name.full_path)
+                .bind(name.parent_record_number as i64)
+                .bind(name.parent_sequence_number)
+                .bind(&name.creation_time)
+                .bind(&name.modification_time)
+                .bind(&name.mft_modified_time)
+                .bind(&name.access_time)
+                .execute(&mut *transaction)
+                .await
+                .context("Failed to insert name into the database")?;
+            }
+
+            for stream in &entry.streams {
+                sqlx::query(
+                    r#"
+                    INSERT INTO streams (record_number, stream_name, size, resident, allocated_clusters)
+                    VALUES (?, ?, ?, ?, ?)
+                    "#,
+                )
+                .bind(entry.record_number as i64)
+                .bind(&stream.stream_name)
+                .bind(stream.size as i64)
+                .bind(stream.resident)
+                .bind(stream.allocated_clusters as i64)
+                .execute(&mut *transaction)
+                .await
+                .context("Failed to insert stream into the database")?;
+            }
+
+            for index_entry in &entry.index_entries {
+                sqlx::query(
+                    r#"
+                    INSERT INTO index_entries (directory_record_number, file_name, record_number, sequence_number)
+                    VALUES (?, ?, ?, ?)
+                    "#,
+                )
+                .bind(entry.record_number as i64)
+                .bind(&index_entry.file_name)
+                .bind(index_entry.record_number as i64)
+                .bind(index_entry.sequence_number)
+                .execute(&mut *transaction)
+                .await
+                .context("Failed to insert index entry into the database")?;
+            }
+
+            for slack_entry in &entry.slack_entries {
+                sqlx::query(
+                    r#"
+                    INSERT INTO index_slack_entries (directory_record_number, file_name, record_number, sequence_number,
+                                                     file_size, creation_time, modification_time, mft_modified_time,
+                                                     access_time, block_number, block_offset)
+                    VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+                    "#,
+                )
+                .bind(entry.record_number as i64)
+                .bind(&slack_entry.file_name)
+                .bind(slack_entry.record_number.map(|record_number| record_number as i64))
+                .bind(slack_entry.sequence_number)
+                .bind(slack_entry.file_size as i64)
+                .bind(&slack_entry.creation_time)
+                .bind(&slack_entry.modification_time)
+                .bind(&slack_entry.mft_modified_time)
+                .bind(&slack_entry.access_time)
+                .bind(slack_entry.block_number as i64)
+                .bind(slack_entry.block_offset as i64)
+                .execute(&mut *transaction)
+                .await
+                .context("Failed to insert slack entry into the database")?;
+            }
+        }
+        Ok(())
+    }
+
+    // Store the events of a timeline as they are read, so the whole timeline is never held at once
+    pub async fn store_timeline<I: Iterator<Item = Result<TimelineEvent>>>(&self, events: I, transaction: &mut Transaction<Sqlite>) -> Result<()> {
+        for event in events {
+            let event = event?;
+            sqlx::query(
+                r#"
+                INSERT INTO timeline (timestamp, event_type, record_number, sequence_number, in_use, full_path, usn_reasons)
+                VALUES (?, ?, ?, ?, ?, ?, ?)
+                "#,
+            )
+            .bind(format_event_timestamp(&event.timestamp))
+            .bind(event.event_type.id())
+            .bind(event.record_number as i64)
+            .bind(event.sequence_number)
+            .bind(event.in_use)
+            .bind(&event.full_path)
+            .bind(&event.usn_reasons)
+            .execute(&mut *transaction)
+            .await
+            .context("Failed to insert timeline event into the database")?;
         }
         Ok(())
     }
@@ -80,7 +449,8 @@ impl DatabaseInterface {
 mod tests {
     use super::*;
     use crate::config::Config;
-    use crate::data_structurer::{DbEntry, StructuredData};
+    use crate::data_structurer::{DbEntry, DbIndexEntry, DbName, DbSlackEntry, DbStream, StructuredData};
+    use crate::timestamp_anomaly::{TimestampAnomaly, TimestampRule};
 
     #[tokio::test]
     async fn test_database_operations() -> Result<()> {
@@ -92,9 +462,71 @@ mod tests {
             entries: vec![
                 DbEntry {
                     record_number: 12345,
+                    sequence_number: 1,
+                    source_path: None,
+                    physical_offset: None,
                     file_name: "test.txt".to_string(),
+                    full_path: "C:\\test.txt".to_string(),
                     file_size: 1024,
                     creation_time: "2021-01-01T00:00:00Z".to_string(),
+                    modification_time: "2021-01-02T00:00:00Z".to_string(),
+                    mft_modified_time: "2021-01-02T00:00:00Z".to_string(),
+                    access_time: "2021-01-03T00:00:00Z".to_string(),
+                    file_attributes: 0x20,
+                    is_directory: false,
+                    in_use: true,
+                    recovery: None,
+                    names: vec![
+                        DbName {
+                            file_name: "test.txt".to_string(),
+                            full_path: "C:\\test.txt".to_string(),
+                            parent_record_number: 5,
+                            parent_sequence_number: 5,
+                            creation_time: "2021-01-01T00:00:00Z".to_string(),
+                            modification_time: "2021-01-01T00:00:00Z".to_string(),
+                            mft_modified_time: "2021-01-01T00:00:00Z".to_string(),
+                            access_time: "2021-01-01T00:00:00Z".to_string(),
+                        },
+                    ],
+                    streams: vec![
+                        DbStream {
+                            stream_name: String::new(),
+                            size: 1024,
+                            resident: false,
+                            allocated_clusters: 1,
+                        },
+                    ],
+                    has_ads: false,
+                    wof_algorithm: None,
+                    reparse_tag: None,
+                    reparse_target: None,
+                    index_entries: vec![
+                        DbIndexEntry {
+                            file_name: "notes.txt".to_string(),
+                            record_number: 12346,
+                            sequence_number: 1,
+                        },
+                    ],
+                    slack_entries: vec![
+                        DbSlackEntry {
+                            file_name: "deleted.txt".to_string(),
+                            record_number: Some(12347),
+                            sequence_number: Some(2),
+                            file_size: 2048,
+                            creation_time: "2021-01-01T00:00:00Z".to_string(),
+                            modification_time: "2021-01-02T00:00:00Z".to_string(),
+                            mft_modified_time: "2021-01-02T00:00:00Z".to_string(),
+                            access_time: "2021-01-03T00:00:00Z".to_string(),
+                            block_number: 0,
+                            block_offset: 0x1A8,
+                        },
+                    ],
+                    timestamp_anomalies: vec![
+                        TimestampAnomaly {
+                            rule: TimestampRule::SiCreatedBeforeFn,
+                            explanation: "$SI creation time 2019-01-01T00:00:00Z is earlier than $FN creation time 2021-01-01T00:00:00Z".to_string(),
+                        },
+                    ],
                 },
             ],
         };
@@ -0,0 +1,175 @@
+use crate::attribute::{decode_utf16, Attribute};
+use crate::mft_parser::FileReference;
+use crate::utils::filetime_to_datetime;
+use anyhow::{bail, Context, Result};
+use byteorder::{ByteOrder, LittleEndian};
+use chrono::{DateTime, Utc};
+
+// Define constants for $FILE_NAME offsets and sizes
+pub(crate) const FN_PARENT_REFERENCE_OFFSET: usize = 0x00;
+pub(crate) const FN_CREATED_OFFSET: usize = 0x08;
+pub(crate) const FN_MODIFIED_OFFSET: usize = 0x10;
+pub(crate) const FN_MFT_MODIFIED_OFFSET: usize = 0x18;
+pub(crate) const FN_ACCESSED_OFFSET: usize = 0x20;
+const FN_ALLOCATED_SIZE_OFFSET: usize = 0x28;
+const FN_REAL_SIZE_OFFSET: usize = 0x30;
+const FN_FLAGS_OFFSET: usize = 0x38;
+const FN_REPARSE_VALUE_OFFSET: usize = 0x3C;
+pub(crate) const FN_NAME_LENGTH_OFFSET: usize = 0x40;
+pub(crate) const FN_NAMESPACE_OFFSET: usize = 0x41;
+pub(crate) const FN_NAME_OFFSET: usize = 0x42;
+
+// Define the namespaces a $FILE_NAME can belong to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FileNamespace {
+    Posix,
+    Win32,
+    Dos,
+    Win32AndDos,
+}
+
+impl FileNamespace {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(FileNamespace::Posix),
+            1 => Some(FileNamespace::Win32),
+            2 => Some(FileNamespace::Dos),
+            3 => Some(FileNamespace::Win32AndDos),
+            _ => None,
+        }
+    }
+
+    // Lower values are preferred when choosing a name to display
+    fn display_priority(self) -> u8 {
+        match self {
+            FileNamespace::Win32AndDos | FileNamespace::Win32 => 0,
+            FileNamespace::Posix => 1,
+            FileNamespace::Dos => 2,
+        }
+    }
+}
+
+// Define a struct to hold a parsed $FILE_NAME attribute
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileName {
+    pub parent: FileReference,
+    pub created: DateTime<Utc>,
+    pub modified: DateTime<Utc>,
+    pub mft_modified: DateTime<Utc>,
+    pub accessed: DateTime<Utc>,
+    pub allocated_size: u64,
+    pub real_size: u64,
+    pub flags: u32,
+    pub reparse_value: u32,
+    pub namespace: FileNamespace,
+    pub name: String,
+}
+
+impl FileName {
+    pub fn parse(value: &[u8]) -> Result<Self> {
+        if value.len() < FN_NAME_OFFSET {
+            bail!("$FILE_NAME of {} bytes is too short", value.len());
+        }
+
+        let name_length = usize::from(value[FN_NAME_LENGTH_OFFSET]);
+        if FN_NAME_OFFSET + name_length * 2 > value.len() {
+            bail!("$FILE_NAME name of {} characters runs past the end of the attribute", name_length);
+        }
+
+        let namespace = match FileNamespace::from_u8(value[FN_NAMESPACE_OFFSET]) {
+            Some(namespace) => namespace,
+            None => bail!("Invalid $FILE_NAME namespace {}", value[FN_NAMESPACE_OFFSET]),
+        };
+
+        Ok(FileName {
+            parent: FileReference::from_u64(LittleEndian::read_u64(&value[FN_PARENT_REFERENCE_OFFSET..])),
+            created: filetime_to_datetime(LittleEndian::read_u64(&value[FN_CREATED_OFFSET..])),
+            modified: filetime_to_datetime(LittleEndian::read_u64(&value[FN_MODIFIED_OFFSET..])),
+            mft_modified: filetime_to_datetime(LittleEndian::read_u64(&value[FN_MFT_MODIFIED_OFFSET..])),
+            accessed: filetime_to_datetime(LittleEndian::read_u64(&value[FN_ACCESSED_OFFSET..])),
+            allocated_size: LittleEndian::read_u64(&value[FN_ALLOCATED_SIZE_OFFSET..]),
+            real_size: LittleEndian::read_u64(&value[FN_REAL_SIZE_OFFSET..]),
+            flags: LittleEndian::read_u32(&value[FN_FLAGS_OFFSET..]),
+            reparse_value: LittleEndian::read_u32(&value[FN_REPARSE_VALUE_OFFSET..]),
+            namespace,
+            name: decode_utf16(&value[FN_NAME_OFFSET..FN_NAME_OFFSET + name_length * 2]),
+        })
+    }
+
+    // Size of the attribute value holding this name, as stored in the MFT or an index key
+    pub fn value_length(&self) -> usize {
+        FN_NAME_OFFSET + self.name.encode_utf16().count() * 2
+    }
+
+    pub fn from_attribute(attribute: &Attribute) -> Result<Self> {
+        let value = attribute.resident_value()
+            .with_context(|| "$FILE_NAME attribute is not resident")?;
+        FileName::parse(value)
+            .with_context(|| "Failed to parse $FILE_NAME attribute")
+    }
+}
+
+// Pick the name to display when a record holds several $FILE_NAME attributes, preferring
+// the long Win32 name over the POSIX one and using the 8.3 DOS name only as a last resort
+pub fn preferred_name(file_names: &[FileName]) -> Option<&FileName> {
+    file_names.iter().min_by_key(|file_name| file_name.namespace.display_priority())
+}
+
+// Reduce the $FILE_NAME attributes of a record to its hard links. A DOS 8.3 name is only an
+// alias of the long name in the same directory, so it counts as a link only when it stands alone.
+pub fn hard_links(file_names: &[FileName]) -> Vec<&FileName> {
+    file_names.iter()
+        .filter(|file_name| {
+            file_name.namespace != FileNamespace::Dos
+                || !file_names.iter().any(|other| other.parent == file_name.parent && other.namespace != FileNamespace::Dos)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::fake_file_name_value;
+
+    #[test]
+    fn test_file_name_parsing() {
+        let parent = FileReference { record_number: 5, sequence_number: 5 };
+        let mut value = fake_file_name_value(parent, "Program Files", 1);
+        LittleEndian::write_u64(&mut value[FN_REAL_SIZE_OFFSET..], 4096);
+        LittleEndian::write_u32(&mut value[FN_FLAGS_OFFSET..], 0x1000_0000);
+
+        let file_name = FileName::parse(&value).unwrap();
+        assert_eq!(file_name.parent, parent);
+        assert_eq!(file_name.name, "Program Files");
+        assert_eq!(file_name.namespace, FileNamespace::Win32);
+        assert_eq!(file_name.real_size, 4096);
+        assert_eq!(file_name.flags, 0x1000_0000);
+    }
+
+    #[test]
+    fn test_preferred_name_selection() {
+        let parent = FileReference { record_number: 5, sequence_number: 5 };
+        let file_names = vec![
+            FileName::parse(&fake_file_name_value(parent, "PROGRA~1", 2)).unwrap(),
+            FileName::parse(&fake_file_name_value(parent, "Program Files", 1)).unwrap(),
+        ];
+        assert_eq!(preferred_name(&file_names).unwrap().name, "Program Files");
+        assert_eq!(preferred_name(&file_names[..1]).unwrap().name, "PROGRA~1");
+        assert!(preferred_name(&[]).is_none());
+    }
+
+    #[test]
+    fn test_hard_link_enumeration() {
+        let documents = FileReference { record_number: 40, sequence_number: 1 };
+        let backup = FileReference { record_number: 41, sequence_number: 1 };
+        let file_names = vec![
+            FileName::parse(&fake_file_name_value(documents, "REPORT~1.DOC", 2)).unwrap(),
+            FileName::parse(&fake_file_name_value(documents, "Report final.docx", 1)).unwrap(),
+            FileName::parse(&fake_file_name_value(backup, "report.docx", 3)).unwrap(),
+            FileName::parse(&fake_file_name_value(backup, "REPORT~2.DOC", 2)).unwrap(),
+        ];
+
+        let links: Vec<&str> = hard_links(&file_names).iter().map(|link| link.name.as_str()).collect();
+        assert_eq!(links, vec!["Report final.docx", "report.docx"]);
+    }
+}
@@ -0,0 +1,132 @@
+use byteorder::{ByteOrder, LittleEndian};
+use thiserror::Error;
+
+// Define constants for the update sequence array header shared by FILE and INDX records
+const USA_OFFSET_OFFSET: usize = 4;
+const USA_COUNT_OFFSET: usize = 6;
+
+// NTFS protects records in 512-byte strides regardless of the physical sector size
+pub const FIXUP_STRIDE: usize = 512;
+
+// Define the errors that can be raised while applying fixups
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum FixupError {
+    #[error("record of {0} bytes is too short to hold an update sequence array")]
+    TooShort(usize),
+    #[error("invalid update sequence array (offset {offset}, count {count}) for a {length} byte record")]
+    InvalidUpdateSequence { offset: usize, count: usize, length: usize },
+    #[error("torn write detected: sector {sector} ends with {found:#06x}, expected update sequence number {expected:#06x}")]
+    TornWrite { sector: usize, expected: u16, found: u16 },
+}
+
+// Verify the trailer of every 512-byte stride against the update sequence number and restore
+// the original bytes from the update sequence array. The record is left untouched on error.
+pub fn apply_fixups(record: &mut [u8]) -> Result<(), FixupError> {
+    let (usa_offset, sectors) = update_sequence_array(record)?;
+    let usn = LittleEndian::read_u16(&record[usa_offset..]);
+
+    // Check every trailer before modifying anything so a torn record is reported intact
+    for sector in 0..sectors {
+        let trailer_offset = (sector + 1) * FIXUP_STRIDE - 2;
+        let found = LittleEndian::read_u16(&record[trailer_offset..]);
+        if found != usn {
+            return Err(FixupError::TornWrite { sector, expected: usn, found });
+        }
+    }
+
+    for sector in 0..sectors {
+        let trailer_offset = (sector + 1) * FIXUP_STRIDE - 2;
+        let original_offset = usa_offset + 2 * (sector + 1);
+        record.copy_within(original_offset..original_offset + 2, trailer_offset);
+    }
+
+    Ok(())
+}
+
+// Restore what can be restored of a record that is being carved rather than parsed, such as a
+// partly overwritten one. Strides whose trailer still holds the update sequence number get
+// their original bytes back and the others are left as they are. Returns the number of strides
+// restored, which is zero when the update sequence array itself is unusable.
+pub fn apply_fixups_best_effort(record: &mut [u8]) -> usize {
+    let (usa_offset, sectors) = match update_sequence_array(record) {
+        Ok(update_sequence_array) => update_sequence_array,
+        Err(_) => return 0,
+    };
+    let usn = LittleEndian::read_u16(&record[usa_offset..]);
+
+    let mut restored = 0;
+    for sector in 0..sectors {
+        let trailer_offset = (sector + 1) * FIXUP_STRIDE - 2;
+        if LittleEndian::read_u16(&record[trailer_offset..]) == usn {
+            let original_offset = usa_offset + 2 * (sector + 1);
+            record.copy_within(original_offset..original_offset + 2, trailer_offset);
+            restored += 1;
+        }
+    }
+
+    restored
+}
+
+// Find the update sequence array of a record, returning its offset and the number of strides
+// it covers
+fn update_sequence_array(record: &[u8]) -> Result<(usize, usize), FixupError> {
+    if record.len() < USA_COUNT_OFFSET + 2 {
+        return Err(FixupError::TooShort(record.len()));
+    }
+
+    let usa_offset = usize::from(LittleEndian::read_u16(&record[USA_OFFSET_OFFSET..]));
+    let usa_count = usize::from(LittleEndian::read_u16(&record[USA_COUNT_OFFSET..]));
+
+    // The array holds the update sequence number followed by one entry per stride
+    let sectors = usa_count.saturating_sub(1);
+    if usa_count < 2
+        || usa_offset < USA_COUNT_OFFSET + 2
+        || usa_offset + usa_count * 2 > record.len()
+        || sectors * FIXUP_STRIDE > record.len()
+    {
+        return Err(FixupError::InvalidUpdateSequence { offset: usa_offset, count: usa_count, length: record.len() });
+    }
+
+    Ok((usa_offset, sectors))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fake_record() -> Vec<u8> {
+        let mut record = vec![0; 1024];
+        record[..4].copy_from_slice(b"FILE");
+        LittleEndian::write_u16(&mut record[USA_OFFSET_OFFSET..], 0x30);
+        LittleEndian::write_u16(&mut record[USA_COUNT_OFFSET..], 3);
+        // Update sequence number followed by the original sector trailers
+        record[0x30..0x36].copy_from_slice(&[0x07, 0x00, 0xAA, 0xBB, 0xCC, 0xDD]);
+        record[510..512].copy_from_slice(&[0x07, 0x00]);
+        record[1022..1024].copy_from_slice(&[0x07, 0x00]);
+        record
+    }
+
+    #[test]
+    fn test_fixup_application() {
+        let mut record = fake_record();
+        apply_fixups(&mut record).unwrap();
+
+        assert_eq!(&record[510..512], &[0xAA, 0xBB]);
+        assert_eq!(&record[1022..1024], &[0xCC, 0xDD]);
+    }
+
+    #[test]
+    fn test_torn_write_detection() {
+        let mut record = fake_record();
+        record[1022..1024].copy_from_slice(&[0x06, 0x00]);
+        let original = record.clone();
+
+        assert_eq!(apply_fixups(&mut record), Err(FixupError::TornWrite { sector: 1, expected: 7, found: 6 }));
+        assert_eq!(record, original);
+
+        // When carving, the intact sector is still restored
+        assert_eq!(apply_fixups_best_effort(&mut record), 1);
+        assert_eq!(&record[510..512], &[0xAA, 0xBB]);
+        assert_eq!(&record[1022..1024], &[0x06, 0x00]);
+    }
+}
@@ -0,0 +1,93 @@
+use anyhow::{bail, Result};
+
+// Longest codeword any of the supported formats uses
+pub const MAX_CODE_LENGTH: u8 = 16;
+
+// Define a decoder for a canonical Huffman code, the form XPRESS and LZX both transmit as a list
+// of codeword lengths. Codewords are assigned in order of length, then symbol value.
+#[derive(Debug, Clone)]
+pub struct HuffmanDecoder {
+    max_length: u8,
+    // Number of codewords of each length
+    counts: [u32; MAX_CODE_LENGTH as usize + 1],
+    // Symbols ordered by codeword length, then by value
+    symbols: Vec<u16>,
+}
+
+impl HuffmanDecoder {
+    // Build a decoder from the codeword length of every symbol, zero meaning unused. Incomplete
+    // codes are accepted, as encoders emit them for blocks using a single symbol or none at all.
+    pub fn new(lengths: &[u8], max_length: u8) -> Result<Self> {
+        let mut counts = [0; MAX_CODE_LENGTH as usize + 1];
+        for &length in lengths {
+            if length > max_length {
+                bail!("Huffman codeword length {} exceeds the maximum of {}", length, max_length);
+            }
+            counts[usize::from(length)] += 1;
+        }
+        counts[0] = 0;
+
+        let mut available: i64 = 1;
+        for &count in &counts[1..=usize::from(max_length)] {
+            available = (available << 1) - i64::from(count);
+            if available < 0 {
+                bail!("Huffman code is oversubscribed");
+            }
+        }
+
+        let mut symbols: Vec<u16> = (0..lengths.len() as u16).filter(|&symbol| lengths[usize::from(symbol)] != 0).collect();
+        symbols.sort_by_key(|&symbol| lengths[usize::from(symbol)]);
+
+        Ok(HuffmanDecoder {
+            max_length,
+            counts,
+            symbols,
+        })
+    }
+
+    // Longest codeword of the code, and so the number of bits `decode` expects
+    pub fn max_length(&self) -> u8 {
+        self.max_length
+    }
+
+    // Decode the symbol at the start of `bits`, which holds the next `max_length` bits of the
+    // stream with the first bit most significant. Returns the symbol and its codeword length.
+    pub fn decode(&self, bits: u32) -> Option<(u16, u8)> {
+        let mut first = 0;
+        let mut index = 0;
+
+        for length in 1..=self.max_length {
+            let code = bits >> (self.max_length - length);
+            let count = self.counts[usize::from(length)];
+            if code >= first && code - first < count {
+                return Some((self.symbols[(index + code - first) as usize], length));
+            }
+            index += count;
+            first = (first + count) << 1;
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_canonical_decoding() {
+        // Lengths 2, 1, 3, 3 give codewords 10, 0, 110 and 111
+        let decoder = HuffmanDecoder::new(&[2, 1, 3, 3], 3).unwrap();
+        assert_eq!(decoder.decode(0b010), Some((1, 1)));
+        assert_eq!(decoder.decode(0b101), Some((0, 2)));
+        assert_eq!(decoder.decode(0b110), Some((2, 3)));
+        assert_eq!(decoder.decode(0b111), Some((3, 3)));
+
+        // An incomplete code leaves some bit patterns undecodable
+        let decoder = HuffmanDecoder::new(&[0, 1], 3).unwrap();
+        assert_eq!(decoder.decode(0b000), Some((1, 1)));
+        assert_eq!(decoder.decode(0b100), None);
+
+        assert!(HuffmanDecoder::new(&[1, 1, 1], 3).is_err());
+    }
+}
@@ -0,0 +1,370 @@
+use crate::attribute::{
+    AttributeForm, ATTRIBUTE_TYPE_BITMAP, ATTRIBUTE_TYPE_FILE_NAME, ATTRIBUTE_TYPE_INDEX_ALLOCATION, ATTRIBUTE_TYPE_INDEX_ROOT,
+};
+use crate::file_name::FileName;
+use crate::fixup::{apply_fixups, apply_fixups_best_effort};
+use crate::index_slack::{carve_slack, SlackEntry};
+use crate::logical_file::LogicalFile;
+use crate::mft_parser::FileReference;
+use crate::mft_reader::MftReader;
+use anyhow::{bail, Context, Result};
+use byteorder::{ByteOrder, LittleEndian};
+use std::collections::HashSet;
+
+// Name of the index attributes holding the file names of a directory
+pub const I30_INDEX_NAME: &str = "$I30";
+
+// Define constants for $INDEX_ROOT offsets and sizes
+pub(crate) const ROOT_INDEXED_TYPE_OFFSET: usize = 0x00;
+pub(crate) const ROOT_COLLATION_RULE_OFFSET: usize = 0x04;
+pub(crate) const ROOT_INDEX_BLOCK_SIZE_OFFSET: usize = 0x08;
+pub(crate) const ROOT_NODE_HEADER_OFFSET: usize = 0x10;
+
+// Define constants for INDX record offsets and sizes
+pub(crate) const INDX_SIGNATURE_OFFSET: usize = 0x00;
+pub(crate) const INDX_SIGNATURE_SIZE: usize = 4;
+pub(crate) const INDX_VCN_OFFSET: usize = 0x10;
+pub(crate) const INDX_NODE_HEADER_OFFSET: usize = 0x18;
+
+// Define constants for index node header offsets and sizes. Offsets within the node are
+// relative to the start of the node header.
+pub(crate) const NODE_ENTRIES_OFFSET_OFFSET: usize = 0x00;
+pub(crate) const NODE_INDEX_LENGTH_OFFSET: usize = 0x04;
+pub(crate) const NODE_ALLOCATED_SIZE_OFFSET: usize = 0x08;
+pub(crate) const NODE_FLAGS_OFFSET: usize = 0x0C;
+pub(crate) const NODE_HEADER_SIZE: usize = 0x10;
+// Set in the root node when the index also has blocks in $INDEX_ALLOCATION
+pub(crate) const NODE_FLAG_LARGE_INDEX: u32 = 0x0000_0001;
+
+// Define constants for index entry offsets, sizes and flags
+pub(crate) const ENTRY_FILE_REFERENCE_OFFSET: usize = 0x00;
+pub(crate) const ENTRY_LENGTH_OFFSET: usize = 0x08;
+pub(crate) const ENTRY_KEY_LENGTH_OFFSET: usize = 0x0A;
+pub(crate) const ENTRY_FLAGS_OFFSET: usize = 0x0C;
+pub(crate) const ENTRY_KEY_OFFSET: usize = 0x10;
+const ENTRY_FLAG_HAS_SUBNODE: u16 = 0x0001;
+pub(crate) const ENTRY_FLAG_LAST: u16 = 0x0002;
+
+// Define a struct to hold a single entry of a $I30 index, naming one file of the directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexEntry {
+    pub file_reference: FileReference,
+    // Copy of the $FILE_NAME attribute of the file, as of its last update in the index
+    pub file_name: FileName,
+}
+
+// Define a struct to hold a parsed $INDEX_ROOT attribute of a $I30 index
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexRoot {
+    pub collation_rule: u32,
+    pub index_block_size: u32,
+    pub large_index: bool,
+    pub entries: Vec<IndexEntry>,
+}
+
+impl IndexRoot {
+    pub fn parse(value: &[u8]) -> Result<Self> {
+        if value.len() < ROOT_NODE_HEADER_OFFSET + NODE_HEADER_SIZE {
+            bail!("$INDEX_ROOT of {} bytes is too short", value.len());
+        }
+
+        let indexed_type = LittleEndian::read_u32(&value[ROOT_INDEXED_TYPE_OFFSET..]);
+        if indexed_type != ATTRIBUTE_TYPE_FILE_NAME {
+            bail!("$INDEX_ROOT indexes attribute {:#x} rather than $FILE_NAME", indexed_type);
+        }
+
+        Ok(IndexRoot {
+            collation_rule: LittleEndian::read_u32(&value[ROOT_COLLATION_RULE_OFFSET..]),
+            index_block_size: LittleEndian::read_u32(&value[ROOT_INDEX_BLOCK_SIZE_OFFSET..]),
+            large_index: LittleEndian::read_u32(&value[ROOT_NODE_HEADER_OFFSET + NODE_FLAGS_OFFSET..]) & NODE_FLAG_LARGE_INDEX != 0,
+            entries: parse_node(&value[ROOT_NODE_HEADER_OFFSET..])?.entries,
+        })
+    }
+}
+
+// Define a struct to hold a parsed INDX record, one block of $INDEX_ALLOCATION
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexRecord {
+    pub vcn: u64,
+    pub entries: Vec<IndexEntry>,
+    // Offsets within the block of the first entry, of the end of the entries in use and of the
+    // end of the space allocated to them. The bytes in between the last two are slack, which
+    // may still hold entries removed from the node.
+    pub entries_start: usize,
+    pub entries_end: usize,
+    pub allocated_end: usize,
+    // The block with fixups applied
+    pub data: Vec<u8>,
+}
+
+impl IndexRecord {
+    pub fn parse(block: &[u8]) -> Result<Self> {
+        if block.len() < INDX_NODE_HEADER_OFFSET + NODE_HEADER_SIZE {
+            bail!("INDX record of {} bytes is too short", block.len());
+        }
+        if &block[INDX_SIGNATURE_OFFSET..INDX_SIGNATURE_OFFSET + INDX_SIGNATURE_SIZE] != b"INDX" {
+            bail!("Invalid INDX record signature {:?}", String::from_utf8_lossy(&block[..INDX_SIGNATURE_SIZE]));
+        }
+
+        let mut block = block.to_vec();
+        apply_fixups(&mut block)
+            .with_context(|| "Failed to apply update sequence fixups")?;
+
+        let node = parse_node(&block[INDX_NODE_HEADER_OFFSET..])?;
+        Ok(IndexRecord {
+            vcn: LittleEndian::read_u64(&block[INDX_VCN_OFFSET..]),
+            entries: node.entries,
+            entries_start: INDX_NODE_HEADER_OFFSET + node.entries_offset,
+            entries_end: INDX_NODE_HEADER_OFFSET + node.index_length,
+            allocated_end: INDX_NODE_HEADER_OFFSET + node.allocated_size,
+            data: block,
+        })
+    }
+}
+
+// Define a struct to hold every file name a directory's $I30 index lists
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DirectoryIndex {
+    pub entries: Vec<IndexEntry>,
+    // Entries carved from the slack of INDX blocks, naming files removed from the directory
+    pub slack_entries: Vec<SlackEntry>,
+    // Blocks of $INDEX_ALLOCATION marked in use but unreadable, by position in the allocation
+    pub damaged_blocks: Vec<u64>,
+}
+
+impl DirectoryIndex {
+    // Read the $I30 index of a directory, returning None for files without one
+    pub fn read(file: &LogicalFile, reader: &mut MftReader) -> Result<Option<Self>> {
+        let root = match file.attribute_fragments(ATTRIBUTE_TYPE_INDEX_ROOT, I30_INDEX_NAME).next() {
+            Some(root) => root.resident_value()
+                .with_context(|| "$INDEX_ROOT attribute is not resident")?,
+            None => return Ok(None),
+        };
+
+        // Only the first fragment carries the size of the allocation
+        let allocation_size = file.attribute_fragments(ATTRIBUTE_TYPE_INDEX_ALLOCATION, I30_INDEX_NAME)
+            .find_map(|attribute| match &attribute.form {
+                AttributeForm::NonResident { starting_vcn: 0, real_size, .. } => Some(*real_size),
+                _ => None,
+            });
+        let allocation = match (file.runlist(ATTRIBUTE_TYPE_INDEX_ALLOCATION, I30_INDEX_NAME)?, allocation_size) {
+            (Some(runlist), Some(size)) => Some(reader.read_attribute_data(&runlist, size)
+                .with_context(|| format!("Failed to read $INDEX_ALLOCATION of MFT entry {}", file.record_number()))?),
+            _ => None,
+        };
+
+        // Large directories outgrow a resident $BITMAP, and then it is read like the allocation
+        let bitmap = file.attribute_fragments(ATTRIBUTE_TYPE_BITMAP, I30_INDEX_NAME)
+            .find(|fragment| fragment.starting_vcn() == 0);
+        let bitmap = match bitmap.map(|bitmap| &bitmap.form) {
+            Some(AttributeForm::Resident { value, .. }) => Some(value.clone()),
+            Some(AttributeForm::NonResident { real_size, .. }) => {
+                let runlist = file.runlist(ATTRIBUTE_TYPE_BITMAP, I30_INDEX_NAME)?.unwrap_or_default();
+                Some(reader.read_attribute_data(&runlist, *real_size)
+                    .with_context(|| format!("Failed to read $BITMAP of MFT entry {}", file.record_number()))?)
+            }
+            None => None,
+        };
+
+        let directory = FileReference {
+            record_number: file.record_number(),
+            sequence_number: file.base.sequence_number,
+        };
+        DirectoryIndex::parse(directory, root, allocation.as_deref(), bitmap.as_deref())
+            .with_context(|| format!("Failed to parse the $I30 index of MFT entry {}", file.record_number()))
+            .map(Some)
+    }
+
+    // Collect the entries of the root node and of every INDX block in use. Blocks are read in
+    // order rather than by walking the tree, so a damaged node does not hide the nodes below it.
+    // The slack of blocks in use and the whole of blocks no longer in use are carved for the
+    // entries of removed files.
+    pub fn parse(directory: FileReference, root: &[u8], allocation: Option<&[u8]>, bitmap: Option<&[u8]>) -> Result<Self> {
+        let root = IndexRoot::parse(root)?;
+        let mut index = DirectoryIndex {
+            entries: root.entries,
+            slack_entries: Vec::new(),
+            damaged_blocks: Vec::new(),
+        };
+
+        let allocation = match allocation {
+            Some(allocation) => allocation,
+            None => return Ok(index),
+        };
+        if root.index_block_size == 0 {
+            bail!("$INDEX_ROOT has an index block size of zero");
+        }
+
+        for (block_number, block) in allocation.chunks_exact(root.index_block_size as usize).enumerate() {
+            let block_number = block_number as u64;
+            // Without a $BITMAP every block is assumed in use
+            let in_use = bitmap.is_none_or(|bitmap| is_block_in_use(bitmap, block_number));
+
+            let slack = match IndexRecord::parse(block) {
+                Ok(record) if in_use => {
+                    index.entries.extend(record.entries);
+                    carve_slack(&record.data, record.entries_end, record.allocated_end, directory.record_number)
+                }
+                Ok(record) => carve_slack(&record.data, record.entries_start, record.allocated_end, directory.record_number),
+                Err(_) if in_use => {
+                    index.damaged_blocks.push(block_number);
+                    continue;
+                }
+                // A stale block may have been partly overwritten, so carve it with whatever
+                // sector trailers can still be restored
+                Err(_) => {
+                    let mut block = block.to_vec();
+                    apply_fixups_best_effort(&mut block);
+                    carve_slack(&block, INDX_NODE_HEADER_OFFSET + NODE_HEADER_SIZE, block.len(), directory.record_number)
+                }
+            };
+            index.slack_entries.extend(slack.into_iter().map(|entry| SlackEntry { block_number, ..entry }));
+        }
+
+        // Removing an entry shifts the ones after it, leaving copies of live entries in the slack.
+        // Copies that lost their entry header are matched by name and parent instead.
+        let live: HashSet<(FileReference, &str)> = index.entries.iter()
+            .map(|entry| (entry.file_reference, entry.file_name.name.as_str()))
+            .collect();
+        let live_names: HashSet<(FileReference, &str)> = index.entries.iter()
+            .map(|entry| (entry.file_name.parent, entry.file_name.name.as_str()))
+            .collect();
+        let slack_entries = std::mem::take(&mut index.slack_entries);
+        index.slack_entries = slack_entries.into_iter()
+            .filter(|entry| {
+                let name = entry.file_name.name.as_str();
+                match entry.file_reference {
+                    Some(reference) => !live.contains(&(reference, name)),
+                    None => !live_names.contains(&(entry.file_name.parent, name)),
+                }
+            })
+            .collect();
+
+        Ok(index)
+    }
+}
+
+fn is_block_in_use(bitmap: &[u8], block_number: u64) -> bool {
+    bitmap.get((block_number / 8) as usize)
+        .is_some_and(|byte| byte & (1 << (block_number % 8)) != 0)
+}
+
+// Define a struct to hold the entries of an index node and where they lie, relative to the
+// start of its node header
+struct IndexNode {
+    entries: Vec<IndexEntry>,
+    entries_offset: usize,
+    index_length: usize,
+    allocated_size: usize,
+}
+
+// Parse the entries of an index node, starting at its node header. The final entry carries
+// no key and only marks the end of the node.
+fn parse_node(node: &[u8]) -> Result<IndexNode> {
+    let entries_offset = LittleEndian::read_u32(&node[NODE_ENTRIES_OFFSET_OFFSET..]) as usize;
+    let index_length = LittleEndian::read_u32(&node[NODE_INDEX_LENGTH_OFFSET..]) as usize;
+    let allocated_size = LittleEndian::read_u32(&node[NODE_ALLOCATED_SIZE_OFFSET..]) as usize;
+    if entries_offset < NODE_HEADER_SIZE || entries_offset > index_length || index_length > allocated_size || allocated_size > node.len() {
+        bail!(
+            "Invalid index node (entries at {}, length {}, allocated {}, {} bytes available)",
+            entries_offset, index_length, allocated_size, node.len()
+        );
+    }
+
+    let mut entries = Vec::new();
+    let mut offset = entries_offset;
+    loop {
+        if offset + ENTRY_KEY_OFFSET > index_length {
+            bail!("Index entry at offset {} runs past the end of the node", offset);
+        }
+        let entry = &node[offset..index_length];
+        let entry_length = usize::from(LittleEndian::read_u16(&entry[ENTRY_LENGTH_OFFSET..]));
+        let key_length = usize::from(LittleEndian::read_u16(&entry[ENTRY_KEY_LENGTH_OFFSET..]));
+        let flags = LittleEndian::read_u16(&entry[ENTRY_FLAGS_OFFSET..]);
+        if entry_length < ENTRY_KEY_OFFSET || entry_length > entry.len() {
+            bail!("Index entry at offset {} has an invalid length {}", offset, entry_length);
+        }
+
+        if flags & ENTRY_FLAG_LAST != 0 {
+            break;
+        }
+
+        // Entries pointing at a subnode end with its VCN, which the key must not overlap
+        let key_end = ENTRY_KEY_OFFSET + key_length;
+        let subnode_size = if flags & ENTRY_FLAG_HAS_SUBNODE != 0 { 8 } else { 0 };
+        if key_end + subnode_size > entry_length {
+            bail!("Key of index entry at offset {} runs past the end of the entry", offset);
+        }
+
+        entries.push(IndexEntry {
+            file_reference: FileReference::from_u64(LittleEndian::read_u64(&entry[ENTRY_FILE_REFERENCE_OFFSET..])),
+            file_name: FileName::parse(&entry[ENTRY_KEY_OFFSET..key_end])
+                .with_context(|| format!("Failed to parse the key of index entry at offset {}", offset))?,
+        });
+
+        offset += entry_length;
+    }
+
+    Ok(IndexNode {
+        entries,
+        entries_offset,
+        index_length,
+        allocated_size,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{fake_file_name_value, fake_index_entry, fake_index_record, fake_index_root_value};
+
+    fn names(index: &DirectoryIndex) -> Vec<&str> {
+        index.entries.iter().map(|entry| entry.file_name.name.as_str()).collect()
+    }
+
+    #[test]
+    fn test_index_root_parsing() {
+        let directory = FileReference { record_number: 5, sequence_number: 5 };
+        let entries = [
+            fake_index_entry(FileReference { record_number: 64, sequence_number: 2 }, &fake_file_name_value(directory, "boot.ini", 3)),
+            fake_index_entry(FileReference { record_number: 65, sequence_number: 1 }, &fake_file_name_value(directory, "Windows", 1)),
+        ];
+
+        let root = IndexRoot::parse(&fake_index_root_value(&entries, 4096, false)).unwrap();
+        assert_eq!(root.index_block_size, 4096);
+        assert!(!root.large_index);
+        assert_eq!(root.entries.len(), 2);
+        assert_eq!(root.entries[0].file_reference, FileReference { record_number: 64, sequence_number: 2 });
+        assert_eq!(root.entries[1].file_name.name, "Windows");
+        assert_eq!(root.entries[1].file_name.parent, directory);
+
+        // An entry claiming to run past the node
+        let mut value = fake_index_root_value(&entries, 4096, false);
+        LittleEndian::write_u16(&mut value[ROOT_NODE_HEADER_OFFSET + NODE_HEADER_SIZE + ENTRY_LENGTH_OFFSET..], 0x400);
+        assert!(IndexRoot::parse(&value).is_err());
+    }
+
+    #[test]
+    fn test_index_allocation_blocks() {
+        let directory = FileReference { record_number: 5, sequence_number: 5 };
+        let entry = |record_number: u64, name: &str| {
+            fake_index_entry(FileReference { record_number, sequence_number: 1 }, &fake_file_name_value(directory, name, 1))
+        };
+
+        let root = fake_index_root_value(&[entry(70, "middle")], 1024, true);
+        let mut allocation = fake_index_record(0, &[entry(71, "apple"), entry(72, "banana")], 1024);
+        // A block left over from a shrunk index, no longer in use
+        allocation.extend(fake_index_record(2, &[entry(73, "stale")], 1024));
+        let mut torn = fake_index_record(4, &[entry(74, "zebra")], 1024);
+        torn[1022] = 0xFF;
+        allocation.extend(torn);
+
+        let index = DirectoryIndex::parse(directory, &root, Some(&allocation), Some(&[0b101])).unwrap();
+        assert_eq!(names(&index), vec!["middle", "apple", "banana"]);
+        assert_eq!(index.damaged_blocks, vec![2]);
+
+        // Without a bitmap every block is read
+        let index = DirectoryIndex::parse(directory, &root, Some(&allocation), None).unwrap();
+        assert_eq!(names(&index), vec!["middle", "apple", "banana", "stale"]);
+    }
+}
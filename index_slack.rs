@@ -0,0 +1,147 @@
+use crate::file_name::FileName;
+use crate::index::{ENTRY_FILE_REFERENCE_OFFSET, ENTRY_KEY_LENGTH_OFFSET, ENTRY_KEY_OFFSET};
+use crate::mft_parser::FileReference;
+use byteorder::{ByteOrder, LittleEndian};
+use chrono::Datelike;
+
+// Index entries, and so their keys, are aligned to 8 bytes within a block
+const ENTRY_ALIGNMENT: usize = 8;
+
+// Timestamps outside these years are taken as a sign of random bytes rather than a name
+const MIN_PLAUSIBLE_YEAR: i32 = 1980;
+const MAX_PLAUSIBLE_YEAR: i32 = 2100;
+
+// Define a struct to hold a $FILE_NAME index key recovered from the slack of an INDX block
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SlackEntry {
+    // The file the entry pointed at, when the entry header survived along with the key
+    pub file_reference: Option<FileReference>,
+    pub file_name: FileName,
+    // Position of the block in $INDEX_ALLOCATION and of the key within the block
+    pub block_number: u64,
+    pub offset: usize,
+}
+
+// Carve the index keys left between `start` and `end` of an INDX block by a directory. Keys are
+// looked for at every aligned offset and kept when they name a child of the directory with
+// a clean name and believable timestamps. The block number is left for the caller to fill in.
+pub fn carve_slack(block: &[u8], start: usize, end: usize, directory_record_number: u64) -> Vec<SlackEntry> {
+    let mut entries = Vec::new();
+    let end = end.min(block.len());
+    let mut offset = start.next_multiple_of(ENTRY_ALIGNMENT);
+
+    while offset < end {
+        let file_name = match plausible_file_name(&block[offset..end], directory_record_number) {
+            Some(file_name) => file_name,
+            None => {
+                offset += ENTRY_ALIGNMENT;
+                continue;
+            }
+        };
+
+        // The entry header is only trusted when it lies in the slack and agrees on the key size
+        let key_length = file_name.value_length();
+        let file_reference = offset.checked_sub(ENTRY_KEY_OFFSET)
+            .filter(|&header| header >= start)
+            .map(|header| &block[header..offset])
+            .filter(|header| usize::from(LittleEndian::read_u16(&header[ENTRY_KEY_LENGTH_OFFSET..])) == key_length)
+            .map(|header| FileReference::from_u64(LittleEndian::read_u64(&header[ENTRY_FILE_REFERENCE_OFFSET..])));
+
+        entries.push(SlackEntry {
+            file_reference,
+            file_name,
+            block_number: 0,
+            offset,
+        });
+        offset += key_length.next_multiple_of(ENTRY_ALIGNMENT);
+    }
+
+    entries
+}
+
+fn plausible_file_name(value: &[u8], directory_record_number: u64) -> Option<FileName> {
+    let file_name = FileName::parse(value).ok()?;
+
+    // Every key of a $I30 index names a child of the directory owning it
+    if file_name.parent.record_number != directory_record_number || file_name.name.is_empty() {
+        return None;
+    }
+    if file_name.name.chars().any(|c| c.is_control() || c == char::REPLACEMENT_CHARACTER || c == '/' || c == '\\') {
+        return None;
+    }
+
+    let timestamps = [file_name.created, file_name.modified, file_name.mft_modified, file_name.accessed];
+    if timestamps.iter().any(|timestamp| !(MIN_PLAUSIBLE_YEAR..=MAX_PLAUSIBLE_YEAR).contains(&timestamp.year())) {
+        return None;
+    }
+
+    Some(file_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::DirectoryIndex;
+    use crate::test_support::{fake_file_name_value, fake_index_entry, fake_index_record, fake_index_root_value, fake_stamped_file_name};
+
+    // 2023-05-01T12:00:00Z as a FILETIME
+    const FILETIME: u64 = 133_273_584_000_000_000;
+
+    #[test]
+    fn test_slack_carving() {
+        let directory = FileReference { record_number: 40, sequence_number: 3 };
+        let deleted = FileReference { record_number: 91, sequence_number: 4 };
+        let mut block = vec![0; 512];
+        // An intact entry, then a key whose header was overwritten, then one of another directory
+        let intact = fake_index_entry(deleted, &fake_stamped_file_name(directory, "secret.docx", FILETIME));
+        block[0x40..0x40 + intact.len()].copy_from_slice(&intact);
+        let orphan_key = fake_stamped_file_name(directory, "payload.exe", FILETIME);
+        block[0x108..0x108 + orphan_key.len()].copy_from_slice(&orphan_key);
+        let foreign_key = fake_stamped_file_name(FileReference { record_number: 41, sequence_number: 1 }, "other.txt", FILETIME);
+        block[0x180..0x180 + foreign_key.len()].copy_from_slice(&foreign_key);
+
+        let entries = carve_slack(&block, 0x40, 0x200, 40);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].file_reference, Some(deleted));
+        assert_eq!(entries[0].file_name.name, "secret.docx");
+        assert_eq!(entries[0].offset, 0x50);
+        assert_eq!(entries[1].file_reference, None);
+        assert_eq!(entries[1].file_name.name, "payload.exe");
+
+        // A name without believable timestamps is not carved
+        let unstamped = fake_file_name_value(directory, "noise", 1);
+        block[0x108..0x108 + unstamped.len()].copy_from_slice(&unstamped);
+        assert_eq!(carve_slack(&block, 0x40, 0x200, 40).len(), 1);
+    }
+
+    #[test]
+    fn test_directory_index_slack() {
+        let directory = FileReference { record_number: 40, sequence_number: 3 };
+        let entry = |record_number: u64, name: &str| {
+            fake_index_entry(FileReference { record_number, sequence_number: 1 }, &fake_stamped_file_name(directory, name, FILETIME))
+        };
+
+        // Deleting "beta" shifted "gamma" over it, leaving a copy of "gamma" in the slack, and
+        // an older copy of "alpha" survives without its entry header
+        let root = fake_index_root_value(&[], 1024, true);
+        let mut block = fake_index_record(0, &[entry(70, "alpha"), entry(72, "gamma")], 1024);
+        let stale = [entry(72, "gamma"), entry(73, "delta"), fake_stamped_file_name(directory, "alpha", FILETIME)].concat();
+        let slack_start = 0x200;
+        block[slack_start..slack_start + stale.len()].copy_from_slice(&stale);
+        // A block no longer in use still holds the entries it had
+        let mut allocation = block;
+        allocation.extend(fake_index_record(2, &[entry(74, "epsilon")], 1024));
+        // One whose second sector was overwritten, with a name across the end of the first
+        let long_name = "x".repeat(140);
+        let mut torn = fake_index_record(4, &[entry(75, &long_name), entry(76, "recovered.txt")], 1024);
+        torn[1022..1024].copy_from_slice(&[0xFF, 0xFF]);
+        allocation.extend(torn);
+
+        let index = DirectoryIndex::parse(directory, &root, Some(&allocation), Some(&[0b001])).unwrap();
+        assert_eq!(index.entries.len(), 2);
+        let slack: Vec<(&str, u64)> = index.slack_entries.iter()
+            .map(|entry| (entry.file_name.name.as_str(), entry.block_number))
+            .collect();
+        assert_eq!(slack, vec![("delta", 0), ("epsilon", 1), (long_name.as_str(), 2), ("recovered.txt", 2)]);
+    }
+}
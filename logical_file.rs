@@ -0,0 +1,425 @@
+use crate::attribute::{
+    decode_utf16, Attribute, AttributeForm, ATTRIBUTE_TYPE_ATTRIBUTE_LIST, ATTRIBUTE_TYPE_DATA,
+    ATTRIBUTE_TYPE_FILE_NAME, ATTRIBUTE_TYPE_REPARSE_POINT, ATTRIBUTE_TYPE_STANDARD_INFORMATION,
+};
+use crate::file_name::FileName;
+use crate::mft_parser::{FileReference, MftEntry, RecordSignature};
+use crate::mft_reader::MftReader;
+use crate::reparse_point::ReparsePoint;
+use crate::runlist::Runlist;
+use crate::standard_information::StandardInformation;
+use crate::wof::WofCompression;
+use anyhow::{bail, Context, Result};
+use byteorder::{ByteOrder, LittleEndian};
+use std::collections::HashMap;
+
+// Define constants for $ATTRIBUTE_LIST entry offsets and sizes
+const LIST_TYPE_OFFSET: usize = 0x00;
+const LIST_RECORD_LENGTH_OFFSET: usize = 0x04;
+const LIST_NAME_LENGTH_OFFSET: usize = 0x06;
+const LIST_NAME_OFFSET_OFFSET: usize = 0x07;
+const LIST_STARTING_VCN_OFFSET: usize = 0x08;
+const LIST_FILE_REFERENCE_OFFSET: usize = 0x10;
+const LIST_ATTRIBUTE_ID_OFFSET: usize = 0x18;
+const LIST_ENTRY_MIN_SIZE: usize = 0x1A;
+
+// Define a struct to hold a single $ATTRIBUTE_LIST entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttributeListEntry {
+    pub type_code: u32,
+    pub name: String,
+    pub starting_vcn: u64,
+    // The record holding the attribute, either the base record or an extension record
+    pub file_reference: FileReference,
+    pub attribute_id: u16,
+}
+
+pub fn parse_attribute_list(data: &[u8]) -> Result<Vec<AttributeListEntry>> {
+    let mut entries = Vec::new();
+    let mut offset = 0;
+
+    while offset + LIST_ENTRY_MIN_SIZE <= data.len() {
+        let entry = &data[offset..];
+        let record_length = usize::from(LittleEndian::read_u16(&entry[LIST_RECORD_LENGTH_OFFSET..]));
+        if record_length < LIST_ENTRY_MIN_SIZE || offset + record_length > data.len() {
+            bail!("$ATTRIBUTE_LIST entry at offset {} has an invalid length {}", offset, record_length);
+        }
+        let entry = &entry[..record_length];
+
+        let name_length = usize::from(entry[LIST_NAME_LENGTH_OFFSET]);
+        let name_offset = usize::from(entry[LIST_NAME_OFFSET_OFFSET]);
+        if name_offset + name_length * 2 > record_length {
+            bail!("Name of $ATTRIBUTE_LIST entry at offset {} runs past the entry", offset);
+        }
+
+        entries.push(AttributeListEntry {
+            type_code: LittleEndian::read_u32(&entry[LIST_TYPE_OFFSET..]),
+            name: decode_utf16(&entry[name_offset..name_offset + name_length * 2]),
+            starting_vcn: LittleEndian::read_u64(&entry[LIST_STARTING_VCN_OFFSET..]),
+            file_reference: FileReference::from_u64(LittleEndian::read_u64(&entry[LIST_FILE_REFERENCE_OFFSET..])),
+            attribute_id: LittleEndian::read_u16(&entry[LIST_ATTRIBUTE_ID_OFFSET..]),
+        });
+
+        offset += record_length;
+    }
+
+    Ok(entries)
+}
+
+// Define a struct to hold a summary of one $DATA stream of a file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataStream {
+    // Empty for the unnamed (default) stream, otherwise an alternate data stream
+    pub name: String,
+    pub size: u64,
+    pub resident: bool,
+    pub allocated_clusters: u64,
+}
+
+impl DataStream {
+    pub fn is_alternate(&self) -> bool {
+        !self.name.is_empty()
+    }
+}
+
+// A record from a damaged or misplaced $MFT can give itself another number than the slot it was
+// read from. Records written by NTFS 3.0 don't give one, and are taken as they are.
+fn matches_slot(entry: &MftEntry, record_number: u64) -> bool {
+    entry.record_number.unwrap_or(record_number) == record_number
+}
+
+// Define a struct to hold a file whose attributes may be spread over several MFT records
+#[derive(Debug, Clone)]
+pub struct LogicalFile {
+    // The slot the base record was read from
+    record_number: u64,
+    pub base: MftEntry,
+    pub extension_records: Vec<u64>,
+    // Attributes from every record, ordered by type, name and starting VCN
+    pub attributes: Vec<Attribute>,
+}
+
+impl LogicalFile {
+    // Merge a base record with its extension records, each given with the slot it was read
+    // from. Extension records left behind by an earlier file in the same slot point at another
+    // sequence number, and are dropped, as are records whose header names another slot.
+    pub fn from_records(record_number: u64, base: MftEntry, extensions: Vec<(u64, MftEntry)>) -> Result<Self> {
+        let base_reference = FileReference {
+            record_number,
+            sequence_number: base.sequence_number,
+        };
+
+        let mut attributes = base.attributes().collect::<Result<Vec<_>>>()
+            .with_context(|| format!("Failed to read attributes of MFT entry {}", record_number))?;
+        let mut extension_records = Vec::new();
+
+        for (extension_number, extension) in extensions {
+            if extension.base_record != base_reference || !matches_slot(&extension, extension_number) {
+                continue;
+            }
+
+            for attribute in extension.attributes() {
+                attributes.push(attribute
+                    .with_context(|| format!("Failed to read attributes of MFT entry {}", extension_number))?);
+            }
+            extension_records.push(extension_number);
+        }
+
+        // Keep fragments of the same attribute together and in VCN order
+        attributes.sort_by(|a, b| {
+            (a.type_code, &a.name, a.starting_vcn()).cmp(&(b.type_code, &b.name, b.starting_vcn()))
+        });
+
+        Ok(LogicalFile {
+            record_number,
+            base,
+            extension_records,
+            attributes,
+        })
+    }
+
+    // Read the $ATTRIBUTE_LIST of a base record, resident or not, and fetch the extension
+    // records it references through the reader. `record_number` is the slot the base was read from.
+    pub fn resolve(record_number: u64, base: MftEntry, reader: &mut MftReader) -> Result<Self> {
+        let mut attribute_list = None;
+        for attribute in base.attributes() {
+            let attribute = attribute?;
+            if attribute.type_code == ATTRIBUTE_TYPE_ATTRIBUTE_LIST {
+                attribute_list = Some(attribute);
+                break;
+            }
+        }
+
+        let attribute_list = match attribute_list {
+            Some(attribute_list) => attribute_list,
+            None => return LogicalFile::from_records(record_number, base, Vec::new()),
+        };
+
+        let list_data = match &attribute_list.form {
+            AttributeForm::Resident { value, .. } => value.clone(),
+            AttributeForm::NonResident { real_size, .. } => {
+                let runlist = attribute_list.runlist()?.unwrap_or_default();
+                reader.read_attribute_data(&runlist, *real_size)
+                    .with_context(|| "Failed to read non-resident $ATTRIBUTE_LIST")?
+            }
+        };
+
+        let mut extension_numbers: Vec<u64> = parse_attribute_list(&list_data)?
+            .iter()
+            .map(|entry| entry.file_reference.record_number)
+            .filter(|&extension_number| extension_number != record_number)
+            .collect();
+        extension_numbers.sort_unstable();
+        extension_numbers.dedup();
+
+        let mut extensions = Vec::new();
+        for extension_number in extension_numbers {
+            let entry_data = reader.read_mft_entry(extension_number)?;
+            let extension = MftEntry::parse(&entry_data)
+                .with_context(|| format!("Failed to parse extension record {}", extension_number))?;
+            extensions.push((extension_number, extension));
+        }
+
+        LogicalFile::from_records(record_number, base, extensions)
+    }
+
+    // Group an in-memory list of MFT entries, each paired with its slot, into logical files,
+    // attaching extension records to the base record they reference, sequence number included.
+    // Unused record slots and orphaned or stale extension records are dropped.
+    pub fn group_entries(entries: Vec<(u64, MftEntry)>) -> Result<Vec<Self>> {
+        let mut bases = Vec::new();
+        let mut extensions: HashMap<FileReference, Vec<(u64, MftEntry)>> = HashMap::new();
+
+        for (record_number, entry) in entries {
+            if entry.signature != RecordSignature::File {
+                continue;
+            }
+
+            if entry.is_base_record() {
+                bases.push((record_number, entry));
+            } else {
+                extensions.entry(entry.base_record).or_default().push((record_number, entry));
+            }
+        }
+
+        bases.into_iter()
+            .map(|(record_number, base)| {
+                let base_reference = FileReference {
+                    record_number,
+                    sequence_number: base.sequence_number,
+                };
+                let base_extensions = extensions.remove(&base_reference).unwrap_or_default();
+                LogicalFile::from_records(record_number, base, base_extensions)
+            })
+            .collect()
+    }
+
+    pub fn record_number(&self) -> u64 {
+        self.record_number
+    }
+
+    pub fn standard_information(&self) -> Result<Option<StandardInformation>> {
+        self.attributes.iter()
+            .find(|attribute| attribute.type_code == ATTRIBUTE_TYPE_STANDARD_INFORMATION)
+            .map(StandardInformation::from_attribute)
+            .transpose()
+    }
+
+    pub fn file_names(&self) -> Result<Vec<FileName>> {
+        self.attributes.iter()
+            .filter(|attribute| attribute.type_code == ATTRIBUTE_TYPE_FILE_NAME)
+            .map(FileName::from_attribute)
+            .collect()
+    }
+
+    // The raw data of the $REPARSE_POINT attribute. Reparse data is almost always small enough
+    // to be resident, so a non-resident attribute is treated as absent.
+    pub fn reparse_data(&self) -> Option<&[u8]> {
+        self.attribute_fragments(ATTRIBUTE_TYPE_REPARSE_POINT, "")
+            .next()
+            .and_then(Attribute::resident_value)
+    }
+
+    pub fn reparse_point(&self) -> Result<Option<ReparsePoint>> {
+        self.reparse_data().map(ReparsePoint::parse).transpose()
+    }
+
+    // How a file compressed by the Windows Overlay Filter, such as by CompactOS, is compressed
+    pub fn wof_compression(&self) -> Result<Option<WofCompression>> {
+        match self.reparse_data() {
+            Some(data) => WofCompression::from_reparse_data(data),
+            None => Ok(None),
+        }
+    }
+
+    // Summarize the unnamed and named $DATA streams. Only the first fragment of a non-resident
+    // stream carries its sizes, while the allocated clusters come from every fragment.
+    pub fn data_streams(&self) -> Result<Vec<DataStream>> {
+        let mut streams = Vec::new();
+
+        for attribute in &self.attributes {
+            if attribute.type_code != ATTRIBUTE_TYPE_DATA || attribute.starting_vcn() != 0 {
+                continue;
+            }
+
+            let stream = match &attribute.form {
+                AttributeForm::Resident { value_length, .. } => DataStream {
+                    name: attribute.name.clone(),
+                    size: u64::from(*value_length),
+                    resident: true,
+                    allocated_clusters: 0,
+                },
+                AttributeForm::NonResident { real_size, .. } => DataStream {
+                    name: attribute.name.clone(),
+                    size: *real_size,
+                    resident: false,
+                    allocated_clusters: self.runlist(ATTRIBUTE_TYPE_DATA, &attribute.name)?
+                        .map_or(0, |runlist| runlist.allocated_clusters()),
+                },
+            };
+            streams.push(stream);
+        }
+
+        Ok(streams)
+    }
+
+    // Every fragment of the named attribute, in VCN order
+    pub fn attribute_fragments<'a>(&'a self, type_code: u32, name: &'a str) -> impl Iterator<Item = &'a Attribute> + 'a {
+        self.attributes.iter()
+            .filter(move |attribute| attribute.type_code == type_code && attribute.name == name)
+    }
+
+    // Stitch the runlists of every fragment of a non-resident attribute together
+    pub fn runlist(&self, type_code: u32, name: &str) -> Result<Option<Runlist>> {
+        let mut stitched: Option<Runlist> = None;
+
+        for fragment in self.attribute_fragments(type_code, name) {
+            let runlist = match fragment.runlist()? {
+                Some(runlist) => runlist,
+                None => continue,
+            };
+
+            match stitched.as_mut() {
+                Some(stitched) => stitched.extend(runlist)
+                    .with_context(|| format!("Attribute {:#x} of MFT entry {} has a gap in its runlist", type_code, self.record_number()))?,
+                None => stitched = Some(runlist),
+            }
+        }
+
+        Ok(stitched)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::attribute::ATTRIBUTE_TYPE_DATA;
+    use crate::mft_parser::FILE_RECORD_IN_USE;
+    use crate::test_support::{fake_file_record, fake_non_resident_attribute, fake_resident_attribute};
+    use crate::runlist::DataRun;
+
+    #[test]
+    fn test_attribute_list_parsing() {
+        let mut data = vec![0; 0x20];
+        LittleEndian::write_u32(&mut data[LIST_TYPE_OFFSET..], ATTRIBUTE_TYPE_DATA);
+        LittleEndian::write_u16(&mut data[LIST_RECORD_LENGTH_OFFSET..], 0x20);
+        LittleEndian::write_u64(&mut data[LIST_STARTING_VCN_OFFSET..], 0x100);
+        LittleEndian::write_u64(&mut data[LIST_FILE_REFERENCE_OFFSET..], 0x0002_0000_0000_0030);
+        LittleEndian::write_u16(&mut data[LIST_ATTRIBUTE_ID_OFFSET..], 3);
+
+        let entries = parse_attribute_list(&data).unwrap();
+        assert_eq!(entries, vec![AttributeListEntry {
+            type_code: ATTRIBUTE_TYPE_DATA,
+            name: String::new(),
+            starting_vcn: 0x100,
+            file_reference: FileReference { record_number: 0x30, sequence_number: 2 },
+            attribute_id: 3,
+        }]);
+    }
+
+    #[test]
+    fn test_extension_records_stitched_in_vcn_order() {
+        // The base record holds VCNs 0-15 of $DATA, the extension record VCNs 16-23
+        let mut first_fragment = fake_non_resident_attribute(ATTRIBUTE_TYPE_DATA, "", &[0x11, 0x10, 0x40], 24 * 4096);
+        let mut second_fragment = fake_non_resident_attribute(ATTRIBUTE_TYPE_DATA, "", &[0x11, 0x08, 0x10], 0);
+        LittleEndian::write_u64(&mut first_fragment[0x18..], 15);
+        LittleEndian::write_u64(&mut second_fragment[0x10..], 16);
+        LittleEndian::write_u64(&mut second_fragment[0x18..], 23);
+
+        let base_attributes = [
+            fake_resident_attribute(ATTRIBUTE_TYPE_ATTRIBUTE_LIST, "", &[]),
+            first_fragment,
+        ].concat();
+        let base = MftEntry::parse(&fake_file_record(40, FILE_RECORD_IN_USE, &base_attributes)).unwrap();
+
+        let mut extension_record = fake_file_record(41, FILE_RECORD_IN_USE, &second_fragment);
+        // Point the extension record back at record 40, sequence 1
+        LittleEndian::write_u64(&mut extension_record[0x20..], 0x0001_0000_0000_0028);
+        let extension = MftEntry::parse(&extension_record).unwrap();
+
+        // Extension records arrive first to check they are grouped regardless of order
+        let files = LogicalFile::group_entries(vec![(41, extension), (40, base)]).unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].record_number(), 40);
+        assert_eq!(files[0].extension_records, vec![41]);
+
+        let runlist = files[0].runlist(ATTRIBUTE_TYPE_DATA, "").unwrap().unwrap();
+        assert_eq!(runlist.runs, vec![
+            DataRun { vcn: 0, cluster_count: 16, lcn: Some(0x40) },
+            DataRun { vcn: 16, cluster_count: 8, lcn: Some(0x10) },
+        ]);
+    }
+
+    #[test]
+    fn test_data_stream_enumeration() {
+        let attributes = [
+            fake_non_resident_attribute(ATTRIBUTE_TYPE_DATA, "", &[0x11, 0x03, 0x40, 0x01, 0x02], 5 * 4096),
+            fake_resident_attribute(ATTRIBUTE_TYPE_DATA, "Zone.Identifier", b"[ZoneTransfer]\r\nZoneId=3\r\n"),
+        ].concat();
+        let base = MftEntry::parse(&fake_file_record(40, FILE_RECORD_IN_USE, &attributes)).unwrap();
+        let file = LogicalFile::from_records(40, base, Vec::new()).unwrap();
+
+        let streams = file.data_streams().unwrap();
+        assert_eq!(streams, vec![
+            DataStream { name: String::new(), size: 5 * 4096, resident: false, allocated_clusters: 3 },
+            DataStream { name: "Zone.Identifier".to_string(), size: 26, resident: true, allocated_clusters: 0 },
+        ]);
+        assert!(streams[1].is_alternate());
+    }
+
+    #[test]
+    fn test_stale_extension_record_dropped() {
+        let base = MftEntry::parse(&fake_file_record(40, FILE_RECORD_IN_USE, &[])).unwrap();
+        let mut extension_record = fake_file_record(41, FILE_RECORD_IN_USE, &fake_resident_attribute(ATTRIBUTE_TYPE_DATA, "", b"old"));
+        // A stale extension record left behind by a previous file using record 40
+        LittleEndian::write_u64(&mut extension_record[0x20..], 0x0007_0000_0000_0028);
+        let extension = MftEntry::parse(&extension_record).unwrap();
+
+        // The volume still groups, with the file missing only the stale attributes
+        let files = LogicalFile::group_entries(vec![(40, base.clone()), (41, extension.clone())]).unwrap();
+        assert_eq!(files.len(), 1);
+        assert!(files[0].extension_records.is_empty());
+        assert!(files[0].data_streams().unwrap().is_empty());
+
+        let file = LogicalFile::from_records(40, base, vec![(41, extension)]).unwrap();
+        assert!(file.extension_records.is_empty());
+    }
+
+    #[test]
+    fn test_records_keyed_by_slot() {
+        // A base record whose header doesn't carry its number is still found by its slot
+        let base = MftEntry::parse(&fake_file_record(0, FILE_RECORD_IN_USE, &[])).unwrap();
+        let mut extension_record = fake_file_record(41, FILE_RECORD_IN_USE, &fake_resident_attribute(ATTRIBUTE_TYPE_DATA, "", b"new"));
+        LittleEndian::write_u64(&mut extension_record[0x20..], 0x0001_0000_0000_0028);
+        let extension = MftEntry::parse(&extension_record).unwrap();
+
+        let files = LogicalFile::group_entries(vec![(40, base.clone()), (41, extension.clone())]).unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].record_number(), 40);
+        assert_eq!(files[0].extension_records, vec![41]);
+
+        // An extension record claiming another slot than the one it was read from is not trusted
+        let file = LogicalFile::from_records(40, base, vec![(42, extension)]).unwrap();
+        assert!(file.extension_records.is_empty());
+    }
+}
@@ -0,0 +1,161 @@
+use anyhow::{bail, Result};
+use byteorder::{ByteOrder, LittleEndian};
+
+// Every chunk decompresses to at most 4096 bytes
+pub const LZNT1_CHUNK_SIZE: usize = 4096;
+
+// Define masks for the chunk header fields
+const CHUNK_SIZE_MASK: u16 = 0x0FFF;
+const CHUNK_COMPRESSED_FLAG: u16 = 0x8000;
+
+// Decompress LZNT1 data, the format NTFS uses for each compression unit. The input is a series
+// of chunks, each with a 16-bit header giving its size and whether it is compressed, ending at a
+// zero header or at the end of the input. Every chunk stands for 4096 bytes of output, so one
+// that decodes to less is followed by zeros up to the next chunk. Output is cut off at
+// `output_size` bytes.
+pub fn decompress(input: &[u8], output_size: usize) -> Result<Vec<u8>> {
+    let mut output = Vec::with_capacity(output_size);
+    let mut offset = 0;
+
+    while offset + 2 <= input.len() && output.len() < output_size {
+        let header = LittleEndian::read_u16(&input[offset..]);
+        if header == 0 {
+            break;
+        }
+
+        let chunk_length = usize::from(header & CHUNK_SIZE_MASK) + 1;
+        let chunk_start = offset + 2;
+        if chunk_start + chunk_length > input.len() {
+            bail!("LZNT1 chunk at offset {} runs past the end of the input", offset);
+        }
+        let chunk = &input[chunk_start..chunk_start + chunk_length];
+        output.resize(output.len().next_multiple_of(LZNT1_CHUNK_SIZE), 0);
+
+        if header & CHUNK_COMPRESSED_FLAG != 0 {
+            decompress_chunk(chunk, &mut output)
+                .map_err(|e| e.context(format!("Invalid LZNT1 chunk at offset {}", offset)))?;
+        } else {
+            output.extend_from_slice(chunk);
+        }
+
+        offset = chunk_start + chunk_length;
+    }
+
+    output.truncate(output_size);
+    Ok(output)
+}
+
+// Decompress a single chunk. Each flag byte describes the next eight tokens: a clear bit is a
+// literal byte, a set bit a 16-bit back-reference whose split between displacement and length
+// widens the displacement as the chunk grows.
+fn decompress_chunk(chunk: &[u8], output: &mut Vec<u8>) -> Result<()> {
+    let chunk_start = output.len();
+    let mut offset = 0;
+
+    while offset < chunk.len() {
+        let flags = chunk[offset];
+        offset += 1;
+
+        for bit in 0..8 {
+            if offset >= chunk.len() {
+                break;
+            }
+
+            if flags & (1 << bit) == 0 {
+                output.push(chunk[offset]);
+                offset += 1;
+                continue;
+            }
+
+            if offset + 2 > chunk.len() {
+                bail!("Back-reference at offset {} is truncated", offset);
+            }
+            let token = LittleEndian::read_u16(&chunk[offset..]);
+            offset += 2;
+
+            let position = output.len() - chunk_start;
+            if position == 0 {
+                bail!("Back-reference at offset {} precedes any output", offset - 2);
+            }
+            let (length_mask, displacement_shift) = token_split(position);
+            let displacement = usize::from(token >> displacement_shift) + 1;
+            let length = usize::from(token & length_mask) + 3;
+            if displacement > position {
+                bail!("Back-reference displacement {} exceeds the {} bytes decompressed so far", displacement, position);
+            }
+
+            // Copy byte by byte, as the source may overlap the bytes being written
+            let source = output.len() - displacement;
+            for index in 0..length {
+                output.push(output[source + index]);
+            }
+        }
+    }
+
+    if output.len() - chunk_start > LZNT1_CHUNK_SIZE {
+        bail!("Chunk decompresses to {} bytes", output.len() - chunk_start);
+    }
+    Ok(())
+}
+
+// Length mask and displacement shift of a back-reference token at a position within the chunk
+fn token_split(position: usize) -> (u16, u16) {
+    let mut length_mask = 0x0FFF;
+    let mut displacement_shift = 12;
+    let mut threshold = position - 1;
+
+    while threshold >= 0x10 {
+        length_mask >>= 1;
+        displacement_shift -= 1;
+        threshold >>= 1;
+    }
+
+    (length_mask, displacement_shift)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compressed_chunk_decompression() {
+        // "abc" as literals, then a back-reference of 15 bytes at displacement 3
+        let input = [0x05, 0xB0, 0x08, b'a', b'b', b'c', 0x0C, 0x20];
+        assert_eq!(decompress(&input, 4096).unwrap(), b"abcabcabcabcabcabc");
+
+        // 20 literals, then 5 bytes at displacement 20 once the displacement field has widened
+        let mut input = vec![0x18, 0xB0, 0x00];
+        input.extend(b"01234567");
+        input.push(0x00);
+        input.extend(b"89ABCDEF");
+        input.push(0x10);
+        input.extend(b"GHIJ");
+        input.extend([0x02, 0x98]);
+        assert_eq!(decompress(&input, 4096).unwrap(), b"0123456789ABCDEFGHIJ01234");
+    }
+
+    #[test]
+    fn test_uncompressed_chunks_and_errors() {
+        // An uncompressed chunk followed by the end marker
+        let input = [0x02, 0x30, b'x', b'y', b'z', 0x00, 0x00];
+        assert_eq!(decompress(&input, 4096).unwrap(), b"xyz");
+        assert_eq!(decompress(&input, 2).unwrap(), b"xy");
+
+        // A back-reference before any literal
+        assert!(decompress(&[0x02, 0xB0, 0x01, 0x00, 0x00], 4096).is_err());
+        // A chunk longer than the input
+        assert!(decompress(&[0x10, 0xB0, 0x00], 4096).is_err());
+    }
+
+    #[test]
+    fn test_short_chunk_zero_padded() {
+        // Two compressed chunks of three literals each
+        let input = [0x03, 0xB0, 0x00, b'a', b'b', b'c', 0x03, 0xB0, 0x00, b'x', b'y', b'z'];
+        let output = decompress(&input, 8192).unwrap();
+
+        let mut expected = b"abc".to_vec();
+        expected.resize(LZNT1_CHUNK_SIZE, 0);
+        expected.extend(b"xyz");
+        assert_eq!(output, expected);
+    }
+}
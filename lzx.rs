@@ -0,0 +1,398 @@
+use crate::huffman::HuffmanDecoder;
+use anyhow::{bail, Context, Result};
+use byteorder::{ByteOrder, LittleEndian};
+
+// Define constants for the WIM variant of LZX that WOF uses, with a 32 KiB window
+const NUM_CHARS: usize = 256;
+const NUM_OFFSET_SLOTS: usize = 30;
+const MAIN_SYMBOL_COUNT: usize = NUM_CHARS + NUM_OFFSET_SLOTS * 8;
+const LENGTH_SYMBOL_COUNT: usize = 249;
+const PRECODE_SYMBOL_COUNT: usize = 20;
+const ALIGNED_SYMBOL_COUNT: usize = 8;
+const MAX_CODE_LENGTH: u8 = 16;
+const MAX_PRECODE_LENGTH: u8 = 15;
+const MAX_ALIGNED_LENGTH: u8 = 7;
+const NUM_PRIMARY_LENGTHS: u16 = 7;
+const MIN_MATCH_LENGTH: usize = 2;
+const NUM_RECENT_OFFSETS: usize = 3;
+const NUM_ALIGNED_OFFSET_BITS: u8 = 3;
+const DEFAULT_BLOCK_SIZE: usize = 32768;
+
+// Define the block types
+const BLOCK_TYPE_VERBATIM: u32 = 1;
+const BLOCK_TYPE_ALIGNED: u32 = 2;
+const BLOCK_TYPE_UNCOMPRESSED: u32 = 3;
+
+// x86 CALL targets are translated as if the data were part of a file of this size
+const E8_TRANSLATION_SIZE: i32 = 12_000_000;
+
+// Number of extra offset bits for each offset slot
+const EXTRA_OFFSET_BITS: [u8; NUM_OFFSET_SLOTS] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13,
+];
+
+// Define a reader for the LZX bit stream, made of 16-bit little-endian words read most
+// significant bit first. Bits past the end of the input read as zero.
+struct BitReader<'a> {
+    input: &'a [u8],
+    position: usize,
+    buffer: u64,
+    buffered: u8,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(input: &'a [u8]) -> Self {
+        BitReader { input, position: 0, buffer: 0, buffered: 0 }
+    }
+
+    fn ensure_bits(&mut self, count: u8) {
+        while self.buffered < count {
+            let word = self.input.get(self.position..self.position + 2).map_or(0, LittleEndian::read_u16);
+            self.position += 2;
+            self.buffer |= u64::from(word) << (48 - self.buffered);
+            self.buffered += 16;
+        }
+    }
+
+    fn peek_bits(&mut self, count: u8) -> u32 {
+        self.ensure_bits(count);
+        (self.buffer >> (64 - count)) as u32
+    }
+
+    fn remove_bits(&mut self, count: u8) {
+        self.buffer <<= count;
+        self.buffered -= count;
+    }
+
+    fn read_bits(&mut self, count: u8) -> u32 {
+        if count == 0 {
+            return 0;
+        }
+        let bits = self.peek_bits(count);
+        self.remove_bits(count);
+        bits
+    }
+
+    fn read_symbol(&mut self, decoder: &HuffmanDecoder) -> Result<u16> {
+        match decoder.decode(self.peek_bits(decoder.max_length())) {
+            Some((symbol, length)) => {
+                self.remove_bits(length);
+                Ok(symbol)
+            }
+            None => bail!("Invalid LZX codeword at offset {}", self.position),
+        }
+    }
+
+    // Drop the bits left in the current word, so the next read starts on a word boundary. A
+    // stream that is already aligned drops a whole word instead. Words buffered past that one
+    // were read ahead of the stream, and are given back to the input.
+    fn align(&mut self) {
+        self.ensure_bits(1);
+        self.position -= 2 * usize::from((self.buffered - 1) / 16);
+        self.buffer = 0;
+        self.buffered = 0;
+    }
+
+    // Take raw bytes from the input at the current word boundary
+    fn read_bytes(&mut self, count: usize) -> Result<&'a [u8]> {
+        match self.input.get(self.position..self.position + count) {
+            Some(bytes) => {
+                self.position += count;
+                Ok(bytes)
+            }
+            None => bail!("Uncompressed LZX block at offset {} runs past the end of the input", self.position),
+        }
+    }
+}
+
+// Decompress one chunk of WIM-style LZX data, as stored in the WofCompressedData stream
+pub fn decompress(input: &[u8], output_size: usize) -> Result<Vec<u8>> {
+    let mut reader = BitReader::new(input);
+    let mut output = Vec::with_capacity(output_size);
+    let mut recent_offsets = [1usize; NUM_RECENT_OFFSETS];
+    // Code lengths are sent as differences from those of the previous block
+    let mut main_lengths = [0u8; MAIN_SYMBOL_COUNT];
+    let mut length_lengths = [0u8; LENGTH_SYMBOL_COUNT];
+
+    while output.len() < output_size {
+        let block_type = reader.read_bits(3);
+        let block_size = if reader.read_bits(1) == 1 {
+            DEFAULT_BLOCK_SIZE
+        } else {
+            reader.read_bits(16) as usize
+        };
+        let block_end = (output.len() + block_size).min(output_size);
+
+        match block_type {
+            BLOCK_TYPE_VERBATIM | BLOCK_TYPE_ALIGNED => {
+                let aligned_decoder = if block_type == BLOCK_TYPE_ALIGNED {
+                    let lengths: Vec<u8> = (0..ALIGNED_SYMBOL_COUNT).map(|_| reader.read_bits(3) as u8).collect();
+                    Some(HuffmanDecoder::new(&lengths, MAX_ALIGNED_LENGTH).context("Invalid LZX aligned offset code")?)
+                } else {
+                    None
+                };
+
+                read_code_lengths(&mut reader, &mut main_lengths[..NUM_CHARS])?;
+                read_code_lengths(&mut reader, &mut main_lengths[NUM_CHARS..])?;
+                read_code_lengths(&mut reader, &mut length_lengths)?;
+                let main_decoder = HuffmanDecoder::new(&main_lengths, MAX_CODE_LENGTH).context("Invalid LZX main code")?;
+                let length_decoder = HuffmanDecoder::new(&length_lengths, MAX_CODE_LENGTH).context("Invalid LZX length code")?;
+
+                while output.len() < block_end {
+                    let symbol = reader.read_symbol(&main_decoder)?;
+                    if usize::from(symbol) < NUM_CHARS {
+                        output.push(symbol as u8);
+                        continue;
+                    }
+
+                    let symbol = symbol - NUM_CHARS as u16;
+                    let length_header = symbol % 8;
+                    let offset_slot = usize::from(symbol / 8);
+                    let mut match_length = usize::from(length_header) + MIN_MATCH_LENGTH;
+                    if length_header == NUM_PRIMARY_LENGTHS {
+                        match_length += usize::from(reader.read_symbol(&length_decoder)?);
+                    }
+                    // A corrupt stream could run a match past the expected size
+                    match_length = match_length.min(output_size - output.len());
+
+                    let offset = if offset_slot < NUM_RECENT_OFFSETS {
+                        recent_offsets.swap(offset_slot, 0);
+                        recent_offsets[0]
+                    } else {
+                        let extra_bits = EXTRA_OFFSET_BITS[offset_slot];
+                        let mut formatted_offset = offset_slot_base(offset_slot) as usize;
+                        match &aligned_decoder {
+                            Some(aligned_decoder) if extra_bits >= NUM_ALIGNED_OFFSET_BITS => {
+                                formatted_offset += (reader.read_bits(extra_bits - NUM_ALIGNED_OFFSET_BITS) as usize) << NUM_ALIGNED_OFFSET_BITS;
+                                formatted_offset += usize::from(reader.read_symbol(aligned_decoder)?);
+                            }
+                            _ => formatted_offset += reader.read_bits(extra_bits) as usize,
+                        }
+
+                        let offset = formatted_offset - (NUM_RECENT_OFFSETS - 1);
+                        recent_offsets[2] = recent_offsets[1];
+                        recent_offsets[1] = recent_offsets[0];
+                        recent_offsets[0] = offset;
+                        offset
+                    };
+
+                    if offset > output.len() {
+                        bail!("LZX match offset {} exceeds the {} bytes decompressed so far", offset, output.len());
+                    }
+                    // Copy byte by byte, as the source may overlap the bytes being written
+                    let source = output.len() - offset;
+                    for index in 0..match_length {
+                        output.push(output[source + index]);
+                    }
+                }
+            }
+            BLOCK_TYPE_UNCOMPRESSED => {
+                // The recent offsets follow on a word boundary, which is one word further
+                // on when the stream was already aligned
+                reader.align();
+                for recent_offset in recent_offsets.iter_mut() {
+                    *recent_offset = LittleEndian::read_u32(reader.read_bytes(4)?) as usize;
+                }
+
+                let length = block_end - output.len();
+                output.extend_from_slice(reader.read_bytes(length)?);
+                // Blocks of odd size are padded back to a word boundary
+                if block_size % 2 == 1 {
+                    reader.position += 1;
+                }
+            }
+            _ => bail!("Invalid LZX block type {}", block_type),
+        }
+    }
+
+    output.truncate(output_size);
+    undo_e8_translation(&mut output);
+    Ok(output)
+}
+
+// Read a run of code lengths, sent through a precode as differences from the previous lengths
+// with run-length escapes for repeated values
+fn read_code_lengths(reader: &mut BitReader, lengths: &mut [u8]) -> Result<()> {
+    let precode_lengths: Vec<u8> = (0..PRECODE_SYMBOL_COUNT).map(|_| reader.read_bits(4) as u8).collect();
+    let precode = HuffmanDecoder::new(&precode_lengths, MAX_PRECODE_LENGTH).context("Invalid LZX precode")?;
+
+    let delta = |previous: u8, symbol: u16| ((u16::from(previous) + 17 - symbol) % 17) as u8;
+    let mut index = 0;
+    while index < lengths.len() {
+        let symbol = reader.read_symbol(&precode)?;
+        let (run_length, length) = match symbol {
+            0..=16 => (1, delta(lengths[index], symbol)),
+            17 => (4 + reader.read_bits(4) as usize, 0),
+            18 => (20 + reader.read_bits(5) as usize, 0),
+            _ => {
+                let run_length = 4 + reader.read_bits(1) as usize;
+                let symbol = reader.read_symbol(&precode)?;
+                if symbol > 16 {
+                    bail!("Invalid LZX precode symbol {} in a run of lengths", symbol);
+                }
+                (run_length, delta(lengths[index], symbol))
+            }
+        };
+
+        let run_end = (index + run_length).min(lengths.len());
+        lengths[index..run_end].fill(length);
+        index = run_end;
+    }
+
+    Ok(())
+}
+
+// First formatted offset of an offset slot
+fn offset_slot_base(offset_slot: usize) -> u32 {
+    EXTRA_OFFSET_BITS[..offset_slot].iter().fold(0, |base, &bits| base + (1 << bits))
+}
+
+// Turn the absolute x86 CALL targets the compressor produced back into relative ones
+fn undo_e8_translation(data: &mut [u8]) {
+    if data.len() <= 10 {
+        return;
+    }
+
+    let mut position = 0;
+    while position < data.len() - 10 {
+        if data[position] != 0xE8 {
+            position += 1;
+            continue;
+        }
+
+        let target = &mut data[position + 1..position + 5];
+        let absolute = LittleEndian::read_i32(target);
+        let current = position as i32;
+        if (0..E8_TRANSLATION_SIZE).contains(&absolute) {
+            LittleEndian::write_i32(target, absolute - current);
+        } else if absolute < 0 && absolute >= -current {
+            LittleEndian::write_i32(target, absolute + E8_TRANSLATION_SIZE);
+        }
+        position += 5;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Define a writer producing the LZX bit stream, to build blocks for the tests
+    struct BitWriter {
+        output: Vec<u8>,
+        buffer: u32,
+        buffered: u8,
+    }
+
+    impl BitWriter {
+        fn new() -> Self {
+            BitWriter { output: Vec::new(), buffer: 0, buffered: 0 }
+        }
+
+        fn write_bits(&mut self, value: u32, count: u8) {
+            for bit in (0..count).rev() {
+                self.buffer = (self.buffer << 1) | ((value >> bit) & 1);
+                self.buffered += 1;
+                if self.buffered == 16 {
+                    self.output.extend((self.buffer as u16).to_le_bytes());
+                    self.buffer = 0;
+                    self.buffered = 0;
+                }
+            }
+        }
+
+        fn finish(mut self) -> Vec<u8> {
+            if self.buffered > 0 {
+                self.write_bits(0, 16 - self.buffered);
+            }
+            self.output
+        }
+    }
+
+    // Send code lengths through a precode where symbol 0 (unchanged) is codeword 0 and
+    // symbol 16 (one more than zero, so length 1) is codeword 1
+    fn write_code_lengths(writer: &mut BitWriter, lengths: &[u8]) {
+        for symbol in 0..PRECODE_SYMBOL_COUNT {
+            writer.write_bits(if symbol == 0 || symbol == 16 { 1 } else { 0 }, 4);
+        }
+        for &length in lengths {
+            writer.write_bits(u32::from(length), 1);
+        }
+    }
+
+    #[test]
+    fn test_verbatim_block() {
+        // Only 'a' and a length 3 match at the most recent offset (initially 1) are used
+        let mut main_lengths = [0; MAIN_SYMBOL_COUNT];
+        main_lengths[usize::from(b'a')] = 1;
+        main_lengths[NUM_CHARS + 1] = 1;
+
+        let mut writer = BitWriter::new();
+        writer.write_bits(BLOCK_TYPE_VERBATIM, 3);
+        writer.write_bits(0, 1);
+        writer.write_bits(4, 16);
+        write_code_lengths(&mut writer, &main_lengths[..NUM_CHARS]);
+        write_code_lengths(&mut writer, &main_lengths[NUM_CHARS..]);
+        write_code_lengths(&mut writer, &[0; LENGTH_SYMBOL_COUNT]);
+        // 'a' is codeword 0 and the match codeword 1
+        writer.write_bits(0b01, 2);
+
+        assert_eq!(decompress(&writer.finish(), 4).unwrap(), b"aaaa");
+    }
+
+    #[test]
+    fn test_uncompressed_block_with_e8_translation() {
+        let mut content = vec![0x90; 16];
+        // A CALL at position 2 whose target was made absolute: 2 + 0x10 = 0x12
+        content[2] = 0xE8;
+        content[3..7].copy_from_slice(&0x12i32.to_le_bytes());
+
+        let mut writer = BitWriter::new();
+        writer.write_bits(BLOCK_TYPE_UNCOMPRESSED, 3);
+        writer.write_bits(0, 1);
+        writer.write_bits(content.len() as u32, 16);
+        let mut input = writer.finish();
+        for _ in 0..NUM_RECENT_OFFSETS {
+            input.extend(1u32.to_le_bytes());
+        }
+        input.extend(&content);
+
+        let output = decompress(&input, content.len()).unwrap();
+        assert_eq!(&output[3..7], &0x10i32.to_le_bytes());
+        assert_eq!(offset_slot_base(NUM_OFFSET_SLOTS - 1), 24576);
+    }
+
+    #[test]
+    fn test_uncompressed_block_after_verbatim_block() {
+        // Verbatim blocks of every length up to a word of literals leave the uncompressed block
+        // header at every bit of a word, with up to a word read ahead of it
+        for literal_count in 1..=16 {
+            let mut main_lengths = [0; MAIN_SYMBOL_COUNT];
+            main_lengths[usize::from(b'a')] = 1;
+            main_lengths[NUM_CHARS + 1] = 1;
+
+            let mut writer = BitWriter::new();
+            writer.write_bits(BLOCK_TYPE_VERBATIM, 3);
+            writer.write_bits(0, 1);
+            writer.write_bits(literal_count, 16);
+            write_code_lengths(&mut writer, &main_lengths[..NUM_CHARS]);
+            write_code_lengths(&mut writer, &main_lengths[NUM_CHARS..]);
+            write_code_lengths(&mut writer, &[0; LENGTH_SYMBOL_COUNT]);
+            writer.write_bits(0, literal_count as u8);
+
+            writer.write_bits(BLOCK_TYPE_UNCOMPRESSED, 3);
+            writer.write_bits(1, 1);
+            if writer.buffered == 0 {
+                writer.write_bits(0, 16);
+            }
+            let mut input = writer.finish();
+            for _ in 0..NUM_RECENT_OFFSETS {
+                input.extend(1u32.to_le_bytes());
+            }
+            input.extend(b"bcde");
+
+            let mut expected = vec![b'a'; literal_count as usize];
+            expected.extend(b"bcde");
+            assert_eq!(decompress(&input, expected.len()).unwrap(), expected, "{} literals", literal_count);
+        }
+    }
+}
@@ -1,10 +1,28 @@
-use ntfs_mft_lib::config::Config;
+use ntfs_mft_lib::config::{Config, Mode};
 use ntfs_mft_lib::mft_reader::MftReader;
-use ntfs_mft_lib::mft_parser::MftEntry;
-use ntfs_mft_lib::data_structurer::{StructuredData, DbEntry};
+use ntfs_mft_lib::mft_parser::{MftEntry, RecordSignature};
+use ntfs_mft_lib::logical_file::LogicalFile;
+use ntfs_mft_lib::data_structurer::{StructuredData, DbEntry, DbIndexEntry, DbSlackEntry};
+use ntfs_mft_lib::index::DirectoryIndex;
+use ntfs_mft_lib::path_resolver::PathResolver;
+use ntfs_mft_lib::recovery::VolumeBitmap;
+use ntfs_mft_lib::timestamp_anomaly::TimestampAnalyzer;
 use ntfs_mft_lib::database_interface::DatabaseInterface;
+use ntfs_mft_lib::resident_extractor::ResidentExtractor;
+use ntfs_mft_lib::carver::{live_record_keys, RecordCarver, RecordKey};
+use ntfs_mft_lib::bodyfile::write_bodyfile;
+use ntfs_mft_lib::timeline::{write_csv, write_json_lines, TimelineBuilder, TimelineFilter, TimelineOutput};
+use ntfs_mft_lib::usn_journal::open_usn_journal;
 use anyhow::{Result, Context};
+use chrono::Utc;
 use log::{info, warn, error};
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+
+// Record size to assume when the first record of a $MFT dump doesn't tell
+const DEFAULT_MFT_RECORD_SIZE: u64 = 1024;
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -15,29 +33,184 @@ async fn main() -> Result<()> {
     let config = Config::new().context("Failed to load configuration")?;
     // Validate configuration here (if necessary)
 
-    // Initialize the MFT reader
-    let mut mft_reader = MftReader::new(&config).context("Failed to initialize MFT reader")?;
+    match Mode::from_args(std::env::args().skip(1))? {
+        Mode::Index => index(&config).await,
+        Mode::ExtractResident { mft_dump_path, output_directory, records } => {
+            extract_resident(&mft_dump_path, &output_directory, &records)
+        }
+        Mode::Carve { source_path } => carve(&config, &source_path).await,
+        Mode::Bodyfile { output_path } => bodyfile(&config, &output_path),
+        Mode::Timeline { output, filter } => timeline(&config, &output, &filter).await,
+    }
+}
 
+// Read the whole MFT from the volume and store it in the database
+async fn index(config: &Config) -> Result<()> {
     // Initialize the database interface
-    let database_interface = DatabaseInterface::new(&config).await.context("Failed to initialize database interface")?;
+    let database_interface = DatabaseInterface::new(config).await.context("Failed to initialize database interface")?;
 
-    // Read and parse the MFT entries
-    let mut structured_data = StructuredData { entries: Vec::new() };
-    let mut entry_index = 0;
+    let structured_data = read_volume(config)?;
 
     // Start a database transaction
     let mut transaction = database_interface.start_transaction().await.context("Failed to start database transaction")?;
 
-    loop {
-        // Read an MFT entry
-        match mft_reader.read_mft_entry(entry_index).await {
+    // Store the structured data in the database
+    database_interface.store_data(&structured_data, &mut transaction).await.context("Failed to store data in the database")?;
+
+    // Commit the transaction
+    database_interface.commit(&mut transaction).await.context("Failed to commit database transaction")?;
+
+    info!("MFT data has been successfully read, parsed, and stored in the database.");
+
+    Ok(())
+}
+
+// Read the whole MFT from the volume and write its timeline for mactime
+fn bodyfile(config: &Config, output_path: &Path) -> Result<()> {
+    let structured_data = read_volume(config)?;
+
+    let output = File::create(output_path)
+        .with_context(|| format!("Failed to create bodyfile at {:?}", output_path))?;
+    write_bodyfile(&structured_data, BufWriter::new(output))?;
+
+    info!("Wrote the timeline of {} MFT entries to {:?}", structured_data.entries.len(), output_path);
+
+    Ok(())
+}
+
+// Read the whole MFT from the volume and export the events matching the filter in time order.
+// Entries are streamed into the sort rather than held, so the MFT is read twice: once for the
+// directory tree that paths are resolved through, then once for the events. The records of the
+// USN journal are added last, resolved through the same directory tree.
+async fn timeline(config: &Config, output: &TimelineOutput, filter: &TimelineFilter) -> Result<()> {
+    let mut mft_reader = MftReader::new(config).context("Failed to initialize MFT reader")?;
+
+    let mut path_resolver = PathResolver::new(&config.volume_root);
+    for_each_file(&mut mft_reader, |file, _| path_resolver.add_file(&file));
+
+    // Events are sorted in chunks spilled to the temporary directory, then merged as written
+    let mut timeline_builder = TimelineBuilder::new(filter, &std::env::temp_dir());
+    for_each_file(&mut mft_reader, |file, _| {
+        let mut db_entry = DbEntry::from_logical_file(&file, None)?;
+        db_entry.resolve_paths(&mut path_resolver);
+        timeline_builder.add_entry(&db_entry)
+    });
+    let entry_count = timeline_builder.entry_count();
+
+    // A journal that can't be read loses its change events, not the rest of the timeline
+    match open_usn_journal(&mut mft_reader) {
+        Ok(Some(usn_records)) => {
+            for record in usn_records {
+                match record {
+                    Ok(record) => timeline_builder.add_usn_record(&record, &mut path_resolver)?,
+                    Err(e) => warn!("Skipped a record of the USN journal: {}", e),
+                }
+            }
+        },
+        Ok(None) => info!("The volume has no USN journal"),
+        Err(e) => warn!("Failed to open the USN journal, the timeline will not hold USN change events: {}", e),
+    }
+    let usn_record_count = timeline_builder.usn_record_count();
+
+    let events = timeline_builder.finish()?;
+    let create_output = |output_path: &Path| {
+        File::create(output_path)
+            .map(BufWriter::new)
+            .with_context(|| format!("Failed to create timeline at {:?}", output_path))
+    };
+    match output {
+        TimelineOutput::Csv(output_path) => write_csv(events, create_output(output_path)?)?,
+        TimelineOutput::JsonLines(output_path) => write_json_lines(events, create_output(output_path)?)?,
+        TimelineOutput::Sqlite => {
+            let database_interface = DatabaseInterface::new(config).await.context("Failed to initialize database interface")?;
+            let mut transaction = database_interface.start_transaction().await.context("Failed to start database transaction")?;
+            database_interface.store_timeline(events, &mut transaction).await.context("Failed to store the timeline in the database")?;
+            database_interface.commit(&mut transaction).await.context("Failed to commit database transaction")?;
+        },
+    }
+
+    info!("Wrote the timeline of {} MFT entries and {} USN records to {:?}", entry_count, usn_record_count, output);
+
+    Ok(())
+}
+
+// Read and parse every file of the MFT, with its directory index, deleted file assessment and
+// timestamp anomalies
+fn read_volume(config: &Config) -> Result<StructuredData> {
+    // Initialize the MFT reader
+    let mut mft_reader = MftReader::new(config).context("Failed to initialize MFT reader")?;
+
+    // Deleted files are assessed against the cluster bitmap, which is loaded once up front
+    let bitmap = match VolumeBitmap::read(&mut mft_reader) {
+        Ok(bitmap) => Some(bitmap),
+        Err(e) => {
+            warn!("Failed to read $Bitmap, deleted files will not be checked against it: {}", e);
+            None
+        }
+    };
+
+    // Timestamps are checked against the start of the scan and the creation of the volume
+    let timestamp_analyzer = TimestampAnalyzer::read(&mut mft_reader, Utc::now()).unwrap_or_else(|e| {
+        warn!("Failed to read the creation time of the volume, timestamps will not be checked against it: {}", e);
+        TimestampAnalyzer::new(Utc::now(), None)
+    });
+
+    // Read and parse the MFT entries
+    let mut structured_data = StructuredData { entries: Vec::new() };
+    let mut path_resolver = PathResolver::new(&config.volume_root);
+
+    for_each_file(&mut mft_reader, |file, mft_reader| {
+        path_resolver.add_file(&file)?;
+        let mut db_entry = DbEntry::from_logical_file(&file, bitmap.as_ref())?;
+        // A broken reparse point loses its target, not the file, see DbEntry::from_logical_file
+        if let Err(e) = file.reparse_point() {
+            warn!("Failed to read $REPARSE_POINT of MFT entry {}: {}", file.record_number(), e);
+        }
+        // A broken index loses the directory listing, not the directory itself
+        match DirectoryIndex::read(&file, mft_reader) {
+            Ok(Some(index)) => {
+                if !index.damaged_blocks.is_empty() {
+                    warn!("Skipped damaged $I30 blocks {:?} of MFT entry {}", index.damaged_blocks, file.record_number());
+                }
+                db_entry.index_entries = index.entries.iter().map(DbIndexEntry::from).collect();
+                db_entry.slack_entries = index.slack_entries.iter().map(DbSlackEntry::from).collect();
+            },
+            Ok(None) => {},
+            Err(e) => warn!("Failed to read the $I30 index of MFT entry {}: {}", file.record_number(), e),
+        }
+        db_entry.timestamp_anomalies = timestamp_analyzer.analyze(&file)?;
+        structured_data.entries.push(db_entry);
+        Ok(())
+    });
+
+    // Now that every directory has been seen, rebuild the full paths
+    structured_data.resolve_paths(&mut path_resolver);
+    structured_data.resolve_sequence_references();
+
+    Ok(structured_data)
+}
+
+// Walk every record of the MFT, handing each file with its extension records merged to `visit`.
+// A file that fails to parse or resolve is skipped with a warning.
+fn for_each_file<F>(mft_reader: &mut MftReader, mut visit: F)
+where
+    F: FnMut(LogicalFile, &mut MftReader) -> Result<()>,
+{
+    for entry_index in 0..mft_reader.record_count() {
+        // Read an MFT entry, skipping slots that can't be read rather than the rest of the MFT
+        match mft_reader.read_mft_entry(entry_index) {
             Ok(entry_data) => {
                 // Parse the MFT entry
                 match MftEntry::parse(&entry_data) {
+                    // Unused slots carry no file, and extension records are read through their base record
+                    Ok(mft_entry) if mft_entry.signature != RecordSignature::File || !mft_entry.is_base_record() => {},
                     Ok(mft_entry) => {
-                        // Convert the parsed MFT entry to a database entry
-                        let db_entry = DbEntry::from_mft_entry(mft_entry);
-                        structured_data.entries.push(db_entry);
+                        // Merge any extension records before handing the logical file over
+                        let result = LogicalFile::resolve(entry_index, mft_entry, mft_reader)
+                            .and_then(|file| visit(file, mft_reader));
+                        if let Err(e) = result {
+                            warn!("Failed to resolve MFT entry at index {}: {}", entry_index, e);
+                        }
                     },
                     Err(e) => {
                         warn!("Failed to parse MFT entry at index {}: {}", entry_index, e);
@@ -45,25 +218,110 @@ async fn main() -> Result<()> {
                     },
                 }
             },
+            Err(e) => warn!("Failed to read MFT entry at index {}: {}", entry_index, e),
+        }
+
+        if (entry_index + 1) % 100 == 0 {
+            info!("Processed {} MFT entries", entry_index + 1);
+        }
+    }
+}
+
+// Scan raw data for FILE records and store the ones missing from the live MFT
+async fn carve(config: &Config, source_path: &Path) -> Result<()> {
+    // Records the live MFT still holds are already covered by indexing
+    let mut seen: HashSet<RecordKey> = match MftReader::new(config) {
+        Ok(mut mft_reader) => live_record_keys(&mut mft_reader),
+        Err(e) => {
+            warn!("Failed to open the live MFT, carved records will not be deduplicated against it: {}", e);
+            HashSet::new()
+        }
+    };
+
+    let source = File::open(source_path)
+        .with_context(|| format!("Failed to open carving source at {:?}", source_path))?;
+    let database_interface = DatabaseInterface::new(config).await.context("Failed to initialize database interface")?;
+
+    // Directories are looked up among the carved records themselves, by record number. Each
+    // version of a record keeps the path of its own name, while parent directories resolve
+    // through the version carved last, whose sequence number has to match the reference, and
+    // otherwise land in $Orphan rather than under the wrong directory.
+    let mut structured_data = StructuredData { entries: Vec::new() };
+    let mut path_resolver = PathResolver::new(&config.volume_root);
+
+    for record in RecordCarver::new(BufReader::new(source)) {
+        let record = match record {
+            Ok(record) => record,
             Err(e) => {
-                error!("Failed to read MFT entry at index {}: {}", entry_index, e);
-                break; // Stop reading if we encounter an error
+                error!("Failed to read {:?}: {}", source_path, e);
+                break;
             }
+        };
+
+        // Extension records cannot be matched to the right version of their base record, and
+        // copies of the same version of a record only need storing once
+        if !record.entry.is_base_record() || !seen.insert(RecordKey::of(&record.entry)) {
+            continue;
         }
 
-        entry_index += 1; // Increment the entry index to read the next MFT entry
-        if entry_index % 100 == 0 {
-            info!("Processed {} MFT entries", entry_index);
+        // Carved records only have the number their header gives, which NTFS 3.0 leaves out
+        let physical_offset = record.physical_offset;
+        let record_number = record.entry.record_number.unwrap_or_default();
+        let db_entry = LogicalFile::from_records(record_number, record.entry, Vec::new()).and_then(|file| {
+            path_resolver.add_file(&file)?;
+            DbEntry::from_logical_file(&file, None)
+        });
+        match db_entry {
+            Ok(mut db_entry) => {
+                db_entry.source_path = Some(source_path.display().to_string());
+                db_entry.physical_offset = Some(physical_offset);
+                structured_data.entries.push(db_entry);
+                if structured_data.entries.len() % 1000 == 0 {
+                    info!("Carved {} FILE records", structured_data.entries.len());
+                }
+            },
+            Err(e) => warn!("Failed to read carved record at offset {}: {}", physical_offset, e),
         }
     }
 
-    // Store the structured data in the database
-    database_interface.store_data(&structured_data, &mut transaction).await.context("Failed to store data in the database")?;
+    structured_data.resolve_paths(&mut path_resolver);
 
-    // Commit the transaction
+    let mut transaction = database_interface.start_transaction().await.context("Failed to start database transaction")?;
+    database_interface.store_data(&structured_data, &mut transaction).await.context("Failed to store data in the database")?;
     database_interface.commit(&mut transaction).await.context("Failed to commit database transaction")?;
 
-    info!("MFT data has been successfully read, parsed, and stored in the database.");
+    info!("Stored {} carved FILE records from {:?}", structured_data.entries.len(), source_path);
+
+    Ok(())
+}
+
+// Write the resident content of selected records of a $MFT dump, which needs no volume image
+fn extract_resident(mft_dump_path: &Path, output_directory: &Path, records: &[u64]) -> Result<()> {
+    let mft_data = std::fs::read(mft_dump_path)
+        .with_context(|| format!("Failed to read $MFT dump at {:?}", mft_dump_path))?;
+    let record_size = MftEntry::read_allocated_size(&mft_data).unwrap_or(DEFAULT_MFT_RECORD_SIZE);
+    let record_count = mft_data.len() as u64 / record_size;
+    let extractor = ResidentExtractor::new(output_directory)?;
+
+    let records: Vec<u64> = if records.is_empty() { (0..record_count).collect() } else { records.to_vec() };
+    for record in records {
+        if record >= record_count {
+            warn!("MFT entry {} lies beyond the end of the dump ({} records)", record, record_count);
+            continue;
+        }
+
+        let offset = (record * record_size) as usize;
+        let written = MftEntry::parse(&mft_data[offset..offset + record_size as usize])
+            .and_then(|mft_entry| extractor.extract(record, &mft_entry));
+        match written {
+            Ok(written) => {
+                for path in written {
+                    info!("Extracted MFT entry {} to {:?}", record, path);
+                }
+            },
+            Err(e) => warn!("Failed to extract MFT entry {}: {}", record, e),
+        }
+    }
 
     Ok(())
-}
\ No newline at end of file
+}
@@ -1,46 +1,272 @@
-use crate::utils::{read_bytes, read_u16, read_u32, read_u64, read_string};
-use anyhow::{Result, Context};
-use std::collections::HashMap;
+use crate::attribute::{AttributeIter, ATTRIBUTE_TYPE_DATA, ATTRIBUTE_TYPE_FILE_NAME, ATTRIBUTE_TYPE_STANDARD_INFORMATION};
+use crate::file_name::FileName;
+use crate::fixup::apply_fixups;
+use crate::logical_file::LogicalFile;
+use crate::standard_information::StandardInformation;
+use anyhow::{bail, Context, Result};
+use byteorder::{ByteOrder, LittleEndian};
 
 // Define constants for MFT Entry header offsets and sizes
-const FILE_SIGNATURE_OFFSET: usize = 0;
-const FILE_SIGNATURE_SIZE: usize = 4;
-const FILE_RECORD_NUMBER_OFFSET: usize = 44;
-const FILE_RECORD_NUMBER_SIZE: usize = 6;
+pub(crate) const FILE_SIGNATURE_OFFSET: usize = 0;
+pub(crate) const FILE_SIGNATURE_SIZE: usize = 4;
+pub(crate) const FILE_USA_OFFSET_OFFSET: usize = 4;
+pub(crate) const FILE_USA_COUNT_OFFSET: usize = 6;
+const FILE_LSN_OFFSET: usize = 8;
+pub(crate) const FILE_SEQUENCE_NUMBER_OFFSET: usize = 16;
+pub(crate) const FILE_HARD_LINK_COUNT_OFFSET: usize = 18;
+pub(crate) const FILE_FIRST_ATTRIBUTE_OFFSET: usize = 20;
+pub(crate) const FILE_FLAGS_OFFSET: usize = 22;
+pub(crate) const FILE_USED_SIZE_OFFSET: usize = 24;
+pub(crate) const FILE_ALLOCATED_SIZE_OFFSET: usize = 28;
+const FILE_BASE_RECORD_OFFSET: usize = 32;
+const FILE_NEXT_ATTRIBUTE_ID_OFFSET: usize = 40;
+pub(crate) const FILE_RECORD_NUMBER_OFFSET: usize = 44;
+
+// NTFS 3.0 records end their header before the record number, NTFS 3.1 records after it
+const FILE_HEADER_SIZE_V30: usize = 42;
+const FILE_HEADER_SIZE_V31: usize = 48;
+
+// Define constants for the FILE record header flags
+pub const FILE_RECORD_IN_USE: u16 = 0x0001;
+pub const FILE_RECORD_IS_DIRECTORY: u16 = 0x0002;
+pub const FILE_RECORD_IS_EXTENSION: u16 = 0x0004;
+pub const FILE_RECORD_HAS_VIEW_INDEX: u16 = 0x0008;
+
+// Smallest and largest FILE record sizes seen on real volumes
+const MIN_FILE_RECORD_SIZE: u64 = 256;
+pub const MAX_FILE_RECORD_SIZE: u64 = 65536;
+
+// Define the states a record slot in the MFT can be in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RecordSignature {
+    // A FILE record whose header has been decoded
+    File,
+    // A record chkdsk marked as bad after a multi-sector transfer error
+    Baad,
+    // A record slot that has never been written
+    #[default]
+    Zeroed,
+}
+
+// Define a struct to hold an MFT file reference (48-bit record number + 16-bit sequence number)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct FileReference {
+    pub record_number: u64,
+    pub sequence_number: u16,
+}
+
+impl FileReference {
+    pub fn from_u64(value: u64) -> Self {
+        FileReference {
+            record_number: value & 0x0000_FFFF_FFFF_FFFF,
+            sequence_number: (value >> 48) as u16,
+        }
+    }
+}
 
 // Define a struct to hold the parsed MFT entry data
-#[derive(Debug)]
+#[derive(Debug, Clone, Default)]
 pub struct MftEntry {
-    pub signature: String,
-    pub record_number: u64,
+    pub signature: RecordSignature,
+    pub update_sequence_offset: u16,
+    pub update_sequence_count: u16,
+    pub logfile_sequence_number: u64,
+    pub sequence_number: u16,
+    pub hard_link_count: u16,
+    pub first_attribute_offset: u16,
+    pub flags: u16,
+    pub used_size: u32,
+    pub allocated_size: u32,
+    pub base_record: FileReference,
+    pub next_attribute_id: u16,
+    // The number the record gives itself, which NTFS 3.0 headers leave out. The slot a record
+    // was read from is the authoritative number, this is only checked against it.
+    pub record_number: Option<u64>,
+    // The record with fixups applied, used to decode its attributes
+    pub record_data: Vec<u8>,
     // Add more fields as necessary to represent the MFT entry data
 }
 
 impl MftEntry {
     pub fn parse(entry_data: &[u8]) -> Result<Self> {
-        // Parse the signature
-        let signature = read_string(entry_data, FILE_SIGNATURE_OFFSET, FILE_SIGNATURE_SIZE)
-            .with_context(|| "Failed to parse file signature")?;
+        if entry_data.len() < FILE_SIGNATURE_SIZE {
+            bail!("MFT entry of {} bytes is too short to hold a signature", entry_data.len());
+        }
+
+        // Parse the signature, keeping bad and never used records as distinct states
+        match &entry_data[FILE_SIGNATURE_OFFSET..FILE_SIGNATURE_OFFSET + FILE_SIGNATURE_SIZE] {
+            b"FILE" => {}
+            b"BAAD" => return Ok(MftEntry { signature: RecordSignature::Baad, ..Default::default() }),
+            [0, 0, 0, 0] => return Ok(MftEntry { signature: RecordSignature::Zeroed, ..Default::default() }),
+            other => bail!("Invalid MFT entry signature {:?}", String::from_utf8_lossy(other)),
+        }
+
+        if entry_data.len() < FILE_HEADER_SIZE_V31 {
+            bail!("MFT entry of {} bytes is too short to hold a FILE record header", entry_data.len());
+        }
+
+        // Restore the sector trailers before reading any field, refusing torn records
+        let mut record_data = entry_data.to_vec();
+        apply_fixups(&mut record_data)
+            .with_context(|| "Failed to apply update sequence fixups")?;
+        let entry_data = record_data.as_slice();
 
-        // Parse the record number
-        let record_number = read_u64(entry_data, FILE_RECORD_NUMBER_OFFSET)
-            .with_context(|| "Failed to parse file record number")?;
+        let update_sequence_offset = LittleEndian::read_u16(&entry_data[FILE_USA_OFFSET_OFFSET..]);
+        let update_sequence_count = LittleEndian::read_u16(&entry_data[FILE_USA_COUNT_OFFSET..]);
+        let first_attribute_offset = LittleEndian::read_u16(&entry_data[FILE_FIRST_ATTRIBUTE_OFFSET..]);
+        let used_size = LittleEndian::read_u32(&entry_data[FILE_USED_SIZE_OFFSET..]);
+        let allocated_size = LittleEndian::read_u32(&entry_data[FILE_ALLOCATED_SIZE_OFFSET..]);
+
+        // Validate the sizes so later attribute parsing stays within the record
+        if allocated_size as usize > entry_data.len() || used_size > allocated_size {
+            bail!("Invalid FILE record sizes (used {}, allocated {}, read {})", used_size, allocated_size, entry_data.len());
+        }
+        if usize::from(first_attribute_offset) < FILE_HEADER_SIZE_V30 || u32::from(first_attribute_offset) >= used_size {
+            bail!("Invalid first attribute offset {}", first_attribute_offset);
+        }
+
+        // The record number only exists in NTFS 3.1 headers, which place the USA after it
+        let record_number = if usize::from(update_sequence_offset) >= FILE_HEADER_SIZE_V31 {
+            Some(u64::from(LittleEndian::read_u32(&entry_data[FILE_RECORD_NUMBER_OFFSET..])))
+        } else {
+            None
+        };
 
         // Create and return the MftEntry struct
         Ok(MftEntry {
-            signature,
+            signature: RecordSignature::File,
+            update_sequence_offset,
+            update_sequence_count,
+            logfile_sequence_number: LittleEndian::read_u64(&entry_data[FILE_LSN_OFFSET..]),
+            sequence_number: LittleEndian::read_u16(&entry_data[FILE_SEQUENCE_NUMBER_OFFSET..]),
+            hard_link_count: LittleEndian::read_u16(&entry_data[FILE_HARD_LINK_COUNT_OFFSET..]),
+            first_attribute_offset,
+            flags: LittleEndian::read_u16(&entry_data[FILE_FLAGS_OFFSET..]),
+            used_size,
+            allocated_size,
+            base_record: FileReference::from_u64(LittleEndian::read_u64(&entry_data[FILE_BASE_RECORD_OFFSET..])),
+            next_attribute_id: LittleEndian::read_u16(&entry_data[FILE_NEXT_ATTRIBUTE_ID_OFFSET..]),
             record_number,
-            // Initialize other fields as necessary
+            record_data,
         })
     }
+
+    // Iterate over the attributes stored in this record, up to its used size
+    pub fn attributes(&self) -> AttributeIter<'_> {
+        let used_size = (self.used_size as usize).min(self.record_data.len());
+        AttributeIter::new(&self.record_data[..used_size], usize::from(self.first_attribute_offset))
+    }
+
+    // Find and parse the $STANDARD_INFORMATION attribute, if the record holds one
+    pub fn standard_information(&self) -> Result<Option<StandardInformation>> {
+        for attribute in self.attributes() {
+            let attribute = attribute?;
+            if attribute.type_code != ATTRIBUTE_TYPE_STANDARD_INFORMATION {
+                continue;
+            }
+
+            return StandardInformation::from_attribute(&attribute).map(Some);
+        }
+
+        Ok(None)
+    }
+
+    // Parse every $FILE_NAME attribute of the record, one per hard link and namespace
+    pub fn file_names(&self) -> Result<Vec<FileName>> {
+        let mut file_names = Vec::new();
+
+        for attribute in self.attributes() {
+            let attribute = attribute?;
+            if attribute.type_code != ATTRIBUTE_TYPE_FILE_NAME {
+                continue;
+            }
+
+            file_names.push(FileName::from_attribute(&attribute)?);
+        }
+
+        Ok(file_names)
+    }
+
+    // Return the content of a $DATA stream stored inside the record itself, using an empty name
+    // for the default stream. Returns None if the stream is missing or lives in clusters.
+    pub fn resident_data(&self, stream_name: &str) -> Result<Option<Vec<u8>>> {
+        for attribute in self.attributes() {
+            let attribute = attribute?;
+            if attribute.type_code != ATTRIBUTE_TYPE_DATA || attribute.name != stream_name {
+                continue;
+            }
+
+            return Ok(attribute.resident_value().map(<[u8]>::to_vec));
+        }
+
+        Ok(None)
+    }
+
+    // Return the name and content of every resident $DATA stream, default stream first
+    pub fn resident_streams(&self) -> Result<Vec<(String, Vec<u8>)>> {
+        let mut streams = Vec::new();
+
+        for attribute in self.attributes() {
+            let attribute = attribute?;
+            if attribute.type_code != ATTRIBUTE_TYPE_DATA {
+                continue;
+            }
+
+            if let Some(value) = attribute.resident_value() {
+                streams.push((attribute.name.clone(), value.to_vec()));
+            }
+        }
+
+        streams.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(streams)
+    }
+
+    pub fn is_in_use(&self) -> bool {
+        self.flags & FILE_RECORD_IN_USE != 0
+    }
+
+    pub fn is_directory(&self) -> bool {
+        self.flags & FILE_RECORD_IS_DIRECTORY != 0
+    }
+
+    pub fn is_extension(&self) -> bool {
+        self.flags & FILE_RECORD_IS_EXTENSION != 0
+    }
+
+    pub fn has_view_index(&self) -> bool {
+        self.flags & FILE_RECORD_HAS_VIEW_INDEX != 0
+    }
+
+    // Extension records point back at the base record holding the rest of the file. Those of
+    // $MFT itself point at record 0, so the sequence number has to be compared too.
+    pub fn is_base_record(&self) -> bool {
+        self.base_record == FileReference::default()
+    }
+
+    // Read the allocated size of a FILE record from its header, returning None
+    // if the data is not a FILE record or the size is not a plausible record size.
+    pub fn read_allocated_size(entry_data: &[u8]) -> Option<u64> {
+        if entry_data.len() < FILE_ALLOCATED_SIZE_OFFSET + 4 || &entry_data[..FILE_SIGNATURE_SIZE] != b"FILE" {
+            return None;
+        }
+
+        let allocated_size = u64::from(LittleEndian::read_u32(&entry_data[FILE_ALLOCATED_SIZE_OFFSET..]));
+        if allocated_size.is_power_of_two() && (MIN_FILE_RECORD_SIZE..=MAX_FILE_RECORD_SIZE).contains(&allocated_size) {
+            Some(allocated_size)
+        } else {
+            None
+        }
+    }
 }
 
 // Define a struct to represent the MFT parser
-pub struct MftParser;
+pub struct MftParser {
+    record_size: usize,
+}
 
 impl MftParser {
-    pub fn new() -> Self {
-        MftParser
+    pub fn new(record_size: usize) -> Self {
+        MftParser { record_size }
     }
 
     pub fn parse_mft_entries(&self, mft_data: Vec<u8>) -> Result<Vec<MftEntry>> {
@@ -48,8 +274,7 @@ impl MftParser {
         let mut offset = 0;
 
         while offset < mft_data.len() {
-            // Assuming the default size of an MFT entry is 1024 bytes
-            let mft_entry_size = 1024;
+            let mft_entry_size = self.record_size;
 
             // Check if we have enough data left to read a full MFT entry
             if offset + mft_entry_size > mft_data.len() {
@@ -72,45 +297,117 @@ impl MftParser {
 
         Ok(entries)
     }
-}
 
-// Add more methods and logic as needed for your project.
-
-// Utility functions to read data from a byte slice
-fn read_string(entry_data: &[u8], offset: usize, size: usize) -> Result<String> {
-    let end = offset + size;
-    let bytes = &entry_data[offset..end];
-    let string = String::from_utf8_lossy(bytes).to_string();
-    Ok(string)
+    // Parse the MFT and merge extension records into the logical file of their base record
+    pub fn parse_logical_files(&self, mft_data: Vec<u8>) -> Result<Vec<LogicalFile>> {
+        let entries = self.parse_mft_entries(mft_data)?;
+        // Entries are parsed in MFT order, so the position of an entry is its record number
+        LogicalFile::group_entries(entries.into_iter().enumerate().map(|(index, entry)| (index as u64, entry)).collect())
+    }
 }
 
-fn read_u64(entry_data: &[u8], offset: usize) -> Result<u64> {
-    let end = offset + FILE_RECORD_NUMBER_SIZE;
-    let bytes = &entry_data[offset..end];
-    let mut cursor = std::io::Cursor::new(bytes);
-    let value = cursor.read_u64::<byteorder::LittleEndian>()
-        .with_context(|| format!("Failed to read u64 from entry data at offset {}", offset))?;
-    Ok(value)
-}
+// Add more methods and logic as needed for your project.
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_support::{fake_file_record, fake_non_resident_attribute, fake_resident_attribute};
 
     #[test]
     fn test_mft_entry_parsing() {
-        // Create a fake MFT entry for testing purposes
-        let mut fake_mft_entry = vec![0; 1024];
-        // Set a fake signature "FILE"
-        fake_mft_entry[FILE_SIGNATURE_OFFSET..FILE_SIGNATURE_OFFSET + FILE_SIGNATURE_SIZE].copy_from_slice(b"FILE");
-        // Set a fake record number
-        fake_mft_entry[FILE_RECORD_NUMBER_OFFSET..FILE_RECORD_NUMBER_OFFSET + FILE_RECORD_NUMBER_SIZE].copy_from_slice(&12345u64.to_le_bytes()[..FILE_RECORD_NUMBER_SIZE]);
+        let mut fake_mft_entry = fake_file_record(12345, FILE_RECORD_IN_USE | FILE_RECORD_IS_DIRECTORY, &[]);
+        // Set the remaining header fields
+        LittleEndian::write_u64(&mut fake_mft_entry[FILE_LSN_OFFSET..], 0x1122_3344);
+        LittleEndian::write_u16(&mut fake_mft_entry[FILE_SEQUENCE_NUMBER_OFFSET..], 3);
+        LittleEndian::write_u16(&mut fake_mft_entry[FILE_HARD_LINK_COUNT_OFFSET..], 2);
+        LittleEndian::write_u16(&mut fake_mft_entry[FILE_NEXT_ATTRIBUTE_ID_OFFSET..], 4);
 
         // Parse the fake MFT entry
         let entry = MftEntry::parse(&fake_mft_entry).unwrap();
 
         // Check the parsed values
-        assert_eq!(entry.signature, "FILE");
-        assert_eq!(entry.record_number, 12345);
+        assert_eq!(entry.signature, RecordSignature::File);
+        assert_eq!(entry.record_number, Some(12345));
+        assert_eq!(entry.logfile_sequence_number, 0x1122_3344);
+        assert_eq!(entry.sequence_number, 3);
+        assert_eq!(entry.hard_link_count, 2);
+        assert_eq!(entry.first_attribute_offset, 0x38);
+        assert_eq!(entry.used_size, 0x40);
+        assert_eq!(entry.attributes().count(), 0);
+        assert!(entry.standard_information().unwrap().is_none());
+        assert_eq!(entry.allocated_size, 1024);
+        assert_eq!(entry.next_attribute_id, 4);
+        assert!(entry.is_in_use());
+        assert!(entry.is_directory());
+        assert!(!entry.is_extension());
+        assert!(entry.is_base_record());
+
+        // An extension record of $MFT, whose base is record 0 with sequence number 1
+        LittleEndian::write_u64(&mut fake_mft_entry[FILE_BASE_RECORD_OFFSET..], 1 << 48);
+        assert!(!MftEntry::parse(&fake_mft_entry).unwrap().is_base_record());
+    }
+
+    #[test]
+    fn test_attributes_across_sector_boundary() {
+        // A resident value large enough to straddle the 512-byte fixup boundary
+        let value: Vec<u8> = (0..600).map(|i| (i % 251) as u8).collect();
+        let attribute = fake_resident_attribute(ATTRIBUTE_TYPE_DATA, "", &value);
+        let entry = MftEntry::parse(&fake_file_record(40, FILE_RECORD_IN_USE, &attribute)).unwrap();
+
+        let attributes: Vec<_> = entry.attributes().collect::<Result<_>>().unwrap();
+        assert_eq!(attributes.len(), 1);
+        assert_eq!(attributes[0].resident_value(), Some(&value[..]));
+    }
+
+    #[test]
+    fn test_resident_data_extraction() {
+        let attributes = [
+            fake_resident_attribute(ATTRIBUTE_TYPE_DATA, "Zone.Identifier", b"ZoneId=3"),
+            fake_resident_attribute(ATTRIBUTE_TYPE_DATA, "", b"hello"),
+            fake_non_resident_attribute(ATTRIBUTE_TYPE_DATA, "big", &[0x11, 0x01, 0x40], 4096),
+        ].concat();
+        let entry = MftEntry::parse(&fake_file_record(64, FILE_RECORD_IN_USE, &attributes)).unwrap();
+
+        assert_eq!(entry.resident_data("").unwrap(), Some(b"hello".to_vec()));
+        assert_eq!(entry.resident_data("big").unwrap(), None);
+        assert_eq!(entry.resident_data("missing").unwrap(), None);
+        assert_eq!(entry.resident_streams().unwrap(), vec![
+            (String::new(), b"hello".to_vec()),
+            ("Zone.Identifier".to_string(), b"ZoneId=3".to_vec()),
+        ]);
+    }
+
+    #[test]
+    fn test_baad_and_zeroed_records() {
+        let mut fake_mft_entry = vec![0; 1024];
+        assert_eq!(MftEntry::parse(&fake_mft_entry).unwrap().signature, RecordSignature::Zeroed);
+
+        fake_mft_entry[..4].copy_from_slice(b"BAAD");
+        assert_eq!(MftEntry::parse(&fake_mft_entry).unwrap().signature, RecordSignature::Baad);
+
+        fake_mft_entry[..4].copy_from_slice(b"INDX");
+        assert!(MftEntry::parse(&fake_mft_entry).is_err());
+    }
+
+    #[test]
+    fn test_file_reference_decoding() {
+        let reference = FileReference::from_u64(0x0005_0000_0000_0024);
+        assert_eq!(reference, FileReference { record_number: 0x24, sequence_number: 5 });
+    }
+
+    #[test]
+    fn test_allocated_size_reading() {
+        let mut fake_mft_entry = vec![0; 4096];
+        fake_mft_entry[FILE_SIGNATURE_OFFSET..FILE_SIGNATURE_OFFSET + FILE_SIGNATURE_SIZE].copy_from_slice(b"FILE");
+        fake_mft_entry[FILE_ALLOCATED_SIZE_OFFSET..FILE_ALLOCATED_SIZE_OFFSET + 4].copy_from_slice(&4096u32.to_le_bytes());
+        assert_eq!(MftEntry::read_allocated_size(&fake_mft_entry), Some(4096));
+
+        // A size that is not a power of two is rejected
+        fake_mft_entry[FILE_ALLOCATED_SIZE_OFFSET..FILE_ALLOCATED_SIZE_OFFSET + 4].copy_from_slice(&1000u32.to_le_bytes());
+        assert_eq!(MftEntry::read_allocated_size(&fake_mft_entry), None);
+
+        // Anything that is not a FILE record is rejected
+        fake_mft_entry[FILE_SIGNATURE_OFFSET..FILE_SIGNATURE_OFFSET + FILE_SIGNATURE_SIZE].copy_from_slice(b"BAAD");
+        assert_eq!(MftEntry::read_allocated_size(&fake_mft_entry), None);
     }
 }
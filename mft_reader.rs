@@ -1,14 +1,31 @@
+use crate::attribute::ATTRIBUTE_TYPE_DATA;
+use crate::boot_sector::NtfsBootSector;
 use crate::config::Config;
-use crate::utils::{read_bytes, read_u16, read_u32, read_u64};
-use anyhow::{Context, Result};
+use crate::content_reader::ContentReader;
+use crate::logical_file::LogicalFile;
+use crate::mft_parser::MftEntry;
+use crate::runlist::Runlist;
+use crate::utils::read_bytes;
+use anyhow::{bail, Context, Result};
 use std::fs::File;
 use std::io::{Seek, SeekFrom};
+use thiserror::Error;
+
+// Number of bytes read from record 0 to find its allocated size
+const MFT_HEADER_PROBE_SIZE: usize = 64;
+
+// Define the errors that callers may want to handle explicitly
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum MftReaderError {
+    #[error("MFT record {record} lies beyond the mapped $MFT data runs ({record_count} records)")]
+    RecordOutOfRange { record: u64, record_count: u64 },
+}
 
 pub struct MftReader {
     file: File,
-    bytes_per_sector: u16,
-    sectors_per_cluster: u8,
-    mft_start_lcn: u64,
+    boot_sector: NtfsBootSector,
+    mft_record_size: u64,
+    mft_runs: Runlist,
 }
 
 impl MftReader {
@@ -16,51 +33,146 @@ impl MftReader {
         let mut file = File::open(&config.mft_file_path)
             .with_context(|| format!("Failed to open MFT file at {:?}", config.mft_file_path))?;
 
-        // Read necessary boot sector values to calculate the MFT offset
-        let bytes_per_sector = read_u16(&mut file, 11)?;
-        let sectors_per_cluster = read_bytes(&mut file, 13, 1)?[0];
-        let mft_start_lcn = read_u64(&mut file, 48)?;
+        // Read and validate the boot sector to calculate the MFT offset
+        let boot_sector = NtfsBootSector::read(&mut file)?;
+        let mut mft_record_size = boot_sector.mft_record_size();
+
+        // Prefer the allocated size stored in the $MFT record itself when it is readable,
+        // as it is what the file system actually used when laying out the records
+        let mft_offset = boot_sector.mft_lcn * boot_sector.cluster_size();
+        if let Ok(header) = read_bytes(&mut file, mft_offset, MFT_HEADER_PROBE_SIZE) {
+            if let Some(allocated_size) = MftEntry::read_allocated_size(&header) {
+                mft_record_size = allocated_size;
+            }
+        }
+
+        // Bootstrap from record 0, whose $DATA attribute maps every fragment of the $MFT
+        let mft_record = read_bytes(&mut file, mft_offset, mft_record_size as usize)
+            .with_context(|| "Failed to read the $MFT record")?;
+        let mft_entry = MftEntry::parse(&mft_record)
+            .with_context(|| "Failed to parse the $MFT record")?;
+        let mft_runs = read_mft_data_runs(&mft_entry)
+            .with_context(|| "Failed to decode the $MFT data runs")?;
 
         Ok(MftReader {
             file,
-            bytes_per_sector,
-            sectors_per_cluster,
-            mft_start_lcn,
+            boot_sector,
+            mft_record_size,
+            mft_runs,
         })
     }
 
-    pub fn read_mft_entry(&mut self, entry_index: u64) -> Result<Vec<u8>> {
-        // Calculate the offset of the MFT entry
-        let mft_offset = self.calculate_mft_offset(entry_index);
+    pub fn boot_sector(&self) -> &NtfsBootSector {
+        &self.boot_sector
+    }
 
-        // Seek to the MFT entry offset
-        self.file.seek(SeekFrom::Start(mft_offset))
-            .with_context(|| format!("Failed to seek to MFT entry at offset {}", mft_offset))?;
+    pub fn mft_record_size(&self) -> u64 {
+        self.mft_record_size
+    }
 
-        // Read the MFT entry - assuming the default size of an MFT entry is 1024 bytes
-        // This size can vary and should ideally be retrieved from the boot sector or $MFT record
-        let mft_entry_size = 1024;
-        let mft_entry = read_bytes(&mut self.file, mft_offset, mft_entry_size)?;
+    pub fn record_count(&self) -> u64 {
+        self.mft_runs.total_clusters() * self.boot_sector.cluster_size() / self.mft_record_size
+    }
+
+    pub fn read_mft_entry(&mut self, entry_index: u64) -> Result<Vec<u8>> {
+        let record_count = self.record_count();
+        if entry_index >= record_count {
+            return Err(MftReaderError::RecordOutOfRange { record: entry_index, record_count }.into());
+        }
+
+        // A record may straddle two fragments when clusters are smaller than records,
+        // so read it piece by piece following the runs
+        let mut mft_entry = Vec::with_capacity(self.mft_record_size as usize);
+        let mut logical_offset = entry_index * self.mft_record_size;
+        while (mft_entry.len() as u64) < self.mft_record_size {
+            let (mft_offset, contiguous) = self.calculate_mft_offset(logical_offset)?;
+            let remaining = self.mft_record_size - mft_entry.len() as u64;
+            let size = remaining.min(contiguous);
+
+            // Seek to the MFT entry offset
+            self.file.seek(SeekFrom::Start(mft_offset))
+                .with_context(|| format!("Failed to seek to MFT entry at offset {}", mft_offset))?;
+
+            mft_entry.extend(read_bytes(&mut self.file, mft_offset, size as usize)?);
+            logical_offset += size;
+        }
 
         Ok(mft_entry)
     }
 
-    fn calculate_mft_offset(&self, entry_index: u64) -> u64 {
-        // Calculate the byte offset of the MFT entry
-        // This assumes the default size of an MFT entry is 1024 bytes
-        let mft_entry_size = 1024;
-        let cluster_size = u64::from(self.bytes_per_sector) * u64::from(self.sectors_per_cluster);
-        let mft_offset = self.mft_start_lcn * cluster_size + entry_index * mft_entry_size;
+    // Read the content of a non-resident attribute into memory, zero-filling sparse runs.
+    // Only meant for small metadata attributes such as $ATTRIBUTE_LIST.
+    pub fn read_attribute_data(&mut self, runlist: &Runlist, size: u64) -> Result<Vec<u8>> {
+        let cluster_size = self.boot_sector.cluster_size();
+        if size > runlist.total_clusters() * cluster_size {
+            bail!("Attribute size {} exceeds the {} clusters mapped by its runlist", size, runlist.total_clusters());
+        }
+
+        let mut data = Vec::with_capacity(size as usize);
+        for run in &runlist.runs {
+            let remaining = size - data.len() as u64;
+            if remaining == 0 {
+                break;
+            }
+
+            let run_size = (run.cluster_count * cluster_size).min(remaining) as usize;
+            match run.lcn {
+                Some(lcn) => data.extend(read_bytes(&mut self.file, lcn * cluster_size, run_size)?),
+                None => data.resize(data.len() + run_size, 0),
+            }
+        }
+
+        Ok(data)
+    }
 
-        mft_offset
+    // Open a $DATA stream of a file for reading, using an empty name for the default stream.
+    // The content is read from the volume as the returned reader is consumed.
+    pub fn open_stream(&mut self, record_number: u64, stream_name: &str) -> Result<ContentReader<&mut File>> {
+        let entry_data = self.read_mft_entry(record_number)?;
+        let mft_entry = MftEntry::parse(&entry_data)
+            .with_context(|| format!("Failed to parse MFT entry {}", record_number))?;
+        if !mft_entry.is_base_record() {
+            bail!("MFT entry {} is an extension record of {}", record_number, mft_entry.base_record.record_number);
+        }
+
+        let file = LogicalFile::resolve(record_number, mft_entry, self)?;
+        let cluster_size = self.boot_sector.cluster_size();
+        ContentReader::new(&mut self.file, &file, stream_name, cluster_size)
+    }
+
+    fn calculate_mft_offset(&self, logical_offset: u64) -> Result<(u64, u64)> {
+        // Map the byte offset within the $MFT to its physical location on the volume
+        match self.mft_runs.map_offset(self.boot_sector.cluster_size(), logical_offset) {
+            Some(mapping) => Ok(mapping),
+            None => bail!("$MFT offset {} falls in a sparse or unmapped run", logical_offset),
+        }
     }
 }
 
+// Find the unnamed, non-resident $DATA attribute of the $MFT record and decode its runlist
+fn read_mft_data_runs(mft_entry: &MftEntry) -> Result<Runlist> {
+    for attribute in mft_entry.attributes() {
+        let attribute = attribute?;
+        if attribute.type_code != ATTRIBUTE_TYPE_DATA || !attribute.name.is_empty() {
+            continue;
+        }
+
+        if let Some(runlist) = attribute.runlist()? {
+            return Ok(runlist);
+        }
+    }
+
+    bail!("The $MFT record has no non-resident $DATA attribute")
+}
+
 // Add more methods as needed for your project.
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::mft_parser::FILE_RECORD_IN_USE;
+    use crate::runlist::DataRun;
+    use crate::test_support::{fake_file_record, fake_non_resident_attribute};
     use std::path::PathBuf;
 
     #[test]
@@ -68,6 +180,7 @@ mod tests {
         let config = Config {
             database_url: String::from("sqlite:mft_data.db"),
             mft_file_path: PathBuf::from("C:\\path\\to\\MFT"),
+            volume_root: String::from("C:"),
         };
 
         let mft_reader = MftReader::new(&config);
@@ -79,11 +192,24 @@ mod tests {
         let config = Config {
             database_url: String::from("sqlite:mft_data.db"),
             mft_file_path: PathBuf::from("C:\\path\\to\\MFT"),
+            volume_root: String::from("C:"),
         };
 
         let mut mft_reader = MftReader::new(&config).unwrap();
         let mft_entry = mft_reader.read_mft_entry(0);
         assert!(mft_entry.is_ok());
-        assert_eq!(mft_entry.unwrap().len(), 1024);
+        assert_eq!(mft_entry.unwrap().len() as u64, mft_reader.mft_record_size());
+    }
+
+    #[test]
+    fn test_mft_data_runs_bootstrap() {
+        // Build a fake $MFT record with a single non-resident $DATA attribute
+        let runlist = [0x31, 0x40, 0x00, 0x00, 0x0C, 0x21, 0x10, 0x00, 0x01];
+        let attribute = fake_non_resident_attribute(ATTRIBUTE_TYPE_DATA, "", &runlist, 0x50 * 4096);
+        let mft_entry = MftEntry::parse(&fake_file_record(0, FILE_RECORD_IN_USE, &attribute)).unwrap();
+
+        let runlist = read_mft_data_runs(&mft_entry).unwrap();
+        assert_eq!(runlist.runs.len(), 2);
+        assert_eq!(runlist.runs[1], DataRun { vcn: 0x40, cluster_count: 0x10, lcn: Some(0xC0100) });
     }
 }
@@ -0,0 +1,197 @@
+use crate::file_name::preferred_name;
+use crate::logical_file::LogicalFile;
+use crate::mft_parser::FileReference;
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+
+// The root directory of an NTFS volume always lives in record 5
+pub const ROOT_RECORD_NUMBER: u64 = 5;
+
+// Synthetic directory holding entries whose parent chain can't be trusted
+pub const ORPHAN_DIRECTORY: &str = "$Orphan";
+
+// Guard against parent reference cycles in corrupted volumes
+const MAX_PATH_DEPTH: usize = 1024;
+
+// Define a struct to hold what the resolver needs to know about each record
+#[derive(Debug, Clone)]
+struct PathNode {
+    sequence_number: u16,
+    in_use: bool,
+    name: String,
+    parent: FileReference,
+}
+
+// Define a struct that rebuilds full paths from $FILE_NAME parent references
+pub struct PathResolver {
+    root_prefix: String,
+    nodes: HashMap<u64, PathNode>,
+    // Memoized paths of directories that have already been walked
+    directory_paths: HashMap<u64, String>,
+}
+
+impl PathResolver {
+    // `root_prefix` is prepended to every path, for example "C:"
+    pub fn new(root_prefix: &str) -> Self {
+        PathResolver {
+            root_prefix: root_prefix.to_string(),
+            nodes: HashMap::new(),
+            directory_paths: HashMap::new(),
+        }
+    }
+
+    pub fn from_files(files: &[LogicalFile], root_prefix: &str) -> Result<Self> {
+        let mut resolver = PathResolver::new(root_prefix);
+        for file in files {
+            resolver.add_file(file)?;
+        }
+        Ok(resolver)
+    }
+
+    // Record the preferred name and parent of a file, keeping only what path resolution needs
+    pub fn add_file(&mut self, file: &LogicalFile) -> Result<()> {
+        let file_names = file.file_names()
+            .with_context(|| format!("Failed to read $FILE_NAME of MFT entry {}", file.record_number()))?;
+
+        if let Some(file_name) = preferred_name(&file_names) {
+            self.nodes.insert(file.record_number(), PathNode {
+                sequence_number: file.base.sequence_number,
+                in_use: file.base.is_in_use(),
+                name: file_name.name.clone(),
+                parent: file_name.parent,
+            });
+        }
+
+        Ok(())
+    }
+
+    // Whether the record of a file still holds that version of it, as a file in use
+    pub fn is_in_use(&self, file: FileReference) -> bool {
+        self.nodes.get(&file.record_number)
+            .is_some_and(|node| node.in_use && node.sequence_number == file.sequence_number)
+    }
+
+    // Resolve the full path of a record through its preferred name
+    pub fn resolve(&mut self, record_number: u64) -> String {
+        if record_number == ROOT_RECORD_NUMBER {
+            return format!("{}\\", self.root_prefix);
+        }
+
+        match self.nodes.get(&record_number).cloned() {
+            Some(node) => self.resolve_in_directory(node.parent, &node.name),
+            None => format!("{}\\{}\\{}", self.root_prefix, ORPHAN_DIRECTORY, record_number),
+        }
+    }
+
+    // Resolve the path a name has inside a given parent directory, such as one hard link
+    pub fn resolve_in_directory(&mut self, parent: FileReference, name: &str) -> String {
+        let directory = self.directory_path(parent);
+        format!("{}\\{}", directory, name)
+    }
+
+    // Walk parent references up to the root, falling back to the orphan directory when a parent
+    // is missing, no longer in use or has been reused for another file (sequence mismatch)
+    fn directory_path(&mut self, reference: FileReference) -> String {
+        let mut chain = Vec::new();
+        let mut current = reference;
+
+        let mut path = loop {
+            if current.record_number == ROOT_RECORD_NUMBER {
+                break self.root_prefix.clone();
+            }
+
+            let node = match self.nodes.get(&current.record_number) {
+                Some(node) if node.in_use && node.sequence_number == current.sequence_number => node,
+                _ => break format!("{}\\{}", self.root_prefix, ORPHAN_DIRECTORY),
+            };
+
+            if let Some(path) = self.directory_paths.get(&current.record_number) {
+                break path.clone();
+            }
+
+            if chain.contains(&current.record_number) || chain.len() >= MAX_PATH_DEPTH {
+                break format!("{}\\{}", self.root_prefix, ORPHAN_DIRECTORY);
+            }
+
+            chain.push(current.record_number);
+            current = node.parent;
+        };
+
+        // Build the path back down, memoizing every intermediate directory
+        for record_number in chain.iter().rev() {
+            path = format!("{}\\{}", path, self.nodes[record_number].name);
+            self.directory_paths.insert(*record_number, path.clone());
+        }
+
+        path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::attribute::ATTRIBUTE_TYPE_FILE_NAME;
+    use crate::data_structurer::DbEntry;
+    use crate::mft_parser::{MftEntry, FILE_RECORD_IN_USE, FILE_RECORD_IS_DIRECTORY};
+    use crate::test_support::{fake_file_name_value, fake_file_record, fake_resident_attribute, ROOT};
+
+    fn node(sequence_number: u16, name: &str, parent: u64, parent_sequence: u16) -> PathNode {
+        PathNode {
+            sequence_number,
+            in_use: true,
+            name: name.to_string(),
+            parent: FileReference { record_number: parent, sequence_number: parent_sequence },
+        }
+    }
+
+    #[test]
+    fn test_path_resolution() {
+        let mut resolver = PathResolver::new("C:");
+        resolver.nodes.insert(ROOT_RECORD_NUMBER, node(5, ".", 5, 5));
+        resolver.nodes.insert(40, node(1, "Users", 5, 5));
+        resolver.nodes.insert(41, node(2, "alice", 40, 1));
+        resolver.nodes.insert(42, node(1, "notes.txt", 41, 2));
+
+        assert_eq!(resolver.resolve(42), "C:\\Users\\alice\\notes.txt");
+        assert_eq!(resolver.resolve(ROOT_RECORD_NUMBER), "C:\\");
+        assert_eq!(resolver.directory_paths.get(&41).map(String::as_str), Some("C:\\Users\\alice"));
+    }
+
+    #[test]
+    fn test_orphaned_entries() {
+        let mut resolver = PathResolver::new("C:");
+        resolver.nodes.insert(40, node(3, "reused", 5, 5));
+        // Parent record 40 has been reused since this entry was written (sequence 2 != 3)
+        resolver.nodes.insert(41, node(1, "lost.txt", 40, 2));
+        // Parent record 50 no longer exists
+        resolver.nodes.insert(42, node(1, "gone.txt", 50, 1));
+        // Two directories pointing at each other
+        resolver.nodes.insert(43, node(1, "loop_a", 44, 1));
+        resolver.nodes.insert(44, node(1, "loop_b", 43, 1));
+
+        assert_eq!(resolver.resolve(41), "C:\\$Orphan\\lost.txt");
+        assert_eq!(resolver.resolve(42), "C:\\$Orphan\\gone.txt");
+        assert!(resolver.resolve(43).starts_with("C:\\$Orphan\\"));
+    }
+
+    #[test]
+    fn test_files_keyed_by_slot() {
+        // Neither record carries its number in its header, as on NTFS 3.0
+        let users = fake_resident_attribute(ATTRIBUTE_TYPE_FILE_NAME, "", &fake_file_name_value(ROOT, "Users", 1));
+        let users_reference = FileReference { record_number: 40, sequence_number: 1 };
+        let notes = fake_resident_attribute(ATTRIBUTE_TYPE_FILE_NAME, "", &fake_file_name_value(users_reference, "notes.txt", 1));
+        let file = |record_number: u64, flags: u16, attributes: &[u8]| {
+            let entry = MftEntry::parse(&fake_file_record(0, flags, attributes)).unwrap();
+            LogicalFile::from_records(record_number, entry, Vec::new()).unwrap()
+        };
+        let files = vec![
+            file(40, FILE_RECORD_IN_USE | FILE_RECORD_IS_DIRECTORY, &users),
+            file(41, FILE_RECORD_IN_USE, &notes),
+        ];
+
+        let mut resolver = PathResolver::from_files(&files, "C:").unwrap();
+        assert_eq!(resolver.resolve(41), "C:\\Users\\notes.txt");
+        assert!(!resolver.nodes.contains_key(&0));
+        assert_eq!(DbEntry::from_logical_file(&files[1], None).unwrap().record_number, 41);
+    }
+}
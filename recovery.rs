@@ -0,0 +1,215 @@
+use crate::attribute::{AttributeForm, ATTRIBUTE_TYPE_DATA};
+use crate::logical_file::LogicalFile;
+use crate::mft_reader::MftReader;
+use crate::runlist::Runlist;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::Read;
+
+// Record of the $Bitmap metadata file, which holds one bit per cluster of the volume
+pub const BITMAP_RECORD_NUMBER: u64 = 6;
+
+// Define a struct to hold the cluster allocation bitmap of a volume
+#[derive(Debug, Clone)]
+pub struct VolumeBitmap {
+    bits: Vec<u8>,
+}
+
+impl VolumeBitmap {
+    pub fn new(bits: Vec<u8>) -> Self {
+        VolumeBitmap { bits }
+    }
+
+    // Read the content of $Bitmap from the volume
+    pub fn read(reader: &mut MftReader) -> Result<Self> {
+        let mut bits = Vec::new();
+        reader.open_stream(BITMAP_RECORD_NUMBER, "")?
+            .read_to_end(&mut bits)
+            .with_context(|| "Failed to read $Bitmap")?;
+        Ok(VolumeBitmap { bits })
+    }
+
+    // Clusters past the end of the bitmap do not exist, so they count as allocated
+    pub fn is_allocated(&self, lcn: u64) -> bool {
+        self.bits.get((lcn / 8) as usize)
+            .is_none_or(|byte| byte & (1 << (lcn % 8)) != 0)
+    }
+
+    // Count how many clusters of a runlist are still free. Sparse runs have no clusters.
+    pub fn usage(&self, runlist: &Runlist) -> ClusterUsage {
+        let mut usage = ClusterUsage::default();
+        for run in &runlist.runs {
+            if let Some(lcn) = run.lcn {
+                // Clusters past the end of the bitmap are counted without visiting each of them
+                let end = lcn.saturating_add(run.cluster_count);
+                let mapped_end = end.min(self.bits.len() as u64 * 8).max(lcn);
+                for cluster in lcn..mapped_end {
+                    if self.is_allocated(cluster) {
+                        usage.reallocated += 1;
+                    } else {
+                        usage.free += 1;
+                    }
+                }
+                usage.reallocated += end - mapped_end;
+            }
+        }
+        usage
+    }
+}
+
+// Define a struct to hold how the clusters of a deleted file are used now
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClusterUsage {
+    // Still free, so still holding the file's data unless wiped
+    pub free: u64,
+    // Allocated again, most likely to another file
+    pub reallocated: u64,
+}
+
+// Define the verdicts on how much of a deleted file can be brought back
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecoveryVerdict {
+    Recoverable,
+    PartiallyRecoverable,
+    Unrecoverable,
+    // The clusters could not be checked, as $Bitmap was not available
+    Unknown,
+}
+
+impl fmt::Display for RecoveryVerdict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            RecoveryVerdict::Recoverable => "recoverable",
+            RecoveryVerdict::PartiallyRecoverable => "partially recoverable",
+            RecoveryVerdict::Unrecoverable => "unrecoverable",
+            RecoveryVerdict::Unknown => "unknown",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// Define a struct to hold what is left of a deleted file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecoveryAssessment {
+    pub sequence_number: u16,
+    // Whether every resident $DATA stream still holds content, None without resident streams
+    pub resident_data_intact: Option<bool>,
+    // Clusters of the non-resident $DATA streams, None when they exist but $Bitmap could not
+    // be checked
+    pub cluster_usage: Option<ClusterUsage>,
+    // Newest sequence number other metadata refers to the record by, such as directory
+    // index entries or the parent references of its children
+    pub referenced_sequence_number: Option<u16>,
+}
+
+impl RecoveryAssessment {
+    pub fn assess(file: &LogicalFile, bitmap: Option<&VolumeBitmap>) -> Result<Self> {
+        let mut resident_data_intact = None;
+        let mut cluster_usage = Some(ClusterUsage::default());
+
+        for attribute in &file.attributes {
+            if attribute.type_code != ATTRIBUTE_TYPE_DATA || attribute.starting_vcn() != 0 {
+                continue;
+            }
+
+            match &attribute.form {
+                // A value wiped by a secure delete tool reads as zeros
+                AttributeForm::Resident { value, .. } => {
+                    let intact = value.is_empty() || value.iter().any(|&byte| byte != 0);
+                    resident_data_intact = Some(resident_data_intact.unwrap_or(true) && intact);
+                }
+                AttributeForm::NonResident { .. } => match (bitmap, cluster_usage.as_mut()) {
+                    (Some(bitmap), Some(usage)) => {
+                        let runlist = file.runlist(ATTRIBUTE_TYPE_DATA, &attribute.name)?.unwrap_or_default();
+                        let stream_usage = bitmap.usage(&runlist);
+                        usage.free += stream_usage.free;
+                        usage.reallocated += stream_usage.reallocated;
+                    }
+                    _ => cluster_usage = None,
+                },
+            }
+        }
+
+        Ok(RecoveryAssessment {
+            sequence_number: file.base.sequence_number,
+            resident_data_intact,
+            cluster_usage,
+            referenced_sequence_number: None,
+        })
+    }
+
+    // NTFS advances the sequence number of a record when freeing it, so references made while
+    // the file existed carry an older one. None when nothing refers to the record.
+    pub fn sequence_bumped(&self) -> Option<bool> {
+        self.referenced_sequence_number
+            .map(|referenced| self.sequence_number != referenced)
+    }
+
+    pub fn verdict(&self) -> RecoveryVerdict {
+        let data_verdict = match (self.resident_data_intact, self.cluster_usage) {
+            (Some(false), _) => RecoveryVerdict::Unrecoverable,
+            (_, Some(usage)) if usage.reallocated == 0 => RecoveryVerdict::Recoverable,
+            (_, Some(usage)) if usage.free == 0 => RecoveryVerdict::Unrecoverable,
+            (_, Some(_)) => RecoveryVerdict::PartiallyRecoverable,
+            (_, None) => RecoveryVerdict::Unknown,
+        };
+
+        // A record freed without its sequence number advancing was not released by a normal
+        // delete, so its content may not match what the file last held
+        match (data_verdict, self.sequence_bumped()) {
+            (RecoveryVerdict::Recoverable, Some(false)) => RecoveryVerdict::PartiallyRecoverable,
+            (verdict, _) => verdict,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runlist::DataRun;
+    use crate::test_support::{fake_logical_file, fake_non_resident_attribute, fake_resident_attribute};
+
+    #[test]
+    fn test_cluster_usage_verdicts() {
+        // Clusters 0x10-0x13, then a sparse run of 2 clusters
+        let file = fake_logical_file(80, 0, &fake_non_resident_attribute(ATTRIBUTE_TYPE_DATA, "", &[0x11, 0x04, 0x10, 0x01, 0x02], 6 * 4096));
+        // Clusters 0x10 and 0x11 have been allocated again
+        let mut bits = vec![0; 4];
+        bits[2] = 0b0000_0011;
+        let bitmap = VolumeBitmap::new(bits);
+
+        let assessment = RecoveryAssessment::assess(&file, Some(&bitmap)).unwrap();
+        assert_eq!(assessment.cluster_usage, Some(ClusterUsage { free: 2, reallocated: 2 }));
+        assert_eq!(assessment.resident_data_intact, None);
+        assert_eq!(assessment.verdict(), RecoveryVerdict::PartiallyRecoverable);
+
+        let bitmap = VolumeBitmap::new(vec![0; 4]);
+        assert_eq!(RecoveryAssessment::assess(&file, Some(&bitmap)).unwrap().verdict(), RecoveryVerdict::Recoverable);
+        // Clusters beyond the bitmap do not exist
+        let bitmap = VolumeBitmap::new(vec![0; 2]);
+        assert_eq!(RecoveryAssessment::assess(&file, Some(&bitmap)).unwrap().verdict(), RecoveryVerdict::Unrecoverable);
+        let huge_run = Runlist { runs: vec![DataRun { vcn: 0, cluster_count: u64::MAX, lcn: Some(8) }] };
+        assert_eq!(bitmap.usage(&huge_run), ClusterUsage { free: 8, reallocated: u64::MAX - 16 });
+        assert_eq!(RecoveryAssessment::assess(&file, None).unwrap().verdict(), RecoveryVerdict::Unknown);
+    }
+
+    #[test]
+    fn test_resident_and_sequence_verdicts() {
+        let file = fake_logical_file(80, 0, &fake_resident_attribute(ATTRIBUTE_TYPE_DATA, "", b"meeting notes"));
+        let mut assessment = RecoveryAssessment::assess(&file, None).unwrap();
+        assert_eq!(assessment.resident_data_intact, Some(true));
+        assert_eq!(assessment.verdict(), RecoveryVerdict::Recoverable);
+
+        // Referred to by an older sequence number, as after a normal delete
+        assessment.referenced_sequence_number = Some(0);
+        assert_eq!(assessment.sequence_bumped(), Some(true));
+        assert_eq!(assessment.verdict(), RecoveryVerdict::Recoverable);
+        // Still referred to by its current sequence number
+        assessment.referenced_sequence_number = Some(1);
+        assert_eq!(assessment.verdict(), RecoveryVerdict::PartiallyRecoverable);
+
+        let wiped = fake_logical_file(80, 0, &fake_resident_attribute(ATTRIBUTE_TYPE_DATA, "", &[0; 16]));
+        assert_eq!(RecoveryAssessment::assess(&wiped, None).unwrap().verdict(), RecoveryVerdict::Unrecoverable);
+    }
+}
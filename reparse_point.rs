@@ -0,0 +1,195 @@
+use crate::attribute::decode_utf16;
+use crate::wof::IO_REPARSE_TAG_WOF;
+use anyhow::{bail, Result};
+use byteorder::{ByteOrder, LittleEndian};
+
+// Define the reparse tags that are decoded
+pub const IO_REPARSE_TAG_MOUNT_POINT: u32 = 0xA000_0003;
+pub const IO_REPARSE_TAG_SYMLINK: u32 = 0xA000_000C;
+pub const IO_REPARSE_TAG_DEDUP: u32 = 0x8000_0013;
+pub const IO_REPARSE_TAG_APPEXECLINK: u32 = 0x8000_001B;
+// Cloud files tags share their low bits, the 0xF000 bits hold the provider class
+pub const IO_REPARSE_TAG_CLOUD: u32 = 0x9000_001A;
+const IO_REPARSE_TAG_CLOUD_MASK: u32 = 0x0000_F000;
+
+// Define constants for reparse data offsets and sizes
+pub(crate) const REPARSE_TAG_OFFSET: usize = 0x00;
+pub(crate) const REPARSE_DATA_LENGTH_OFFSET: usize = 0x04;
+pub(crate) const REPARSE_HEADER_SIZE: usize = 0x08;
+const SUBSTITUTE_NAME_OFFSET_OFFSET: usize = 0x08;
+pub(crate) const SUBSTITUTE_NAME_LENGTH_OFFSET: usize = 0x0A;
+pub(crate) const PRINT_NAME_OFFSET_OFFSET: usize = 0x0C;
+pub(crate) const PRINT_NAME_LENGTH_OFFSET: usize = 0x0E;
+pub(crate) const SYMLINK_FLAGS_OFFSET: usize = 0x10;
+pub(crate) const MOUNT_POINT_PATH_BUFFER_OFFSET: usize = 0x10;
+pub(crate) const SYMLINK_PATH_BUFFER_OFFSET: usize = 0x14;
+const SYMLINK_FLAG_RELATIVE: u32 = 0x0000_0001;
+
+// NT namespace prefix of absolute substitute names, as in \??\C:\target
+const NT_PATH_PREFIX: &str = "\\??\\";
+
+// Define what a reparse point redirects to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReparseData {
+    // A junction or volume mount point
+    MountPoint { substitute_name: String, print_name: String },
+    SymbolicLink { substitute_name: String, print_name: String, relative: bool },
+    // A cloud files placeholder, such as a OneDrive file that may not be downloaded
+    CloudFiles { provider_class: u8 },
+    // A tag whose data is not decoded, such as WOF compression or deduplication
+    Other,
+}
+
+// Define a struct to hold a parsed $REPARSE_POINT attribute
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReparsePoint {
+    pub tag: u32,
+    pub data: ReparseData,
+}
+
+impl ReparsePoint {
+    pub fn parse(value: &[u8]) -> Result<Self> {
+        if value.len() < REPARSE_HEADER_SIZE {
+            bail!("$REPARSE_POINT of {} bytes is too short", value.len());
+        }
+
+        let tag = LittleEndian::read_u32(&value[REPARSE_TAG_OFFSET..]);
+        let data_length = usize::from(LittleEndian::read_u16(&value[REPARSE_DATA_LENGTH_OFFSET..]));
+        if REPARSE_HEADER_SIZE + data_length > value.len() {
+            bail!("Reparse data of {} bytes runs past the end of the attribute", data_length);
+        }
+        let value = &value[..REPARSE_HEADER_SIZE + data_length];
+
+        let data = match tag {
+            IO_REPARSE_TAG_MOUNT_POINT => {
+                let (substitute_name, print_name) = read_names(value, MOUNT_POINT_PATH_BUFFER_OFFSET)?;
+                ReparseData::MountPoint { substitute_name, print_name }
+            }
+            IO_REPARSE_TAG_SYMLINK => {
+                if value.len() < SYMLINK_PATH_BUFFER_OFFSET {
+                    bail!("Symbolic link reparse data of {} bytes is too short", value.len());
+                }
+                let (substitute_name, print_name) = read_names(value, SYMLINK_PATH_BUFFER_OFFSET)?;
+                let flags = LittleEndian::read_u32(&value[SYMLINK_FLAGS_OFFSET..]);
+                ReparseData::SymbolicLink { substitute_name, print_name, relative: flags & SYMLINK_FLAG_RELATIVE != 0 }
+            }
+            tag => undecoded_data(tag),
+        };
+
+        Ok(ReparsePoint { tag, data })
+    }
+
+    // Keep only the tag of reparse data too damaged to parse, so the kind of reparse point is
+    // still known even though where it points is not
+    pub fn from_tag(value: &[u8]) -> Option<Self> {
+        if value.len() < REPARSE_TAG_OFFSET + 4 {
+            return None;
+        }
+        let tag = LittleEndian::read_u32(&value[REPARSE_TAG_OFFSET..]);
+        Some(ReparsePoint { tag, data: undecoded_data(tag) })
+    }
+
+    // Where a link points, preferring the name meant for display. Absolute substitute names are
+    // stripped of their NT namespace prefix.
+    pub fn target(&self) -> Option<String> {
+        match &self.data {
+            ReparseData::MountPoint { substitute_name, print_name }
+            | ReparseData::SymbolicLink { substitute_name, print_name, .. } => {
+                if !print_name.is_empty() {
+                    Some(print_name.clone())
+                } else {
+                    Some(substitute_name.strip_prefix(NT_PATH_PREFIX).unwrap_or(substitute_name).to_string())
+                }
+            }
+            _ => None,
+        }
+    }
+
+    // Readable name of the tag, falling back to its value for unknown tags
+    pub fn tag_name(&self) -> String {
+        match (self.tag, &self.data) {
+            (IO_REPARSE_TAG_MOUNT_POINT, _) => "MOUNT_POINT".to_string(),
+            (IO_REPARSE_TAG_SYMLINK, _) => "SYMLINK".to_string(),
+            (IO_REPARSE_TAG_WOF, _) => "WOF".to_string(),
+            (IO_REPARSE_TAG_DEDUP, _) => "DEDUP".to_string(),
+            (IO_REPARSE_TAG_APPEXECLINK, _) => "APPEXECLINK".to_string(),
+            (_, ReparseData::CloudFiles { provider_class: 0 }) => "CLOUD".to_string(),
+            (_, ReparseData::CloudFiles { provider_class }) => format!("CLOUD_{:X}", provider_class),
+            (tag, _) => format!("{:#010x}", tag),
+        }
+    }
+}
+
+// What the tag alone tells about reparse data that isn't decoded
+fn undecoded_data(tag: u32) -> ReparseData {
+    if tag & !IO_REPARSE_TAG_CLOUD_MASK == IO_REPARSE_TAG_CLOUD {
+        ReparseData::CloudFiles { provider_class: ((tag & IO_REPARSE_TAG_CLOUD_MASK) >> 12) as u8 }
+    } else {
+        ReparseData::Other
+    }
+}
+
+// Read the substitute and print names, whose offsets are relative to the path buffer
+fn read_names(value: &[u8], path_buffer_offset: usize) -> Result<(String, String)> {
+    if value.len() < path_buffer_offset {
+        bail!("Reparse data of {} bytes is too short for its name offsets", value.len());
+    }
+
+    let read_name = |offset_offset: usize, length_offset: usize| -> Result<String> {
+        let start = path_buffer_offset + usize::from(LittleEndian::read_u16(&value[offset_offset..]));
+        let end = start + usize::from(LittleEndian::read_u16(&value[length_offset..]));
+        match value.get(start..end) {
+            Some(name) => Ok(decode_utf16(name)),
+            None => bail!("Reparse name at offset {} runs past the end of the reparse data", start),
+        }
+    };
+
+    Ok((
+        read_name(SUBSTITUTE_NAME_OFFSET_OFFSET, SUBSTITUTE_NAME_LENGTH_OFFSET)?,
+        read_name(PRINT_NAME_OFFSET_OFFSET, PRINT_NAME_LENGTH_OFFSET)?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::fake_link_reparse_data;
+
+    #[test]
+    fn test_link_decoding() {
+        let junction = ReparsePoint::parse(&fake_link_reparse_data(IO_REPARSE_TAG_MOUNT_POINT, "\\??\\C:\\Users\\Public", "", 0)).unwrap();
+        assert_eq!(junction.data, ReparseData::MountPoint {
+            substitute_name: "\\??\\C:\\Users\\Public".to_string(),
+            print_name: String::new(),
+        });
+        assert_eq!(junction.target().as_deref(), Some("C:\\Users\\Public"));
+        assert_eq!(junction.tag_name(), "MOUNT_POINT");
+
+        let symlink = ReparsePoint::parse(&fake_link_reparse_data(IO_REPARSE_TAG_SYMLINK, "..\\shared", "..\\shared", SYMLINK_FLAG_RELATIVE)).unwrap();
+        assert!(matches!(symlink.data, ReparseData::SymbolicLink { relative: true, .. }));
+        assert_eq!(symlink.target().as_deref(), Some("..\\shared"));
+
+        // A name running past the reparse data
+        let mut truncated = fake_link_reparse_data(IO_REPARSE_TAG_SYMLINK, "target", "target", 0);
+        LittleEndian::write_u16(&mut truncated[PRINT_NAME_LENGTH_OFFSET..], 0x100);
+        assert!(ReparsePoint::parse(&truncated).is_err());
+        let damaged = ReparsePoint::from_tag(&truncated).unwrap();
+        assert_eq!((damaged.tag_name(), damaged.target()), ("SYMLINK".to_string(), None));
+        assert_eq!(ReparsePoint::from_tag(&truncated[..2]), None);
+    }
+
+    #[test]
+    fn test_cloud_and_other_tags() {
+        let mut value = vec![0; REPARSE_HEADER_SIZE];
+        LittleEndian::write_u32(&mut value[REPARSE_TAG_OFFSET..], 0x9000_601A);
+        let cloud = ReparsePoint::parse(&value).unwrap();
+        assert_eq!(cloud.data, ReparseData::CloudFiles { provider_class: 6 });
+        assert_eq!(cloud.tag_name(), "CLOUD_6");
+        assert_eq!(cloud.target(), None);
+
+        LittleEndian::write_u32(&mut value[REPARSE_TAG_OFFSET..], IO_REPARSE_TAG_DEDUP);
+        let dedup = ReparsePoint::parse(&value).unwrap();
+        assert_eq!(dedup.data, ReparseData::Other);
+        assert_eq!(dedup.tag_name(), "DEDUP");
+    }
+}
@@ -0,0 +1,103 @@
+use crate::file_name::preferred_name;
+use crate::mft_parser::MftEntry;
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Keep generated names well below the path limits of common file systems
+const MAX_FILE_NAME_LENGTH: usize = 200;
+
+// Build a file name that is safe to create in the output directory. The record number keeps
+// names unique, and anything that could escape the directory or upset the host file system
+// (separators, drive colons, control characters, leading dots) is replaced.
+pub fn safe_file_name(record_number: u64, file_name: &str, stream_name: &str) -> String {
+    let mut name = format!("{}_{}", record_number, sanitize(file_name));
+    if !stream_name.is_empty() {
+        name = format!("{}_{}", name, sanitize(stream_name));
+    }
+
+    name.chars().take(MAX_FILE_NAME_LENGTH).collect()
+}
+
+fn sanitize(name: &str) -> String {
+    let sanitized: String = name.chars()
+        .map(|c| if c.is_alphanumeric() || matches!(c, '.' | '-' | '_' | ' ') { c } else { '_' })
+        .collect();
+    let sanitized = sanitized.trim_start_matches('.').trim_end_matches([' ', '.']);
+
+    if sanitized.is_empty() {
+        "unnamed".to_string()
+    } else {
+        sanitized.to_string()
+    }
+}
+
+// Define a struct that writes the resident content of MFT records to a directory
+pub struct ResidentExtractor {
+    output_directory: PathBuf,
+}
+
+impl ResidentExtractor {
+    pub fn new(output_directory: &Path) -> Result<Self> {
+        fs::create_dir_all(output_directory)
+            .with_context(|| format!("Failed to create output directory {:?}", output_directory))?;
+
+        Ok(ResidentExtractor { output_directory: output_directory.to_path_buf() })
+    }
+
+    // Write every resident $DATA stream of the record, returning the paths written. Streams
+    // stored in clusters are skipped, as their content is not part of the $MFT. Files are named
+    // after `record_number`, the slot the record was read from, as records written by NTFS 3.0
+    // don't carry their own number.
+    pub fn extract(&self, record_number: u64, entry: &MftEntry) -> Result<Vec<PathBuf>> {
+        let file_names = entry.file_names()
+            .with_context(|| format!("Failed to read $FILE_NAME of MFT entry {}", record_number))?;
+        let file_name = preferred_name(&file_names).map_or("", |file_name| file_name.name.as_str());
+
+        let streams = entry.resident_streams()
+            .with_context(|| format!("Failed to read $DATA of MFT entry {}", record_number))?;
+
+        let mut written = Vec::new();
+        for (stream_name, content) in streams {
+            let path = self.output_directory.join(safe_file_name(record_number, file_name, &stream_name));
+            fs::write(&path, content)
+                .with_context(|| format!("Failed to write resident content to {:?}", path))?;
+            written.push(path);
+        }
+
+        Ok(written)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::attribute::{ATTRIBUTE_TYPE_DATA, ATTRIBUTE_TYPE_FILE_NAME};
+    use crate::mft_parser::FILE_RECORD_IN_USE;
+    use crate::test_support::{fake_file_name_value, fake_file_record, fake_resident_attribute, ROOT};
+
+    #[test]
+    fn test_safe_file_naming() {
+        assert_eq!(safe_file_name(42, "notes.txt", ""), "42_notes.txt");
+        assert_eq!(safe_file_name(42, "notes.txt", "Zone.Identifier"), "42_notes.txt_Zone.Identifier");
+        assert_eq!(safe_file_name(7, "..\\..\\Windows\\evil:bat", ""), "7__.._Windows_evil_bat");
+        assert_eq!(safe_file_name(7, "..", ""), "7_unnamed");
+        assert_eq!(safe_file_name(7, &"a".repeat(500), "").len(), MAX_FILE_NAME_LENGTH);
+    }
+
+    #[test]
+    fn test_files_named_after_record_slot() {
+        let attributes = [
+            fake_resident_attribute(ATTRIBUTE_TYPE_FILE_NAME, "", &fake_file_name_value(ROOT, "notes.txt", 1)),
+            fake_resident_attribute(ATTRIBUTE_TYPE_DATA, "", b"meeting notes"),
+        ].concat();
+        // Written by NTFS 3.0, so the header doesn't hold the record number
+        let entry = MftEntry::parse(&fake_file_record(0, FILE_RECORD_IN_USE, &attributes)).unwrap();
+
+        let directory = std::env::temp_dir().join(format!("ntfs-mft-extract-test-{}", std::process::id()));
+        let written = ResidentExtractor::new(&directory).unwrap().extract(42, &entry).unwrap();
+        assert_eq!(written, vec![directory.join("42_notes.txt")]);
+        assert_eq!(fs::read(&written[0]).unwrap(), b"meeting notes");
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
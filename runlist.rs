@@ -0,0 +1,276 @@
+use anyhow::{bail, Result};
+
+// Define a struct to hold a single decoded data run, an extent of consecutive VCNs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataRun {
+    pub vcn: u64,
+    pub cluster_count: u64,
+    // None for sparse runs, which have no clusters allocated on disk
+    pub lcn: Option<u64>,
+}
+
+impl DataRun {
+    pub fn is_sparse(&self) -> bool {
+        self.lcn.is_none()
+    }
+
+    // The first VCN after this run
+    pub fn end_vcn(&self) -> u64 {
+        self.vcn + self.cluster_count
+    }
+}
+
+// Define how a single compression unit of a compressed attribute is stored
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CompressionUnit {
+    // Every cluster of the unit is allocated, so the data is stored uncompressed
+    Uncompressed { vcn: u64 },
+    // Only part of the unit is allocated; the sparse tail is padding, not a hole
+    Compressed { vcn: u64, allocated_clusters: u64 },
+    // No cluster of the unit is allocated, so it reads as zeros
+    Sparse { vcn: u64 },
+}
+
+// Define a struct to hold the decoded runlist of a non-resident attribute
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Runlist {
+    pub runs: Vec<DataRun>,
+}
+
+impl Runlist {
+    // Decode a runlist into data runs. Each run starts with a header byte whose low nibble is
+    // the size of the length field and high nibble the size of the signed, relative LCN offset.
+    pub fn decode(data: &[u8], starting_vcn: u64) -> Result<Self> {
+        let mut runs = Vec::new();
+        let mut offset = 0;
+        let mut vcn = starting_vcn;
+        let mut lcn = 0i64;
+
+        while offset < data.len() && data[offset] != 0 {
+            let header = data[offset];
+            let length_size = usize::from(header & 0x0F);
+            let offset_size = usize::from(header >> 4);
+            offset += 1;
+
+            if length_size == 0 || length_size > 8 || offset_size > 8 {
+                bail!("Invalid data run header {:#04x} at offset {}", header, offset - 1);
+            }
+            if offset + length_size + offset_size > data.len() {
+                bail!("Data run at offset {} runs past the end of the runlist", offset - 1);
+            }
+
+            let cluster_count = read_unsigned(&data[offset..offset + length_size]);
+            offset += length_size;
+            if cluster_count == 0 {
+                bail!("Data run at VCN {} has a zero length", vcn);
+            }
+
+            // A run without an offset field is sparse and leaves the running LCN untouched
+            let run_lcn = if offset_size == 0 {
+                None
+            } else {
+                let Some(next_lcn) = lcn.checked_add(read_signed(&data[offset..offset + offset_size])) else {
+                    bail!("Data run at VCN {} has an LCN out of range", vcn);
+                };
+                lcn = next_lcn;
+                offset += offset_size;
+                if lcn < 0 {
+                    bail!("Data run at VCN {} has a negative LCN {}", vcn, lcn);
+                }
+                if (lcn as u64).checked_add(cluster_count).is_none() {
+                    bail!("Data run at VCN {} runs past the last LCN", vcn);
+                }
+                Some(lcn as u64)
+            };
+
+            let Some(next_vcn) = vcn.checked_add(cluster_count) else {
+                bail!("Data run at VCN {} runs past the last VCN", vcn);
+            };
+            runs.push(DataRun { vcn, cluster_count, lcn: run_lcn });
+            vcn = next_vcn;
+        }
+
+        Ok(Runlist { runs })
+    }
+
+    // Number of clusters covered by the runlist, including sparse runs
+    pub fn total_clusters(&self) -> u64 {
+        self.runs.iter().map(|run| run.cluster_count).sum()
+    }
+
+    // Number of clusters actually allocated on disk
+    pub fn allocated_clusters(&self) -> u64 {
+        self.runs.iter().filter(|run| !run.is_sparse()).map(|run| run.cluster_count).sum()
+    }
+
+    // Number of physically discontiguous extents, merging runs that continue on disk
+    pub fn fragment_count(&self) -> usize {
+        let mut fragments = 0;
+        let mut next_lcn = None;
+
+        for run in &self.runs {
+            if let Some(lcn) = run.lcn {
+                if next_lcn != Some(lcn) {
+                    fragments += 1;
+                }
+                next_lcn = Some(lcn + run.cluster_count);
+            }
+        }
+
+        fragments
+    }
+
+    // Append the runs of a later attribute fragment, which must continue at the next VCN
+    pub fn extend(&mut self, other: Runlist) -> Result<()> {
+        let next_vcn = self.runs.last().map_or(0, DataRun::end_vcn);
+        if let Some(first) = other.runs.first() {
+            if first.vcn != next_vcn {
+                bail!("Runlist fragment starts at VCN {}, expected VCN {}", first.vcn, next_vcn);
+            }
+        }
+
+        self.runs.extend(other.runs);
+        Ok(())
+    }
+
+    // Find the run holding a VCN, sparse or not. Returns None for VCNs the runlist doesn't map.
+    pub fn run_at(&self, vcn: u64) -> Option<&DataRun> {
+        self.runs.iter().find(|run| vcn >= run.vcn && vcn < run.end_vcn())
+    }
+
+    // Map a byte offset within the attribute to a physical byte offset on the volume, along with
+    // the number of bytes that are contiguous on disk from that point. Returns None for offsets
+    // that fall in a sparse run or beyond the last run.
+    pub fn map_offset(&self, cluster_size: u64, byte_offset: u64) -> Option<(u64, u64)> {
+        let run = self.run_at(byte_offset / cluster_size)?;
+        let lcn = run.lcn?;
+
+        let run_start = run.vcn * cluster_size;
+        let run_end = run.end_vcn() * cluster_size;
+        let physical_offset = lcn * cluster_size + (byte_offset - run_start);
+
+        Some((physical_offset, run_end - byte_offset))
+    }
+
+    // Split a compressed attribute into compression units of `unit_clusters` clusters,
+    // classifying each by how many of its clusters are allocated
+    pub fn compression_units(&self, unit_clusters: u64) -> Vec<CompressionUnit> {
+        let total_clusters = self.runs.last().map_or(0, DataRun::end_vcn);
+        let first_vcn = self.runs.first().map_or(0, |run| run.vcn);
+        let mut units = Vec::new();
+
+        let mut vcn = first_vcn - first_vcn % unit_clusters;
+        while vcn < total_clusters {
+            units.push(self.compression_unit(vcn, unit_clusters));
+            vcn += unit_clusters;
+        }
+
+        units
+    }
+
+    // Classify the single compression unit starting at `vcn`, looking only at the runs that
+    // overlap it
+    pub fn compression_unit(&self, vcn: u64, unit_clusters: u64) -> CompressionUnit {
+        let unit_end = vcn + unit_clusters;
+        let first_run = self.runs.partition_point(|run| run.end_vcn() <= vcn);
+        let allocated_clusters: u64 = self.runs[first_run..].iter()
+            .take_while(|run| run.vcn < unit_end)
+            .filter(|run| !run.is_sparse())
+            .map(|run| run.end_vcn().min(unit_end) - run.vcn.max(vcn))
+            .sum();
+
+        match allocated_clusters {
+            0 => CompressionUnit::Sparse { vcn },
+            clusters if clusters >= unit_clusters => CompressionUnit::Uncompressed { vcn },
+            clusters => CompressionUnit::Compressed { vcn, allocated_clusters: clusters },
+        }
+    }
+}
+
+fn read_unsigned(bytes: &[u8]) -> u64 {
+    bytes.iter().rev().fold(0, |value, &byte| (value << 8) | u64::from(byte))
+}
+
+fn read_signed(bytes: &[u8]) -> i64 {
+    let value = read_unsigned(bytes);
+    let shift = 64 - 8 * bytes.len() as u32;
+    ((value << shift) as i64) >> shift
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_runlist_decoding() {
+        // 0x40 clusters at LCN 0xC0000, then 0x10 clusters 0x100 clusters further on,
+        // then 8 clusters 0x20 clusters back from there, then a sparse run of 4 clusters
+        let runlist = [0x31, 0x40, 0x00, 0x00, 0x0C, 0x21, 0x10, 0x00, 0x01, 0x11, 0x08, 0xE0, 0x01, 0x04, 0x00];
+        let runlist = Runlist::decode(&runlist, 0).unwrap();
+
+        assert_eq!(runlist.runs, vec![
+            DataRun { vcn: 0, cluster_count: 0x40, lcn: Some(0xC0000) },
+            DataRun { vcn: 0x40, cluster_count: 0x10, lcn: Some(0xC0100) },
+            DataRun { vcn: 0x50, cluster_count: 0x08, lcn: Some(0xC00E0) },
+            DataRun { vcn: 0x58, cluster_count: 0x04, lcn: None },
+        ]);
+        assert_eq!(runlist.total_clusters(), 0x5C);
+        assert_eq!(runlist.allocated_clusters(), 0x58);
+        assert_eq!(runlist.fragment_count(), 3);
+    }
+
+    #[test]
+    fn test_overflowing_runs_rejected() {
+        // A sparse run of 2^64 - 1 clusters only fits when it starts at VCN 0
+        let runlist = [0x08, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x00];
+        assert!(Runlist::decode(&runlist, 0).is_ok());
+        assert!(Runlist::decode(&runlist, 1).is_err());
+
+        // The largest LCN, moved on by one more cluster
+        let runlist = [0x81, 0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x7F, 0x11, 0x01, 0x01, 0x00];
+        assert!(Runlist::decode(&runlist, 0).is_err());
+
+        // A run ending past the largest LCN
+        let runlist = [0x88, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x7F, 0x00];
+        assert!(Runlist::decode(&runlist, 0).is_err());
+    }
+
+    #[test]
+    fn test_offset_mapping_across_fragments() {
+        let runlist = Runlist {
+            runs: vec![
+                DataRun { vcn: 0, cluster_count: 2, lcn: Some(100) },
+                DataRun { vcn: 2, cluster_count: 1, lcn: None },
+                DataRun { vcn: 3, cluster_count: 4, lcn: Some(50) },
+            ],
+        };
+
+        assert_eq!(runlist.map_offset(4096, 1024), Some((100 * 4096 + 1024, 8192 - 1024)));
+        assert_eq!(runlist.map_offset(4096, 3 * 4096 + 2048), Some((50 * 4096 + 2048, 4 * 4096 - 2048)));
+        assert_eq!(runlist.map_offset(4096, 2 * 4096), None);
+        assert_eq!(runlist.map_offset(4096, 7 * 4096), None);
+    }
+
+    #[test]
+    fn test_compression_unit_classification() {
+        // One compressed unit (5 clusters + 11 padding), one uncompressed unit, one sparse unit
+        let runlist = Runlist {
+            runs: vec![
+                DataRun { vcn: 0, cluster_count: 5, lcn: Some(200) },
+                DataRun { vcn: 5, cluster_count: 11, lcn: None },
+                DataRun { vcn: 16, cluster_count: 16, lcn: Some(300) },
+                DataRun { vcn: 32, cluster_count: 16, lcn: None },
+            ],
+        };
+
+        assert_eq!(runlist.compression_units(16), vec![
+            CompressionUnit::Compressed { vcn: 0, allocated_clusters: 5 },
+            CompressionUnit::Uncompressed { vcn: 16 },
+            CompressionUnit::Sparse { vcn: 32 },
+        ]);
+        assert_eq!(runlist.compression_unit(16, 16), CompressionUnit::Uncompressed { vcn: 16 });
+        assert_eq!(runlist.compression_unit(48, 16), CompressionUnit::Sparse { vcn: 48 });
+        // Two fragments, as the padding is not a gap between clusters on disk
+        assert_eq!(runlist.fragment_count(), 2);
+    }
+}
@@ -0,0 +1,132 @@
+use crate::attribute::Attribute;
+use crate::utils::filetime_to_datetime;
+use anyhow::{bail, Context, Result};
+use byteorder::{ByteOrder, LittleEndian};
+use chrono::{DateTime, Utc};
+
+// Define constants for $STANDARD_INFORMATION offsets and sizes
+pub(crate) const SI_CREATED_OFFSET: usize = 0x00;
+pub(crate) const SI_MODIFIED_OFFSET: usize = 0x08;
+pub(crate) const SI_MFT_MODIFIED_OFFSET: usize = 0x10;
+pub(crate) const SI_ACCESSED_OFFSET: usize = 0x18;
+pub(crate) const SI_FILE_ATTRIBUTES_OFFSET: usize = 0x20;
+const SI_MAX_VERSIONS_OFFSET: usize = 0x24;
+const SI_VERSION_NUMBER_OFFSET: usize = 0x28;
+const SI_CLASS_ID_OFFSET: usize = 0x2C;
+const SI_OWNER_ID_OFFSET: usize = 0x30;
+const SI_SECURITY_ID_OFFSET: usize = 0x34;
+const SI_QUOTA_CHARGED_OFFSET: usize = 0x38;
+const SI_USN_OFFSET: usize = 0x40;
+
+// NTFS 1.2 stops after the class id, NTFS 3.x adds the owner, security, quota and USN fields
+const SI_SIZE_V12: usize = 0x30;
+pub(crate) const SI_SIZE_V3: usize = 0x48;
+
+// Define constants for the DOS file attribute flags
+pub const FILE_ATTRIBUTE_READONLY: u32 = 0x0000_0001;
+pub const FILE_ATTRIBUTE_HIDDEN: u32 = 0x0000_0002;
+pub const FILE_ATTRIBUTE_SYSTEM: u32 = 0x0000_0004;
+pub const FILE_ATTRIBUTE_ARCHIVE: u32 = 0x0000_0020;
+pub const FILE_ATTRIBUTE_DEVICE: u32 = 0x0000_0040;
+pub const FILE_ATTRIBUTE_NORMAL: u32 = 0x0000_0080;
+pub const FILE_ATTRIBUTE_TEMPORARY: u32 = 0x0000_0100;
+pub const FILE_ATTRIBUTE_SPARSE_FILE: u32 = 0x0000_0200;
+pub const FILE_ATTRIBUTE_REPARSE_POINT: u32 = 0x0000_0400;
+pub const FILE_ATTRIBUTE_COMPRESSED: u32 = 0x0000_0800;
+pub const FILE_ATTRIBUTE_OFFLINE: u32 = 0x0000_1000;
+pub const FILE_ATTRIBUTE_NOT_CONTENT_INDEXED: u32 = 0x0000_2000;
+pub const FILE_ATTRIBUTE_ENCRYPTED: u32 = 0x0000_4000;
+
+// Define a struct to hold the parsed $STANDARD_INFORMATION attribute
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StandardInformation {
+    pub created: DateTime<Utc>,
+    pub modified: DateTime<Utc>,
+    pub mft_modified: DateTime<Utc>,
+    pub accessed: DateTime<Utc>,
+    pub file_attributes: u32,
+    pub max_versions: u32,
+    pub version_number: u32,
+    pub class_id: u32,
+    // The following fields are only present on NTFS 3.x volumes
+    pub owner_id: Option<u32>,
+    pub security_id: Option<u32>,
+    pub quota_charged: Option<u64>,
+    pub usn: Option<u64>,
+}
+
+impl StandardInformation {
+    pub fn parse(value: &[u8]) -> Result<Self> {
+        if value.len() < SI_SIZE_V12 {
+            bail!("$STANDARD_INFORMATION of {} bytes is too short", value.len());
+        }
+
+        let extended = value.len() >= SI_SIZE_V3;
+
+        Ok(StandardInformation {
+            created: filetime_to_datetime(LittleEndian::read_u64(&value[SI_CREATED_OFFSET..])),
+            modified: filetime_to_datetime(LittleEndian::read_u64(&value[SI_MODIFIED_OFFSET..])),
+            mft_modified: filetime_to_datetime(LittleEndian::read_u64(&value[SI_MFT_MODIFIED_OFFSET..])),
+            accessed: filetime_to_datetime(LittleEndian::read_u64(&value[SI_ACCESSED_OFFSET..])),
+            file_attributes: LittleEndian::read_u32(&value[SI_FILE_ATTRIBUTES_OFFSET..]),
+            max_versions: LittleEndian::read_u32(&value[SI_MAX_VERSIONS_OFFSET..]),
+            version_number: LittleEndian::read_u32(&value[SI_VERSION_NUMBER_OFFSET..]),
+            class_id: LittleEndian::read_u32(&value[SI_CLASS_ID_OFFSET..]),
+            owner_id: extended.then(|| LittleEndian::read_u32(&value[SI_OWNER_ID_OFFSET..])),
+            security_id: extended.then(|| LittleEndian::read_u32(&value[SI_SECURITY_ID_OFFSET..])),
+            quota_charged: extended.then(|| LittleEndian::read_u64(&value[SI_QUOTA_CHARGED_OFFSET..])),
+            usn: extended.then(|| LittleEndian::read_u64(&value[SI_USN_OFFSET..])),
+        })
+    }
+
+    pub fn from_attribute(attribute: &Attribute) -> Result<Self> {
+        let value = attribute.resident_value()
+            .with_context(|| "$STANDARD_INFORMATION attribute is not resident")?;
+        StandardInformation::parse(value)
+            .with_context(|| "Failed to parse $STANDARD_INFORMATION attribute")
+    }
+
+    pub fn is_hidden(&self) -> bool {
+        self.file_attributes & FILE_ATTRIBUTE_HIDDEN != 0
+    }
+
+    pub fn is_system(&self) -> bool {
+        self.file_attributes & FILE_ATTRIBUTE_SYSTEM != 0
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.file_attributes & FILE_ATTRIBUTE_READONLY != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_standard_information_parsing() {
+        let mut value = vec![0; SI_SIZE_V3];
+        // 2022-01-01T00:00:00.1234567Z as a FILETIME
+        LittleEndian::write_u64(&mut value[SI_CREATED_OFFSET..], 132_854_688_001_234_567);
+        LittleEndian::write_u32(&mut value[SI_FILE_ATTRIBUTES_OFFSET..], FILE_ATTRIBUTE_HIDDEN | FILE_ATTRIBUTE_ARCHIVE);
+        LittleEndian::write_u32(&mut value[SI_SECURITY_ID_OFFSET..], 0x100);
+        LittleEndian::write_u64(&mut value[SI_USN_OFFSET..], 0x0123_4567);
+
+        let standard_information = StandardInformation::parse(&value).unwrap();
+        let expected = Utc.with_ymd_and_hms(2022, 1, 1, 0, 0, 0).unwrap() + chrono::Duration::nanoseconds(123_456_700);
+        assert_eq!(standard_information.created, expected);
+        assert!(standard_information.is_hidden());
+        assert!(!standard_information.is_system());
+        assert_eq!(standard_information.security_id, Some(0x100));
+        assert_eq!(standard_information.usn, Some(0x0123_4567));
+    }
+
+    #[test]
+    fn test_ntfs_12_standard_information() {
+        let standard_information = StandardInformation::parse(&[0; SI_SIZE_V12]).unwrap();
+        assert_eq!(standard_information.owner_id, None);
+        assert_eq!(standard_information.usn, None);
+        assert_eq!(standard_information.created, Utc.with_ymd_and_hms(1601, 1, 1, 0, 0, 0).unwrap());
+    }
+}
@@ -0,0 +1,272 @@
+// Builders of on-disk structures shared by the tests of all modules
+use crate::attribute::{
+    ATTRIBUTE_LENGTH_OFFSET, ATTRIBUTE_NAME_LENGTH_OFFSET, ATTRIBUTE_NAME_OFFSET_OFFSET, ATTRIBUTE_NON_RESIDENT_OFFSET,
+    ATTRIBUTE_TYPE_FILE_NAME, ATTRIBUTE_TYPE_OFFSET, ATTRIBUTE_TYPE_STANDARD_INFORMATION, NON_RESIDENT_ALLOCATED_SIZE_OFFSET,
+    NON_RESIDENT_HEADER_SIZE, NON_RESIDENT_INITIALIZED_SIZE_OFFSET, NON_RESIDENT_REAL_SIZE_OFFSET,
+    NON_RESIDENT_RUNLIST_OFFSET_OFFSET, RESIDENT_HEADER_SIZE, RESIDENT_VALUE_LENGTH_OFFSET, RESIDENT_VALUE_OFFSET_OFFSET,
+};
+use crate::file_name::{
+    FN_ACCESSED_OFFSET, FN_CREATED_OFFSET, FN_MFT_MODIFIED_OFFSET, FN_MODIFIED_OFFSET, FN_NAMESPACE_OFFSET,
+    FN_NAME_LENGTH_OFFSET, FN_NAME_OFFSET, FN_PARENT_REFERENCE_OFFSET,
+};
+use crate::index::{
+    ENTRY_FILE_REFERENCE_OFFSET, ENTRY_FLAGS_OFFSET, ENTRY_FLAG_LAST, ENTRY_KEY_LENGTH_OFFSET, ENTRY_KEY_OFFSET,
+    ENTRY_LENGTH_OFFSET, INDX_NODE_HEADER_OFFSET, INDX_SIGNATURE_OFFSET, INDX_SIGNATURE_SIZE, INDX_VCN_OFFSET,
+    NODE_ALLOCATED_SIZE_OFFSET, NODE_ENTRIES_OFFSET_OFFSET, NODE_FLAGS_OFFSET, NODE_FLAG_LARGE_INDEX, NODE_HEADER_SIZE,
+    NODE_INDEX_LENGTH_OFFSET, ROOT_COLLATION_RULE_OFFSET, ROOT_INDEXED_TYPE_OFFSET, ROOT_INDEX_BLOCK_SIZE_OFFSET,
+    ROOT_NODE_HEADER_OFFSET,
+};
+use crate::logical_file::LogicalFile;
+use crate::mft_parser::{
+    FileReference, MftEntry, FILE_ALLOCATED_SIZE_OFFSET, FILE_FIRST_ATTRIBUTE_OFFSET, FILE_FLAGS_OFFSET,
+    FILE_HARD_LINK_COUNT_OFFSET, FILE_RECORD_NUMBER_OFFSET, FILE_SEQUENCE_NUMBER_OFFSET, FILE_SIGNATURE_OFFSET,
+    FILE_SIGNATURE_SIZE, FILE_USA_COUNT_OFFSET, FILE_USA_OFFSET_OFFSET, FILE_USED_SIZE_OFFSET,
+};
+use crate::reparse_point::{
+    IO_REPARSE_TAG_SYMLINK, MOUNT_POINT_PATH_BUFFER_OFFSET, PRINT_NAME_LENGTH_OFFSET, PRINT_NAME_OFFSET_OFFSET,
+    REPARSE_DATA_LENGTH_OFFSET, REPARSE_HEADER_SIZE, REPARSE_TAG_OFFSET, SUBSTITUTE_NAME_LENGTH_OFFSET,
+    SYMLINK_FLAGS_OFFSET, SYMLINK_PATH_BUFFER_OFFSET,
+};
+use crate::standard_information::{
+    SI_ACCESSED_OFFSET, SI_CREATED_OFFSET, SI_FILE_ATTRIBUTES_OFFSET, SI_MFT_MODIFIED_OFFSET, SI_MODIFIED_OFFSET, SI_SIZE_V3,
+};
+use crate::wof::{
+    FILE_PROVIDER_ALGORITHM_OFFSET, FILE_PROVIDER_VERSION_OFFSET, IO_REPARSE_TAG_WOF, WOF_CURRENT_VERSION,
+    WOF_PROVIDER_FILE, WOF_PROVIDER_OFFSET, WOF_REPARSE_DATA_SIZE, WOF_VERSION_OFFSET,
+};
+use byteorder::{ByteOrder, LittleEndian};
+
+// The root directory, parent of the files the tests build
+pub(crate) const ROOT: FileReference = FileReference { record_number: 5, sequence_number: 5 };
+
+fn utf16(name: &str) -> Vec<u8> {
+    name.encode_utf16().flat_map(|unit| unit.to_le_bytes()).collect()
+}
+
+// Build a resident attribute
+pub(crate) fn fake_resident_attribute(type_code: u32, name: &str, value: &[u8]) -> Vec<u8> {
+    let name = utf16(name);
+    let value_offset = (RESIDENT_HEADER_SIZE + name.len() + 7) & !7;
+    let length = (value_offset + value.len() + 7) & !7;
+
+    let mut attribute = vec![0; length];
+    LittleEndian::write_u32(&mut attribute[ATTRIBUTE_TYPE_OFFSET..], type_code);
+    LittleEndian::write_u32(&mut attribute[ATTRIBUTE_LENGTH_OFFSET..], length as u32);
+    attribute[ATTRIBUTE_NAME_LENGTH_OFFSET] = (name.len() / 2) as u8;
+    LittleEndian::write_u16(&mut attribute[ATTRIBUTE_NAME_OFFSET_OFFSET..], RESIDENT_HEADER_SIZE as u16);
+    LittleEndian::write_u32(&mut attribute[RESIDENT_VALUE_LENGTH_OFFSET..], value.len() as u32);
+    LittleEndian::write_u16(&mut attribute[RESIDENT_VALUE_OFFSET_OFFSET..], value_offset as u16);
+    attribute[RESIDENT_HEADER_SIZE..RESIDENT_HEADER_SIZE + name.len()].copy_from_slice(&name);
+    attribute[value_offset..value_offset + value.len()].copy_from_slice(value);
+    attribute
+}
+
+// Build a non-resident attribute
+pub(crate) fn fake_non_resident_attribute(type_code: u32, name: &str, runlist: &[u8], real_size: u64) -> Vec<u8> {
+    let name = utf16(name);
+    let runlist_offset = (NON_RESIDENT_HEADER_SIZE + name.len() + 7) & !7;
+    let length = (runlist_offset + runlist.len() + 1 + 7) & !7;
+
+    let mut attribute = vec![0; length];
+    LittleEndian::write_u32(&mut attribute[ATTRIBUTE_TYPE_OFFSET..], type_code);
+    LittleEndian::write_u32(&mut attribute[ATTRIBUTE_LENGTH_OFFSET..], length as u32);
+    attribute[ATTRIBUTE_NON_RESIDENT_OFFSET] = 1;
+    attribute[ATTRIBUTE_NAME_LENGTH_OFFSET] = (name.len() / 2) as u8;
+    LittleEndian::write_u16(&mut attribute[ATTRIBUTE_NAME_OFFSET_OFFSET..], NON_RESIDENT_HEADER_SIZE as u16);
+    LittleEndian::write_u16(&mut attribute[NON_RESIDENT_RUNLIST_OFFSET_OFFSET..], runlist_offset as u16);
+    LittleEndian::write_u64(&mut attribute[NON_RESIDENT_ALLOCATED_SIZE_OFFSET..], real_size);
+    LittleEndian::write_u64(&mut attribute[NON_RESIDENT_REAL_SIZE_OFFSET..], real_size);
+    LittleEndian::write_u64(&mut attribute[NON_RESIDENT_INITIALIZED_SIZE_OFFSET..], real_size);
+    attribute[NON_RESIDENT_HEADER_SIZE..NON_RESIDENT_HEADER_SIZE + name.len()].copy_from_slice(&name);
+    attribute[runlist_offset..runlist_offset + runlist.len()].copy_from_slice(runlist);
+    attribute
+}
+
+// Build a $FILE_NAME value with its timestamps left unset
+pub(crate) fn fake_file_name_value(parent: FileReference, name: &str, namespace: u8) -> Vec<u8> {
+    let name = utf16(name);
+    let mut value = vec![0; FN_NAME_OFFSET + name.len()];
+    let parent = parent.record_number | (u64::from(parent.sequence_number) << 48);
+    LittleEndian::write_u64(&mut value[FN_PARENT_REFERENCE_OFFSET..], parent);
+    value[FN_NAME_LENGTH_OFFSET] = (name.len() / 2) as u8;
+    value[FN_NAMESPACE_OFFSET] = namespace;
+    value[FN_NAME_OFFSET..].copy_from_slice(&name);
+    value
+}
+
+// Build a Win32 $FILE_NAME value with all four timestamps set
+pub(crate) fn fake_stamped_file_name(parent: FileReference, name: &str, filetime: u64) -> Vec<u8> {
+    let mut value = fake_file_name_value(parent, name, 1);
+    for offset in [FN_CREATED_OFFSET, FN_MODIFIED_OFFSET, FN_MFT_MODIFIED_OFFSET, FN_ACCESSED_OFFSET] {
+        LittleEndian::write_u64(&mut value[offset..], filetime);
+    }
+    value
+}
+
+// Build a $STANDARD_INFORMATION value with all four timestamps set
+pub(crate) fn fake_standard_information_value(filetime: u64, file_attributes: u32) -> Vec<u8> {
+    let mut value = vec![0; SI_SIZE_V3];
+    for offset in [SI_CREATED_OFFSET, SI_MODIFIED_OFFSET, SI_MFT_MODIFIED_OFFSET, SI_ACCESSED_OFFSET] {
+        LittleEndian::write_u64(&mut value[offset..], filetime);
+    }
+    LittleEndian::write_u32(&mut value[SI_FILE_ATTRIBUTES_OFFSET..], file_attributes);
+    value
+}
+
+// Build the $STANDARD_INFORMATION and $FILE_NAME attributes of a file in the root directory,
+// each with all four timestamps set to the given time
+pub(crate) fn fake_stamped_attributes(name: &str, si_filetime: u64, fn_filetime: u64) -> Vec<u8> {
+    [
+        fake_resident_attribute(ATTRIBUTE_TYPE_STANDARD_INFORMATION, "", &fake_standard_information_value(si_filetime, 0)),
+        fake_resident_attribute(ATTRIBUTE_TYPE_FILE_NAME, "", &fake_stamped_file_name(ROOT, name, fn_filetime)),
+    ]
+    .concat()
+}
+
+// Build a 1024-byte FILE record around the given attributes, with valid fixups
+pub(crate) fn fake_file_record(record_number: u32, flags: u16, attributes: &[u8]) -> Vec<u8> {
+    const USA_OFFSET: usize = 0x30;
+    const FIRST_ATTRIBUTE: usize = 0x38;
+    const USN: [u8; 2] = [0x01, 0x00];
+
+    let mut record = vec![0; 1024];
+    let used_size = FIRST_ATTRIBUTE + attributes.len() + 8;
+    record[FILE_SIGNATURE_OFFSET..FILE_SIGNATURE_OFFSET + FILE_SIGNATURE_SIZE].copy_from_slice(b"FILE");
+    LittleEndian::write_u16(&mut record[FILE_USA_OFFSET_OFFSET..], USA_OFFSET as u16);
+    LittleEndian::write_u16(&mut record[FILE_USA_COUNT_OFFSET..], 3);
+    LittleEndian::write_u16(&mut record[FILE_SEQUENCE_NUMBER_OFFSET..], 1);
+    LittleEndian::write_u16(&mut record[FILE_HARD_LINK_COUNT_OFFSET..], 1);
+    LittleEndian::write_u16(&mut record[FILE_FIRST_ATTRIBUTE_OFFSET..], FIRST_ATTRIBUTE as u16);
+    LittleEndian::write_u16(&mut record[FILE_FLAGS_OFFSET..], flags);
+    LittleEndian::write_u32(&mut record[FILE_USED_SIZE_OFFSET..], used_size as u32);
+    LittleEndian::write_u32(&mut record[FILE_ALLOCATED_SIZE_OFFSET..], 1024);
+    LittleEndian::write_u32(&mut record[FILE_RECORD_NUMBER_OFFSET..], record_number);
+    record[FIRST_ATTRIBUTE..FIRST_ATTRIBUTE + attributes.len()].copy_from_slice(attributes);
+    LittleEndian::write_u32(&mut record[FIRST_ATTRIBUTE + attributes.len()..], 0xFFFF_FFFF);
+    stamp_fixups(&mut record, USA_OFFSET, USN);
+    record
+}
+
+// Build a file of a single FILE record around the given attributes
+pub(crate) fn fake_logical_file(record_number: u32, flags: u16, attributes: &[u8]) -> LogicalFile {
+    let base = MftEntry::parse(&fake_file_record(record_number, flags, attributes)).unwrap();
+    LogicalFile::from_records(u64::from(record_number), base, Vec::new()).unwrap()
+}
+
+// Build an index entry naming a file
+pub(crate) fn fake_index_entry(file_reference: FileReference, file_name_value: &[u8]) -> Vec<u8> {
+    let entry_length = (ENTRY_KEY_OFFSET + file_name_value.len() + 7) & !7;
+    let mut entry = vec![0; entry_length];
+    let file_reference = file_reference.record_number | (u64::from(file_reference.sequence_number) << 48);
+    LittleEndian::write_u64(&mut entry[ENTRY_FILE_REFERENCE_OFFSET..], file_reference);
+    LittleEndian::write_u16(&mut entry[ENTRY_LENGTH_OFFSET..], entry_length as u16);
+    LittleEndian::write_u16(&mut entry[ENTRY_KEY_LENGTH_OFFSET..], file_name_value.len() as u16);
+    entry[ENTRY_KEY_OFFSET..ENTRY_KEY_OFFSET + file_name_value.len()].copy_from_slice(file_name_value);
+    entry
+}
+
+// Build a node header followed by the given entries and a closing entry, padded to the
+// given allocated size
+fn fake_index_node(entries: &[Vec<u8>], allocated_size: usize) -> Vec<u8> {
+    let mut node = vec![0; NODE_HEADER_SIZE];
+    for entry in entries {
+        node.extend(entry);
+    }
+    let mut last_entry = vec![0; ENTRY_KEY_OFFSET];
+    LittleEndian::write_u16(&mut last_entry[ENTRY_LENGTH_OFFSET..], ENTRY_KEY_OFFSET as u16);
+    LittleEndian::write_u16(&mut last_entry[ENTRY_FLAGS_OFFSET..], ENTRY_FLAG_LAST);
+    node.extend(last_entry);
+
+    let index_length = node.len();
+    LittleEndian::write_u32(&mut node[NODE_ENTRIES_OFFSET_OFFSET..], NODE_HEADER_SIZE as u32);
+    LittleEndian::write_u32(&mut node[NODE_INDEX_LENGTH_OFFSET..], index_length as u32);
+    LittleEndian::write_u32(&mut node[NODE_ALLOCATED_SIZE_OFFSET..], allocated_size.max(index_length) as u32);
+    node.resize(allocated_size.max(index_length), 0);
+    node
+}
+
+// Build a $INDEX_ROOT value for a $I30 index
+pub(crate) fn fake_index_root_value(entries: &[Vec<u8>], index_block_size: u32, large_index: bool) -> Vec<u8> {
+    let mut value = vec![0; ROOT_NODE_HEADER_OFFSET];
+    LittleEndian::write_u32(&mut value[ROOT_INDEXED_TYPE_OFFSET..], ATTRIBUTE_TYPE_FILE_NAME);
+    LittleEndian::write_u32(&mut value[ROOT_COLLATION_RULE_OFFSET..], 1);
+    LittleEndian::write_u32(&mut value[ROOT_INDEX_BLOCK_SIZE_OFFSET..], index_block_size);
+    let mut node = fake_index_node(entries, 0);
+    if large_index {
+        LittleEndian::write_u32(&mut node[NODE_FLAGS_OFFSET..], NODE_FLAG_LARGE_INDEX);
+    }
+    value.extend(node);
+    value
+}
+
+// Build an INDX record of the given size around the given entries, with valid fixups
+pub(crate) fn fake_index_record(vcn: u64, entries: &[Vec<u8>], size: usize) -> Vec<u8> {
+    const USA_OFFSET_OFFSET: usize = 0x04;
+    const USA_COUNT_OFFSET: usize = 0x06;
+    const USA_OFFSET: usize = 0x28;
+    const USN: [u8; 2] = [0x02, 0x00];
+    let sectors = size / 512;
+    let node_offset = (USA_OFFSET + 2 * (sectors + 1) + 7) & !7;
+
+    let mut record = vec![0; INDX_NODE_HEADER_OFFSET];
+    record[INDX_SIGNATURE_OFFSET..INDX_SIGNATURE_SIZE].copy_from_slice(b"INDX");
+    LittleEndian::write_u16(&mut record[USA_OFFSET_OFFSET..], USA_OFFSET as u16);
+    LittleEndian::write_u16(&mut record[USA_COUNT_OFFSET..], (sectors + 1) as u16);
+    LittleEndian::write_u64(&mut record[INDX_VCN_OFFSET..], vcn);
+
+    // Entries start after the update sequence array, with offsets relative to the node header
+    let mut node = fake_index_node(entries, size - INDX_NODE_HEADER_OFFSET);
+    let padding = node_offset - INDX_NODE_HEADER_OFFSET - NODE_HEADER_SIZE;
+    node.splice(NODE_HEADER_SIZE..NODE_HEADER_SIZE, vec![0; padding]);
+    node.truncate(size - INDX_NODE_HEADER_OFFSET);
+    for field in [NODE_ENTRIES_OFFSET_OFFSET, NODE_INDEX_LENGTH_OFFSET] {
+        let value = LittleEndian::read_u32(&node[field..]);
+        LittleEndian::write_u32(&mut node[field..], value + padding as u32);
+    }
+    record.extend(node);
+    stamp_fixups(&mut record, USA_OFFSET, USN);
+    record
+}
+
+// Move the real sector trailers into the update sequence array and stamp the USN
+fn stamp_fixups(record: &mut [u8], usa_offset: usize, usn: [u8; 2]) {
+    record[usa_offset..usa_offset + 2].copy_from_slice(&usn);
+    for sector in 0..record.len() / 512 {
+        let trailer = (sector + 1) * 512 - 2;
+        record.copy_within(trailer..trailer + 2, usa_offset + 2 + 2 * sector);
+        record[trailer..trailer + 2].copy_from_slice(&usn);
+    }
+}
+
+// Build the reparse data of a junction or symbolic link
+pub(crate) fn fake_link_reparse_data(tag: u32, substitute_name: &str, print_name: &str, flags: u32) -> Vec<u8> {
+    let (substitute_name, print_name) = (utf16(substitute_name), utf16(print_name));
+    let path_buffer_offset = if tag == IO_REPARSE_TAG_SYMLINK { SYMLINK_PATH_BUFFER_OFFSET } else { MOUNT_POINT_PATH_BUFFER_OFFSET };
+
+    let mut value = vec![0; path_buffer_offset];
+    LittleEndian::write_u32(&mut value[REPARSE_TAG_OFFSET..], tag);
+    LittleEndian::write_u16(&mut value[SUBSTITUTE_NAME_LENGTH_OFFSET..], substitute_name.len() as u16);
+    LittleEndian::write_u16(&mut value[PRINT_NAME_OFFSET_OFFSET..], substitute_name.len() as u16);
+    LittleEndian::write_u16(&mut value[PRINT_NAME_LENGTH_OFFSET..], print_name.len() as u16);
+    if tag == IO_REPARSE_TAG_SYMLINK {
+        LittleEndian::write_u32(&mut value[SYMLINK_FLAGS_OFFSET..], flags);
+    }
+    value.extend(substitute_name);
+    value.extend(print_name);
+
+    let data_length = (value.len() - REPARSE_HEADER_SIZE) as u16;
+    LittleEndian::write_u16(&mut value[REPARSE_DATA_LENGTH_OFFSET..], data_length);
+    value
+}
+
+// Build the reparse data of a WOF file provider reparse point
+pub(crate) fn fake_wof_reparse_data(algorithm: u32) -> Vec<u8> {
+    let mut data = vec![0; WOF_REPARSE_DATA_SIZE];
+    LittleEndian::write_u32(&mut data[REPARSE_TAG_OFFSET..], IO_REPARSE_TAG_WOF);
+    LittleEndian::write_u16(&mut data[REPARSE_DATA_LENGTH_OFFSET..], (WOF_REPARSE_DATA_SIZE - REPARSE_HEADER_SIZE) as u16);
+    LittleEndian::write_u32(&mut data[WOF_VERSION_OFFSET..], WOF_CURRENT_VERSION);
+    LittleEndian::write_u32(&mut data[WOF_PROVIDER_OFFSET..], WOF_PROVIDER_FILE);
+    LittleEndian::write_u32(&mut data[FILE_PROVIDER_VERSION_OFFSET..], WOF_CURRENT_VERSION);
+    LittleEndian::write_u32(&mut data[FILE_PROVIDER_ALGORITHM_OFFSET..], algorithm);
+    data
+}
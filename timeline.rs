@@ -0,0 +1,384 @@
+use crate::data_structurer::DbEntry;
+use crate::path_resolver::PathResolver;
+use crate::timeline_sort::{ExternalSorter, SortedItems};
+use crate::usn_journal::{reason_names, UsnRecord};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Datelike, NaiveDate, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize, Serializer};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+// Events held in memory at a time while sorting, each a few hundred bytes at most
+const SORT_CHUNK_SIZE: usize = 250_000;
+
+// A FILETIME of zero converts to the start of 1601 and means the timestamp was never set
+const UNSET_TIMESTAMP_YEAR: i32 = 1601;
+
+// Define the kinds of event a file's metadata yields, one per timestamp, and the change
+// records of the USN journal
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum EventType {
+    SiCreated,
+    SiModified,
+    SiMftModified,
+    SiAccessed,
+    FnCreated,
+    FnModified,
+    FnMftModified,
+    FnAccessed,
+    UsnChange,
+}
+
+impl EventType {
+    // Stable identifier written with each event, to query by
+    pub fn id(&self) -> &'static str {
+        match self {
+            EventType::SiCreated => "SI_CREATED",
+            EventType::SiModified => "SI_MODIFIED",
+            EventType::SiMftModified => "SI_MFT_MODIFIED",
+            EventType::SiAccessed => "SI_ACCESSED",
+            EventType::FnCreated => "FN_CREATED",
+            EventType::FnModified => "FN_MODIFIED",
+            EventType::FnMftModified => "FN_MFT_MODIFIED",
+            EventType::FnAccessed => "FN_ACCESSED",
+            EventType::UsnChange => "USN_CHANGE",
+        }
+    }
+}
+
+// Define a struct to hold one event of the timeline. Events order by time first, so sorting
+// them sorts the timeline.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct TimelineEvent {
+    #[serde(serialize_with = "serialize_timestamp")]
+    pub timestamp: DateTime<Utc>,
+    pub event_type: EventType,
+    pub record_number: u64,
+    pub sequence_number: u16,
+    // Path of the file for $SI events, of the hard link for $FN events, and of the name the
+    // change was recorded under for USN events
+    pub full_path: String,
+    pub in_use: bool,
+    // Reasons of a USN change, such as FILE_CREATE|CLOSE
+    pub usn_reasons: Option<String>,
+}
+
+// Define where a timeline is exported to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TimelineOutput {
+    Csv(PathBuf),
+    JsonLines(PathBuf),
+    // The timeline table of the database
+    Sqlite,
+}
+
+// Define a struct to hold which events make it to the timeline. The range includes `from` and
+// excludes `to`, and paths are compared without regard to case, as NTFS does.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TimelineFilter {
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub path_prefix: Option<String>,
+}
+
+impl TimelineFilter {
+    pub fn matches(&self, event: &TimelineEvent) -> bool {
+        self.from.is_none_or(|from| event.timestamp >= from)
+            && self.to.is_none_or(|to| event.timestamp < to)
+            && self.path_prefix.as_ref().is_none_or(|prefix| {
+                event.full_path.to_lowercase().starts_with(&prefix.to_lowercase())
+            })
+    }
+}
+
+// Parse a time given on the command line, either in RFC 3339 or as a date taken at midnight UTC
+pub fn parse_time(value: &str) -> Result<DateTime<Utc>> {
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(value) {
+        return Ok(timestamp.with_timezone(&Utc));
+    }
+    let date = NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .with_context(|| format!("Invalid time {:?}, expected YYYY-MM-DD or RFC 3339", value))?;
+    Ok(date.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc())
+}
+
+// Define a struct that sorts the events of a whole volume as its entries are read. Sorting
+// spills to `spill_directory`, so memory stays bounded however many events there are.
+pub struct TimelineBuilder {
+    filter: TimelineFilter,
+    sorter: ExternalSorter<TimelineEvent>,
+    entry_count: usize,
+    usn_record_count: usize,
+}
+
+impl TimelineBuilder {
+    pub fn new(filter: &TimelineFilter, spill_directory: &Path) -> Self {
+        TimelineBuilder {
+            filter: filter.clone(),
+            sorter: ExternalSorter::new(SORT_CHUNK_SIZE, spill_directory),
+            entry_count: 0,
+            usn_record_count: 0,
+        }
+    }
+
+    // Expand an entry into the events matching the filter. Its paths must already be resolved.
+    pub fn add_entry(&mut self, entry: &DbEntry) -> Result<()> {
+        self.entry_count += 1;
+        for event in entry_events(entry) {
+            if self.filter.matches(&event) {
+                self.sorter.push(event)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Add the event of a USN journal record if it matches the filter, resolving its path
+    // through the directory tree of the volume
+    pub fn add_usn_record(&mut self, record: &UsnRecord, path_resolver: &mut PathResolver) -> Result<()> {
+        self.usn_record_count += 1;
+        let event = usn_event(record, path_resolver);
+        if self.filter.matches(&event) {
+            self.sorter.push(event)?;
+        }
+        Ok(())
+    }
+
+    pub fn entry_count(&self) -> usize {
+        self.entry_count
+    }
+
+    pub fn usn_record_count(&self) -> usize {
+        self.usn_record_count
+    }
+
+    pub fn finish(self) -> Result<SortedItems<TimelineEvent>> {
+        self.sorter.finish()
+    }
+}
+
+// One event per set timestamp of $STANDARD_INFORMATION, and of the $FILE_NAME of each hard link
+pub fn entry_events(entry: &DbEntry) -> Vec<TimelineEvent> {
+    let mut events = Vec::new();
+    let mut add_events = |full_path: &str, times: [(EventType, &String); 4]| {
+        for (event_type, timestamp) in times {
+            if let Some(timestamp) = parse_timestamp(timestamp) {
+                events.push(TimelineEvent {
+                    timestamp,
+                    event_type,
+                    record_number: entry.record_number,
+                    sequence_number: entry.sequence_number,
+                    full_path: full_path.to_string(),
+                    in_use: entry.in_use,
+                    usn_reasons: None,
+                });
+            }
+        }
+    };
+
+    add_events(&entry.full_path, [
+        (EventType::SiCreated, &entry.creation_time),
+        (EventType::SiModified, &entry.modification_time),
+        (EventType::SiMftModified, &entry.mft_modified_time),
+        (EventType::SiAccessed, &entry.access_time),
+    ]);
+    for name in &entry.names {
+        add_events(&name.full_path, [
+            (EventType::FnCreated, &name.creation_time),
+            (EventType::FnModified, &name.modification_time),
+            (EventType::FnMftModified, &name.mft_modified_time),
+            (EventType::FnAccessed, &name.access_time),
+        ]);
+    }
+
+    events
+}
+
+// The event of a USN journal record, under the path its directory has now. The file counts as
+// in use if the record it names still holds the same version of it.
+pub fn usn_event(record: &UsnRecord, path_resolver: &mut PathResolver) -> TimelineEvent {
+    TimelineEvent {
+        timestamp: record.timestamp,
+        event_type: EventType::UsnChange,
+        record_number: record.file_reference.record_number,
+        sequence_number: record.file_reference.sequence_number,
+        full_path: path_resolver.resolve_in_directory(record.parent_reference, &record.file_name),
+        in_use: path_resolver.is_in_use(record.file_reference),
+        usn_reasons: Some(reason_names(record.reason)),
+    }
+}
+
+// Timestamps that were never set, or are missing with their attribute, are left out
+fn parse_timestamp(timestamp: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(timestamp).ok()
+        .map(|timestamp| timestamp.with_timezone(&Utc))
+        .filter(|timestamp| timestamp.year() > UNSET_TIMESTAMP_YEAR)
+}
+
+// Timestamps are written with all nine fractional digits, so they sort as text in time order
+pub fn format_event_timestamp(timestamp: &DateTime<Utc>) -> String {
+    timestamp.to_rfc3339_opts(SecondsFormat::Nanos, true)
+}
+
+fn serialize_timestamp<S: Serializer>(timestamp: &DateTime<Utc>, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    serializer.serialize_str(&format_event_timestamp(timestamp))
+}
+
+pub fn write_csv<W: Write, I: Iterator<Item = Result<TimelineEvent>>>(events: I, mut writer: W) -> Result<()> {
+    writeln!(writer, "timestamp,event_type,record_number,sequence_number,in_use,full_path,usn_reasons")
+        .with_context(|| "Failed to write timeline")?;
+    for event in events {
+        let event = event?;
+        writeln!(
+            writer,
+            "{},{},{},{},{},{},{}",
+            format_event_timestamp(&event.timestamp), event.event_type.id(), event.record_number, event.sequence_number,
+            event.in_use, csv_field(&event.full_path), event.usn_reasons.as_deref().unwrap_or(""),
+        )
+        .with_context(|| "Failed to write timeline")?;
+    }
+    writer.flush().with_context(|| "Failed to write timeline")
+}
+
+pub fn write_json_lines<W: Write, I: Iterator<Item = Result<TimelineEvent>>>(events: I, mut writer: W) -> Result<()> {
+    for event in events {
+        serde_json::to_writer(&mut writer, &event?).with_context(|| "Failed to write timeline")?;
+        writer.write_all(b"\n").with_context(|| "Failed to write timeline")?;
+    }
+    writer.flush().with_context(|| "Failed to write timeline")
+}
+
+// Quote a field holding a separator, quote or line break, doubling any quotes inside it
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_structurer::StructuredData;
+    use crate::mft_parser::{FileReference, MftEntry, FILE_RECORD_IN_USE};
+    use crate::test_support::{fake_file_record, fake_logical_file, fake_stamped_attributes, ROOT};
+
+    // 2022-01-01T00:00:00Z and 2021-06-01T00:00:00Z as FILETIMEs
+    const SI_FILETIME: u64 = 132_854_688_000_000_000;
+    const FN_FILETIME: u64 = 132_669_792_000_000_000;
+
+    fn fake_volume() -> StructuredData {
+        let mft_entries = [(64, "report, final.docx"), (65, "notes.txt")].into_iter()
+            .map(|(record_number, name)| {
+                let attributes = fake_stamped_attributes(name, SI_FILETIME, FN_FILETIME);
+                (u64::from(record_number), MftEntry::parse(&fake_file_record(record_number, FILE_RECORD_IN_USE, &attributes)).unwrap())
+            })
+            .collect();
+        StructuredData::from_mft_entries(mft_entries, "C:").unwrap()
+    }
+
+    fn build_timeline(data: &StructuredData, filter: &TimelineFilter, spill_directory: &Path) -> Result<SortedItems<TimelineEvent>> {
+        let mut builder = TimelineBuilder::new(filter, spill_directory);
+        for entry in &data.entries {
+            builder.add_entry(entry)?;
+        }
+        assert_eq!(builder.entry_count(), data.entries.len());
+        builder.finish()
+    }
+
+    #[test]
+    fn test_sorted_filtered_timeline() {
+        let data = fake_volume();
+        let spill_directory = std::env::temp_dir();
+
+        // Four $SI events and four $FN events per file
+        let events: Vec<TimelineEvent> = build_timeline(&data, &TimelineFilter::default(), &spill_directory).unwrap()
+            .collect::<Result<_>>().unwrap();
+        assert_eq!(events.len(), 16);
+        assert_eq!((events[0].event_type, events[0].record_number), (EventType::FnCreated, 64));
+        assert_eq!((events[1].event_type, events[1].record_number), (EventType::FnCreated, 65));
+        assert!(events.windows(2).all(|pair| pair[0] <= pair[1]));
+
+        let filter = TimelineFilter {
+            from: Some(parse_time("2022-01-01").unwrap()),
+            to: None,
+            path_prefix: Some("c:\\NOTES".to_string()),
+        };
+        let events: Vec<TimelineEvent> = build_timeline(&data, &filter, &spill_directory).unwrap()
+            .collect::<Result<_>>().unwrap();
+        let types: Vec<&str> = events.iter().map(|event| event.event_type.id()).collect();
+        assert_eq!(types, vec!["SI_CREATED", "SI_MODIFIED", "SI_MFT_MODIFIED", "SI_ACCESSED"]);
+        assert!(events.iter().all(|event| event.full_path == "C:\\notes.txt"));
+    }
+
+    #[test]
+    fn test_timeline_export() {
+        let data = fake_volume();
+        let events = || entry_events(&data.entries[0]).into_iter().take(1).map(Ok);
+
+        let mut csv = Vec::new();
+        write_csv(events(), &mut csv).unwrap();
+        assert_eq!(String::from_utf8(csv).unwrap(), [
+            "timestamp,event_type,record_number,sequence_number,in_use,full_path,usn_reasons\n",
+            "2022-01-01T00:00:00.000000000Z,SI_CREATED,64,1,true,\"C:\\report, final.docx\",\n",
+        ].concat());
+
+        let mut json_lines = Vec::new();
+        write_json_lines(events(), &mut json_lines).unwrap();
+        let event: serde_json::Value = serde_json::from_slice(&json_lines).unwrap();
+        assert_eq!(event["event_type"], "SI_CREATED");
+        assert_eq!(event["full_path"], "C:\\report, final.docx");
+        assert_eq!(event["timestamp"], "2022-01-01T00:00:00.000000000Z");
+        // Spill files are read back from the same serialization
+        let event: TimelineEvent = serde_json::from_slice(&json_lines).unwrap();
+        assert_eq!(Some(event), events().next().map(Result::unwrap));
+
+        assert!(parse_time("yesterday").is_err());
+    }
+
+    #[test]
+    fn test_usn_change_events() {
+        let data = fake_volume();
+        let files = vec![fake_logical_file(64, FILE_RECORD_IN_USE, &fake_stamped_attributes("report, final.docx", SI_FILETIME, FN_FILETIME))];
+        let mut path_resolver = PathResolver::from_files(&files, "C:").unwrap();
+
+        // An extend of the live file, and the delete of a file whose record has been reused since
+        let extended = UsnRecord {
+            usn: 0x4000,
+            timestamp: parse_time("2022-02-01").unwrap(),
+            file_reference: FileReference { record_number: 64, sequence_number: 1 },
+            parent_reference: ROOT,
+            reason: 0x8000_0002,
+            file_attributes: 0,
+            file_name: "report, final.docx".to_string(),
+        };
+        let deleted = UsnRecord {
+            usn: 0x3000,
+            timestamp: parse_time("2021-01-01").unwrap(),
+            file_reference: FileReference { record_number: 64, sequence_number: 0 },
+            reason: 0x8000_0200,
+            file_name: "draft.txt".to_string(),
+            ..extended.clone()
+        };
+
+        let mut builder = TimelineBuilder::new(&TimelineFilter::default(), &std::env::temp_dir());
+        for entry in &data.entries {
+            builder.add_entry(entry).unwrap();
+        }
+        builder.add_usn_record(&extended, &mut path_resolver).unwrap();
+        builder.add_usn_record(&deleted, &mut path_resolver).unwrap();
+        assert_eq!(builder.usn_record_count(), 2);
+
+        let events: Vec<TimelineEvent> = builder.finish().unwrap().collect::<Result<_>>().unwrap();
+        assert_eq!(events.len(), 18);
+        let (first, last) = (&events[0], &events[17]);
+        assert_eq!((first.event_type, first.full_path.as_str(), first.in_use), (EventType::UsnChange, "C:\\draft.txt", false));
+        assert_eq!(first.usn_reasons.as_deref(), Some("FILE_DELETE|CLOSE"));
+        assert_eq!((last.event_type, last.in_use), (EventType::UsnChange, true));
+
+        let mut csv = Vec::new();
+        write_csv(std::iter::once(Ok(last.clone())), &mut csv).unwrap();
+        assert!(String::from_utf8(csv).unwrap()
+            .ends_with("2022-02-01T00:00:00.000000000Z,USN_CHANGE,64,1,true,\"C:\\report, final.docx\",DATA_EXTEND|CLOSE\n"));
+    }
+}
@@ -0,0 +1,194 @@
+use anyhow::{Context, Result};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Lines, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// Tells apart the spill files of sorters running at the same time in one process
+static NEXT_SPILL_ID: AtomicUsize = AtomicUsize::new(0);
+
+// Define a struct that sorts more items than fit in memory. Items are sorted in chunks of at
+// most `chunk_size`, each full chunk is written to a spill file as JSON lines, and the spill
+// files are merged when the sorted items are read back.
+pub struct ExternalSorter<T> {
+    chunk_size: usize,
+    spill_directory: PathBuf,
+    buffer: Vec<T>,
+    spill_files: Vec<PathBuf>,
+}
+
+impl<T: Ord + Serialize + DeserializeOwned> ExternalSorter<T> {
+    pub fn new(chunk_size: usize, spill_directory: &Path) -> Self {
+        ExternalSorter {
+            chunk_size: chunk_size.max(1),
+            spill_directory: spill_directory.to_path_buf(),
+            buffer: Vec::new(),
+            spill_files: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, item: T) -> Result<()> {
+        self.buffer.push(item);
+        if self.buffer.len() >= self.chunk_size {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    // Read the items back in order. Items that never filled a chunk are sorted in memory.
+    pub fn finish(mut self) -> Result<SortedItems<T>> {
+        if self.spill_files.is_empty() {
+            self.buffer.sort();
+            return Ok(SortedItems::Memory(std::mem::take(&mut self.buffer).into_iter()));
+        }
+        if !self.buffer.is_empty() {
+            self.spill()?;
+        }
+
+        let mut merge = SpillMerge {
+            runs: Vec::new(),
+            heap: BinaryHeap::new(),
+            spill_files: std::mem::take(&mut self.spill_files),
+        };
+        for path in &merge.spill_files {
+            let file = File::open(path).with_context(|| format!("Failed to open spill file {:?}", path))?;
+            merge.runs.push(BufReader::new(file).lines());
+        }
+        for run in 0..merge.runs.len() {
+            merge.refill(run)?;
+        }
+        Ok(SortedItems::Merge(merge))
+    }
+
+    fn spill(&mut self) -> Result<()> {
+        self.buffer.sort();
+        let path = self.spill_directory.join(format!(
+            "ntfs-mft-sort-{}-{}.jsonl",
+            std::process::id(),
+            NEXT_SPILL_ID.fetch_add(1, Ordering::Relaxed),
+        ));
+        // Tracked before writing, so a partly written file is removed too
+        self.spill_files.push(path.clone());
+
+        let file = File::create(&path).with_context(|| format!("Failed to create spill file {:?}", path))?;
+        let mut writer = BufWriter::new(file);
+        for item in self.buffer.drain(..) {
+            serde_json::to_writer(&mut writer, &item).with_context(|| format!("Failed to write spill file {:?}", path))?;
+            writer.write_all(b"\n").with_context(|| format!("Failed to write spill file {:?}", path))?;
+        }
+        writer.flush().with_context(|| format!("Failed to write spill file {:?}", path))
+    }
+}
+
+impl<T> Drop for ExternalSorter<T> {
+    fn drop(&mut self) {
+        remove_spill_files(&self.spill_files);
+    }
+}
+
+// Define the items of a sorter in order, failing only when a spill file cannot be read back
+pub enum SortedItems<T> {
+    Memory(std::vec::IntoIter<T>),
+    Merge(SpillMerge<T>),
+}
+
+impl<T: Ord + DeserializeOwned> Iterator for SortedItems<T> {
+    type Item = Result<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            SortedItems::Memory(items) => items.next().map(Ok),
+            SortedItems::Merge(merge) => merge.next(),
+        }
+    }
+}
+
+// Define a k-way merge of sorted spill files, holding only the next item of each
+pub struct SpillMerge<T> {
+    runs: Vec<Lines<BufReader<File>>>,
+    // The next item of each run, with the run it came from
+    heap: BinaryHeap<Reverse<(T, usize)>>,
+    spill_files: Vec<PathBuf>,
+}
+
+impl<T: Ord + DeserializeOwned> SpillMerge<T> {
+    fn refill(&mut self, run: usize) -> Result<()> {
+        if let Some(line) = self.runs[run].next() {
+            let path = &self.spill_files[run];
+            let line = line.with_context(|| format!("Failed to read spill file {:?}", path))?;
+            let item = serde_json::from_str(&line).with_context(|| format!("Failed to parse spill file {:?}", path))?;
+            self.heap.push(Reverse((item, run)));
+        }
+        Ok(())
+    }
+
+    fn next(&mut self) -> Option<Result<T>> {
+        let Reverse((item, run)) = self.heap.pop()?;
+        match self.refill(run) {
+            Ok(()) => Some(Ok(item)),
+            Err(e) => {
+                // The rest of the order cannot be trusted once a run is lost
+                self.heap.clear();
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+impl<T> Drop for SpillMerge<T> {
+    fn drop(&mut self) {
+        remove_spill_files(&self.spill_files);
+    }
+}
+
+fn remove_spill_files(spill_files: &[PathBuf]) {
+    for path in spill_files {
+        let _ = fs::remove_file(path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spill_directory(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("ntfs-mft-sort-test-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    #[test]
+    fn test_sorting_across_spill_files() {
+        let directory = spill_directory("spill");
+        let mut sorter = ExternalSorter::new(3, &directory);
+        let items: Vec<u64> = (0..10).map(|item| (item * 7) % 10).collect();
+        for &item in &items {
+            sorter.push(item).unwrap();
+        }
+        assert_eq!(fs::read_dir(&directory).unwrap().count(), 3);
+
+        let sorted: Vec<u64> = sorter.finish().unwrap().collect::<Result<_>>().unwrap();
+        assert_eq!(sorted, (0..10).collect::<Vec<u64>>());
+        // The spill files are removed once the merge is dropped
+        assert_eq!(fs::read_dir(&directory).unwrap().count(), 0);
+        fs::remove_dir(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_sorting_in_memory() {
+        let directory = spill_directory("memory");
+        let mut sorter = ExternalSorter::new(100, &directory);
+        for item in ["gamma", "alpha", "beta"] {
+            sorter.push(item.to_string()).unwrap();
+        }
+
+        let sorted: Vec<String> = sorter.finish().unwrap().collect::<Result<_>>().unwrap();
+        assert_eq!(sorted, vec!["alpha", "beta", "gamma"]);
+        assert_eq!(fs::read_dir(&directory).unwrap().count(), 0);
+        fs::remove_dir(&directory).unwrap();
+    }
+}
@@ -0,0 +1,205 @@
+use crate::data_structurer::format_timestamp;
+use crate::file_name::preferred_name;
+use crate::logical_file::LogicalFile;
+use crate::mft_parser::MftEntry;
+use crate::mft_reader::MftReader;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use serde::{Deserialize, Serialize};
+
+// Record of the $Volume metadata file, created when the volume is formatted
+pub const VOLUME_RECORD_NUMBER: u64 = 3;
+
+// A FILETIME of zero converts to the start of 1601 and means the timestamp was never set
+const UNSET_TIMESTAMP_YEAR: i32 = 1601;
+
+// Positions of the creation and MFT modification times in a timestamp set. Copies and archive
+// tools carry modification and access times over from elsewhere, and archive formats store
+// them in whole seconds, so only these two are checked against $FN, the clock and the volume.
+const CREATED: usize = 0;
+const MFT_MODIFIED: usize = 2;
+const TIMESTAMP_NAMES: [&str; 4] = ["creation", "modification", "MFT modification", "access"];
+
+// Define the rules a file's timestamps are checked against
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TimestampRule {
+    // $SI creation time earlier than the $FN one, which the kernel copies from $SI
+    SiCreatedBeforeFn,
+    // $SI MFT modification time earlier than the $FN one, which is only updated on rename or move
+    SiMftModifiedBeforeFn,
+    // $SI time without sub-second part, where NTFS keeps 100 ns precision
+    ZeroedFraction,
+    // Time later than the scan
+    FutureTimestamp,
+    // Time earlier than the formatting of the volume
+    BeforeVolumeCreation,
+}
+
+impl TimestampRule {
+    // Stable identifier stored with each finding, to query by
+    pub fn id(&self) -> &'static str {
+        match self {
+            TimestampRule::SiCreatedBeforeFn => "SI_CREATED_BEFORE_FN",
+            TimestampRule::SiMftModifiedBeforeFn => "SI_MFT_MODIFIED_BEFORE_FN",
+            TimestampRule::ZeroedFraction => "ZEROED_FRACTION",
+            TimestampRule::FutureTimestamp => "FUTURE_TIMESTAMP",
+            TimestampRule::BeforeVolumeCreation => "BEFORE_VOLUME_CREATION",
+        }
+    }
+}
+
+// Define a struct to hold one finding of the analyzer
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimestampAnomaly {
+    pub rule: TimestampRule,
+    pub explanation: String,
+}
+
+// Define a struct to hold what a file's timestamps are checked against
+#[derive(Debug, Clone)]
+pub struct TimestampAnalyzer {
+    now: DateTime<Utc>,
+    volume_created: Option<DateTime<Utc>>,
+}
+
+impl TimestampAnalyzer {
+    pub fn new(now: DateTime<Utc>, volume_created: Option<DateTime<Utc>>) -> Self {
+        TimestampAnalyzer { now, volume_created }
+    }
+
+    // Take the volume creation time from the $STANDARD_INFORMATION of $Volume
+    pub fn read(reader: &mut MftReader, now: DateTime<Utc>) -> Result<Self> {
+        let entry_data = reader.read_mft_entry(VOLUME_RECORD_NUMBER)?;
+        let entry = MftEntry::parse(&entry_data).with_context(|| "Failed to parse $Volume")?;
+        let volume_created = LogicalFile::resolve(VOLUME_RECORD_NUMBER, entry, reader)?
+            .standard_information()
+            .with_context(|| "Failed to read $STANDARD_INFORMATION of $Volume")?
+            .map(|si| si.created);
+        Ok(TimestampAnalyzer::new(now, volume_created))
+    }
+
+    // Check the $STANDARD_INFORMATION times of a file and those of its preferred $FILE_NAME
+    pub fn analyze(&self, file: &LogicalFile) -> Result<Vec<TimestampAnomaly>> {
+        let mut anomalies = Vec::new();
+        let standard_information = file.standard_information()
+            .with_context(|| format!("Failed to read $STANDARD_INFORMATION of MFT entry {}", file.record_number()))?;
+        let file_names = file.file_names()
+            .with_context(|| format!("Failed to read $FILE_NAME of MFT entry {}", file.record_number()))?;
+
+        let si_times = standard_information.map(|si| [si.created, si.modified, si.mft_modified, si.accessed]);
+        let fn_times = preferred_name(&file_names).map(|name| [name.created, name.modified, name.mft_modified, name.accessed]);
+
+        if let (Some(si_times), Some(fn_times)) = (&si_times, &fn_times) {
+            for index in [CREATED, MFT_MODIFIED] {
+                let (si_time, fn_time) = (&si_times[index], &fn_times[index]);
+                if si_time >= fn_time {
+                    continue;
+                }
+                let rule = if index == CREATED { TimestampRule::SiCreatedBeforeFn } else { TimestampRule::SiMftModifiedBeforeFn };
+                anomalies.push(TimestampAnomaly {
+                    rule,
+                    explanation: format!(
+                        "$SI {} time {} is earlier than $FN {} time {}",
+                        TIMESTAMP_NAMES[index], format_timestamp(si_time), TIMESTAMP_NAMES[index], format_timestamp(fn_time),
+                    ),
+                });
+            }
+        }
+
+        if let Some(si_times) = &si_times {
+            for index in [CREATED, MFT_MODIFIED] {
+                let timestamp = &si_times[index];
+                if is_set(timestamp) && timestamp.nanosecond() == 0 {
+                    anomalies.push(TimestampAnomaly {
+                        rule: TimestampRule::ZeroedFraction,
+                        explanation: format!("$SI {} time {} has no sub-second part", TIMESTAMP_NAMES[index], format_timestamp(timestamp)),
+                    });
+                }
+            }
+        }
+
+        for (set_name, times) in [("$SI", &si_times), ("$FN", &fn_times)] {
+            let Some(times) = times else { continue };
+            for (index, timestamp) in times.iter().enumerate() {
+                if *timestamp > self.now {
+                    anomalies.push(TimestampAnomaly {
+                        rule: TimestampRule::FutureTimestamp,
+                        explanation: format!(
+                            "{} {} time {} is later than the scan at {}",
+                            set_name, TIMESTAMP_NAMES[index], format_timestamp(timestamp), format_timestamp(&self.now),
+                        ),
+                    });
+                }
+            }
+
+            let Some(volume_created) = &self.volume_created else { continue };
+            for index in [CREATED, MFT_MODIFIED] {
+                let timestamp = &times[index];
+                if is_set(timestamp) && timestamp < volume_created {
+                    anomalies.push(TimestampAnomaly {
+                        rule: TimestampRule::BeforeVolumeCreation,
+                        explanation: format!(
+                            "{} {} time {} is earlier than the volume creation at {}",
+                            set_name, TIMESTAMP_NAMES[index], format_timestamp(timestamp), format_timestamp(volume_created),
+                        ),
+                    });
+                }
+            }
+        }
+
+        Ok(anomalies)
+    }
+}
+
+fn is_set(timestamp: &DateTime<Utc>) -> bool {
+    timestamp.year() > UNSET_TIMESTAMP_YEAR
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mft_parser::FILE_RECORD_IN_USE;
+    use crate::test_support::{fake_logical_file, fake_stamped_attributes};
+    use crate::utils::filetime_to_datetime;
+
+    // 2023-05-01T12:00:00.123456Z and 2019-01-01T00:00:00Z as FILETIMEs
+    const FILETIME: u64 = 133_274_160_001_234_560;
+    const BACKDATED_FILETIME: u64 = 131_907_744_000_000_000;
+
+    fn fake_file(si_filetime: u64, fn_filetime: u64) -> LogicalFile {
+        fake_logical_file(64, FILE_RECORD_IN_USE, &fake_stamped_attributes("invoice.pdf", si_filetime, fn_filetime))
+    }
+
+    fn rule_ids(anomalies: &[TimestampAnomaly]) -> Vec<&'static str> {
+        anomalies.iter().map(|anomaly| anomaly.rule.id()).collect()
+    }
+
+    #[test]
+    fn test_backdated_standard_information() {
+        let analyzer = TimestampAnalyzer::new(filetime_to_datetime(FILETIME + 1), None);
+        assert!(analyzer.analyze(&fake_file(FILETIME, FILETIME)).unwrap().is_empty());
+
+        let anomalies = analyzer.analyze(&fake_file(BACKDATED_FILETIME, FILETIME)).unwrap();
+        // Modification and access times carried over by a copy are not taken for backdating
+        assert_eq!(rule_ids(&anomalies), vec![
+            "SI_CREATED_BEFORE_FN", "SI_MFT_MODIFIED_BEFORE_FN", "ZEROED_FRACTION", "ZEROED_FRACTION",
+        ]);
+        assert_eq!(anomalies[0].explanation, "$SI creation time 2019-01-01T00:00:00Z is earlier than $FN creation time 2023-05-01T12:00:00.123456Z");
+    }
+
+    #[test]
+    fn test_clock_and_volume_rules() {
+        // The file was stamped after the scan, and its $FN times predate the volume
+        let now = filetime_to_datetime(FILETIME - 1);
+        let analyzer = TimestampAnalyzer::new(now, Some(now));
+        let anomalies = analyzer.analyze(&fake_file(FILETIME, FILETIME - 10_000_000)).unwrap();
+        assert_eq!(rule_ids(&anomalies), vec![
+            "FUTURE_TIMESTAMP", "FUTURE_TIMESTAMP", "FUTURE_TIMESTAMP", "FUTURE_TIMESTAMP", "BEFORE_VOLUME_CREATION", "BEFORE_VOLUME_CREATION",
+        ]);
+        assert!(anomalies[4].explanation.starts_with("$FN creation time"));
+
+        // Timestamps that were never set are not taken for tampering
+        let analyzer = TimestampAnalyzer::new(now, Some(now));
+        assert!(analyzer.analyze(&fake_file(0, 0)).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,359 @@
+use crate::attribute::decode_utf16;
+use crate::content_reader::ContentReader;
+use crate::index::DirectoryIndex;
+use crate::logical_file::LogicalFile;
+use crate::mft_parser::{FileReference, MftEntry};
+use crate::mft_reader::MftReader;
+use crate::utils::filetime_to_datetime;
+use anyhow::{anyhow, bail, Context, Result};
+use byteorder::{ByteOrder, LittleEndian};
+use chrono::{DateTime, Utc};
+use std::fs::File;
+use std::io::{self, Read};
+
+// Record of the $Extend metadata directory, which holds the USN journal among other files
+pub const EXTEND_RECORD_NUMBER: u64 = 11;
+
+// The USN journal is the $J stream of $Extend\$UsnJrnl
+pub const USN_JOURNAL_NAME: &str = "$UsnJrnl";
+pub const USN_JOURNAL_STREAM: &str = "$J";
+
+// Define constants for USN record offsets common to every version
+const RECORD_LENGTH_OFFSET: usize = 0x00;
+const MAJOR_VERSION_OFFSET: usize = 0x04;
+const RECORD_HEADER_SIZE: usize = 0x08;
+
+// Records are aligned to 8 bytes, and far smaller than this even with the longest name
+const RECORD_ALIGNMENT: usize = 8;
+const MAX_RECORD_LENGTH: usize = 0x1_0000;
+
+// Version 4 records only track the ranges of a change already reported by a version 3 record
+const RANGE_TRACKING_VERSION: u16 = 4;
+
+// Journal data is read in blocks of this size
+const READ_BLOCK_SIZE: usize = 0x1_0000;
+
+// Define where the fields of a USN record are, which differs between versions 2 and 3
+struct RecordLayout {
+    file_reference: usize,
+    parent_reference: usize,
+    usn: usize,
+    timestamp: usize,
+    reason: usize,
+    file_attributes: usize,
+    file_name_length: usize,
+    file_name_offset: usize,
+    size: usize,
+}
+
+// USN_RECORD_V2, with 64-bit file references
+const RECORD_LAYOUT_V2: RecordLayout = RecordLayout {
+    file_reference: 0x08,
+    parent_reference: 0x10,
+    usn: 0x18,
+    timestamp: 0x20,
+    reason: 0x28,
+    file_attributes: 0x34,
+    file_name_length: 0x38,
+    file_name_offset: 0x3A,
+    size: 0x3C,
+};
+
+// USN_RECORD_V3, with 128-bit file ids whose low 64 bits are the file reference on NTFS
+const RECORD_LAYOUT_V3: RecordLayout = RecordLayout {
+    file_reference: 0x08,
+    parent_reference: 0x18,
+    usn: 0x28,
+    timestamp: 0x30,
+    reason: 0x38,
+    file_attributes: 0x44,
+    file_name_length: 0x48,
+    file_name_offset: 0x4A,
+    size: 0x4C,
+};
+
+// Names of the USN_REASON flags, in bit order
+const REASON_NAMES: [(u32, &str); 24] = [
+    (0x0000_0001, "DATA_OVERWRITE"),
+    (0x0000_0002, "DATA_EXTEND"),
+    (0x0000_0004, "DATA_TRUNCATION"),
+    (0x0000_0010, "NAMED_DATA_OVERWRITE"),
+    (0x0000_0020, "NAMED_DATA_EXTEND"),
+    (0x0000_0040, "NAMED_DATA_TRUNCATION"),
+    (0x0000_0100, "FILE_CREATE"),
+    (0x0000_0200, "FILE_DELETE"),
+    (0x0000_0400, "EA_CHANGE"),
+    (0x0000_0800, "SECURITY_CHANGE"),
+    (0x0000_1000, "RENAME_OLD_NAME"),
+    (0x0000_2000, "RENAME_NEW_NAME"),
+    (0x0000_4000, "INDEXABLE_CHANGE"),
+    (0x0000_8000, "BASIC_INFO_CHANGE"),
+    (0x0001_0000, "HARD_LINK_CHANGE"),
+    (0x0002_0000, "COMPRESSION_CHANGE"),
+    (0x0004_0000, "ENCRYPTION_CHANGE"),
+    (0x0008_0000, "OBJECT_ID_CHANGE"),
+    (0x0010_0000, "REPARSE_POINT_CHANGE"),
+    (0x0020_0000, "STREAM_CHANGE"),
+    (0x0040_0000, "TRANSACTED_CHANGE"),
+    (0x0080_0000, "INTEGRITY_CHANGE"),
+    (0x0100_0000, "DESIRED_STORAGE_CLASS_CHANGE"),
+    (0x8000_0000, "CLOSE"),
+];
+
+// Define a struct to hold one change record of the USN journal
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UsnRecord {
+    pub usn: u64,
+    pub timestamp: DateTime<Utc>,
+    pub file_reference: FileReference,
+    // The directory the file was in when the change was recorded
+    pub parent_reference: FileReference,
+    // USN_REASON flags accumulated since the file was opened
+    pub reason: u32,
+    pub file_attributes: u32,
+    pub file_name: String,
+}
+
+impl UsnRecord {
+    pub fn parse(record: &[u8]) -> Result<Self> {
+        if record.len() < RECORD_HEADER_SIZE {
+            bail!("USN record of {} bytes is too short", record.len());
+        }
+
+        let layout = match LittleEndian::read_u16(&record[MAJOR_VERSION_OFFSET..]) {
+            2 => &RECORD_LAYOUT_V2,
+            3 => &RECORD_LAYOUT_V3,
+            version => bail!("Unsupported USN record version {}", version),
+        };
+        if record.len() < layout.size {
+            bail!("USN record of {} bytes is too short for its version", record.len());
+        }
+
+        let name_offset = usize::from(LittleEndian::read_u16(&record[layout.file_name_offset..]));
+        let name_length = usize::from(LittleEndian::read_u16(&record[layout.file_name_length..]));
+        let file_name = match record.get(name_offset..name_offset + name_length) {
+            Some(file_name) => decode_utf16(file_name),
+            None => bail!("Name of USN record at offset {} runs past the record", name_offset),
+        };
+
+        Ok(UsnRecord {
+            usn: LittleEndian::read_u64(&record[layout.usn..]),
+            timestamp: filetime_to_datetime(LittleEndian::read_u64(&record[layout.timestamp..])),
+            file_reference: FileReference::from_u64(LittleEndian::read_u64(&record[layout.file_reference..])),
+            parent_reference: FileReference::from_u64(LittleEndian::read_u64(&record[layout.parent_reference..])),
+            reason: LittleEndian::read_u32(&record[layout.reason..]),
+            file_attributes: LittleEndian::read_u32(&record[layout.file_attributes..]),
+            file_name,
+        })
+    }
+}
+
+// Readable names of USN_REASON flags joined by '|', with any unknown bits in hex
+pub fn reason_names(reason: u32) -> String {
+    let mut names: Vec<String> = REASON_NAMES.iter()
+        .filter(|(flag, _)| reason & flag != 0)
+        .map(|(_, name)| name.to_string())
+        .collect();
+    let unknown = REASON_NAMES.iter().fold(reason, |rest, (flag, _)| rest & !flag);
+    if unknown != 0 {
+        names.push(format!("{:#x}", unknown));
+    }
+    names.join("|")
+}
+
+// Define an iterator over the records of a $J stream. The journal is sparse up to the oldest
+// record kept, and records are padded with zeros, which are skipped. A record that can't be
+// parsed is reported and skipped, so the records after it are still read.
+pub struct UsnRecords<R> {
+    source: R,
+    buffer: Vec<u8>,
+    // Start of the next record in `buffer`, and its offset in the stream
+    start: usize,
+    offset: u64,
+    finished: bool,
+}
+
+impl<R: Read> UsnRecords<R> {
+    pub fn new(source: R) -> Self {
+        UsnRecords { source, buffer: Vec::new(), start: 0, offset: 0, finished: false }
+    }
+
+    // Read until at least `wanted` bytes follow the start of the next record, returning false
+    // if the stream ends first
+    fn fill(&mut self, wanted: usize) -> io::Result<bool> {
+        if self.buffer.len() - self.start >= wanted {
+            return Ok(true);
+        }
+        self.buffer.drain(..self.start);
+        self.start = 0;
+
+        let mut block = vec![0; READ_BLOCK_SIZE];
+        while self.buffer.len() < wanted {
+            match self.source.read(&mut block) {
+                Ok(0) => return Ok(false),
+                Ok(read) => self.buffer.extend_from_slice(&block[..read]),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(true)
+    }
+
+    fn advance(&mut self, count: usize) {
+        self.start += count;
+        self.offset += count as u64;
+    }
+}
+
+impl<R: Read> Iterator for UsnRecords<R> {
+    type Item = Result<UsnRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.finished {
+            match self.fill(RECORD_HEADER_SIZE) {
+                Ok(true) => {}
+                Ok(false) => return None,
+                Err(e) => {
+                    self.finished = true;
+                    return Some(Err(e).with_context(|| format!("Failed to read the USN journal at offset {}", self.offset)));
+                }
+            }
+
+            let available = &self.buffer[self.start..];
+            let length = LittleEndian::read_u32(&available[RECORD_LENGTH_OFFSET..]) as usize;
+            if length == 0 {
+                // Skip the whole run of zeros in the buffer, keeping to the record alignment
+                let zeros = available.iter().position(|&byte| byte != 0).unwrap_or(available.len());
+                self.advance((zeros & !(RECORD_ALIGNMENT - 1)).max(RECORD_ALIGNMENT));
+                continue;
+            }
+
+            let offset = self.offset;
+            if length < RECORD_HEADER_SIZE || !length.is_multiple_of(RECORD_ALIGNMENT) || length > MAX_RECORD_LENGTH {
+                self.advance(RECORD_ALIGNMENT);
+                return Some(Err(anyhow!("Invalid USN record length {} at offset {}", length, offset)));
+            }
+            match self.fill(length) {
+                Ok(true) => {}
+                Ok(false) => {
+                    self.finished = true;
+                    return Some(Err(anyhow!("USN record at offset {} runs past the end of the journal", offset)));
+                }
+                Err(e) => {
+                    self.finished = true;
+                    return Some(Err(e).with_context(|| format!("Failed to read the USN journal at offset {}", offset)));
+                }
+            }
+
+            let record = &self.buffer[self.start..self.start + length];
+            let version = LittleEndian::read_u16(&record[MAJOR_VERSION_OFFSET..]);
+            let parsed = (version != RANGE_TRACKING_VERSION).then(|| {
+                UsnRecord::parse(record).with_context(|| format!("Failed to parse the USN record at offset {}", offset))
+            });
+            self.advance(length);
+            if parsed.is_some() {
+                return parsed;
+            }
+        }
+        None
+    }
+}
+
+// Find the record of $UsnJrnl through the index of $Extend, returning None for volumes that
+// have no journal, such as those formatted by NTFS 1.2
+pub fn find_usn_journal(reader: &mut MftReader) -> Result<Option<u64>> {
+    let entry_data = reader.read_mft_entry(EXTEND_RECORD_NUMBER)?;
+    let entry = MftEntry::parse(&entry_data).with_context(|| "Failed to parse $Extend")?;
+    let extend = LogicalFile::resolve(EXTEND_RECORD_NUMBER, entry, reader)?;
+    let index = DirectoryIndex::read(&extend, reader)
+        .with_context(|| "Failed to read the index of $Extend")?;
+
+    Ok(index.and_then(|index| {
+        index.entries.iter()
+            .find(|entry| entry.file_name.name == USN_JOURNAL_NAME)
+            .map(|entry| entry.file_reference.record_number)
+    }))
+}
+
+// Open the records of the USN journal of the volume, if it has one
+pub fn open_usn_journal(reader: &mut MftReader) -> Result<Option<UsnRecords<ContentReader<&mut File>>>> {
+    let Some(record_number) = find_usn_journal(reader)? else {
+        return Ok(None);
+    };
+    let stream = reader.open_stream(record_number, USN_JOURNAL_STREAM)
+        .with_context(|| format!("Failed to open {}:{}", USN_JOURNAL_NAME, USN_JOURNAL_STREAM))?;
+    Ok(Some(UsnRecords::new(stream)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    // 2022-01-01T00:00:00Z as a FILETIME
+    const FILETIME: u64 = 132_854_688_000_000_000;
+
+    // Build a USN record of the given version, padded to the record alignment
+    fn fake_usn_record(version: u16, file_reference: u64, parent_reference: u64, reason: u32, name: &str) -> Vec<u8> {
+        let layout = if version == 3 { &RECORD_LAYOUT_V3 } else { &RECORD_LAYOUT_V2 };
+        let name: Vec<u8> = name.encode_utf16().flat_map(u16::to_le_bytes).collect();
+        let length = (layout.size + name.len()).next_multiple_of(RECORD_ALIGNMENT);
+
+        let mut record = vec![0; length];
+        LittleEndian::write_u32(&mut record[RECORD_LENGTH_OFFSET..], length as u32);
+        LittleEndian::write_u16(&mut record[MAJOR_VERSION_OFFSET..], version);
+        LittleEndian::write_u64(&mut record[layout.file_reference..], file_reference);
+        LittleEndian::write_u64(&mut record[layout.parent_reference..], parent_reference);
+        LittleEndian::write_u64(&mut record[layout.usn..], 0x4000);
+        LittleEndian::write_u64(&mut record[layout.timestamp..], FILETIME);
+        LittleEndian::write_u32(&mut record[layout.reason..], reason);
+        LittleEndian::write_u16(&mut record[layout.file_name_length..], name.len() as u16);
+        LittleEndian::write_u16(&mut record[layout.file_name_offset..], layout.size as u16);
+        record[layout.size..layout.size + name.len()].copy_from_slice(&name);
+        record
+    }
+
+    #[test]
+    fn test_usn_record_parsing() {
+        let record = UsnRecord::parse(&fake_usn_record(2, 0x0002_0000_0000_0040, 0x0005_0000_0000_0005, 0x8000_0100, "notes.txt")).unwrap();
+        assert_eq!(record.file_reference, FileReference { record_number: 0x40, sequence_number: 2 });
+        assert_eq!(record.parent_reference, FileReference { record_number: 5, sequence_number: 5 });
+        assert_eq!(record.usn, 0x4000);
+        assert_eq!(record.timestamp.to_rfc3339(), "2022-01-01T00:00:00+00:00");
+        assert_eq!(record.file_name, "notes.txt");
+        assert_eq!(reason_names(record.reason), "FILE_CREATE|CLOSE");
+
+        let record = UsnRecord::parse(&fake_usn_record(3, 0x0001_0000_0000_0041, 0x0005_0000_0000_0005, 0x0000_1000, "old.txt")).unwrap();
+        assert_eq!(record.file_reference, FileReference { record_number: 0x41, sequence_number: 1 });
+        assert_eq!(record.file_name, "old.txt");
+        assert_eq!(reason_names(record.reason | 0x0000_0008), "RENAME_OLD_NAME|0x8");
+
+        let mut truncated = fake_usn_record(2, 0x40, 5, 0x100, "notes.txt");
+        LittleEndian::write_u16(&mut truncated[RECORD_LAYOUT_V2.file_name_length..], 0x100);
+        assert!(UsnRecord::parse(&truncated).is_err());
+    }
+
+    #[test]
+    fn test_usn_journal_reading() {
+        // The sparse start of the journal, two records, a range tracking record and a damaged length
+        let mut journal = vec![0; 3 * READ_BLOCK_SIZE];
+        journal.extend(fake_usn_record(2, 0x40, 5, 0x100, "a.txt"));
+        journal.extend(fake_usn_record(4, 0x40, 5, 0, ""));
+        journal.extend(vec![0; 16]);
+        journal.extend(12u32.to_le_bytes());
+        journal.extend(vec![0; 4]);
+        journal.extend(fake_usn_record(3, 0x41, 5, 0x200, "b.txt"));
+
+        let records: Vec<Result<UsnRecord>> = UsnRecords::new(Cursor::new(journal)).collect();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0].as_ref().unwrap().file_name, "a.txt");
+        assert!(records[1].is_err());
+        assert_eq!(records[2].as_ref().unwrap().file_name, "b.txt");
+
+        // A record cut short by the end of the stream
+        let record = fake_usn_record(2, 0x40, 5, 0x100, "a.txt");
+        let records: Vec<Result<UsnRecord>> = UsnRecords::new(Cursor::new(&record[..40])).collect();
+        assert_eq!(records.len(), 1);
+        assert!(records[0].is_err());
+    }
+}
@@ -2,6 +2,11 @@ use std::fs::File;
 use std::io::{self, Read, Seek, SeekFrom};
 use byteorder::{ReadBytesExt, LittleEndian};
 use anyhow::{Result, Context};
+use chrono::{DateTime, Utc};
+
+// Number of seconds between the FILETIME epoch (1601-01-01) and the Unix epoch
+const FILETIME_UNIX_EPOCH_OFFSET: i64 = 11_644_473_600;
+const FILETIME_TICKS_PER_SECOND: u64 = 10_000_000;
 
 pub fn read_bytes(file: &mut File, offset: u64, size: usize) -> Result<Vec<u8>> {
     let mut buffer = vec![0; size];
@@ -42,4 +47,11 @@ pub fn read_string(file: &mut File, offset: u64, length: usize) -> Result<String
     Ok(string)
 }
 
+// Convert a Windows FILETIME (100ns intervals since 1601-01-01 UTC) to a UTC datetime
+pub fn filetime_to_datetime(filetime: u64) -> DateTime<Utc> {
+    let seconds = (filetime / FILETIME_TICKS_PER_SECOND) as i64 - FILETIME_UNIX_EPOCH_OFFSET;
+    let nanoseconds = (filetime % FILETIME_TICKS_PER_SECOND) as u32 * 100;
+    DateTime::from_timestamp(seconds, nanoseconds).unwrap_or_default()
+}
+
 // Add more utility functions as needed for your project.
@@ -0,0 +1,221 @@
+use crate::lzx;
+use crate::xpress_huffman;
+use anyhow::{bail, Result};
+use byteorder::{ByteOrder, LittleEndian};
+use std::fmt;
+
+// Reparse tag of files whose data is provided by the Windows Overlay Filter
+pub const IO_REPARSE_TAG_WOF: u32 = 0x8000_0017;
+
+// Named $DATA stream holding the compressed content of a WOF file
+pub const WOF_COMPRESSED_DATA_STREAM: &str = "WofCompressedData";
+
+// Define constants for the WOF reparse data offsets and values
+const REPARSE_TAG_OFFSET: usize = 0x00;
+pub(crate) const WOF_VERSION_OFFSET: usize = 0x08;
+pub(crate) const WOF_PROVIDER_OFFSET: usize = 0x0C;
+pub(crate) const FILE_PROVIDER_VERSION_OFFSET: usize = 0x10;
+pub(crate) const FILE_PROVIDER_ALGORITHM_OFFSET: usize = 0x14;
+pub(crate) const WOF_REPARSE_DATA_SIZE: usize = 0x18;
+pub(crate) const WOF_CURRENT_VERSION: u32 = 1;
+// Data backed by a file's own WofCompressedData stream, rather than by an external WIM
+pub(crate) const WOF_PROVIDER_FILE: u32 = 2;
+
+// Define the compression algorithms of the WOF file provider
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WofAlgorithm {
+    Xpress4k,
+    Lzx,
+    Xpress8k,
+    Xpress16k,
+}
+
+impl WofAlgorithm {
+    pub fn from_u32(value: u32) -> Option<Self> {
+        match value {
+            0 => Some(WofAlgorithm::Xpress4k),
+            1 => Some(WofAlgorithm::Lzx),
+            2 => Some(WofAlgorithm::Xpress8k),
+            3 => Some(WofAlgorithm::Xpress16k),
+            _ => None,
+        }
+    }
+
+    // Size of the chunks the data is compressed in
+    pub fn chunk_size(self) -> u64 {
+        match self {
+            WofAlgorithm::Xpress4k => 4096,
+            WofAlgorithm::Xpress8k => 8192,
+            WofAlgorithm::Xpress16k => 16384,
+            WofAlgorithm::Lzx => 32768,
+        }
+    }
+
+    // Decompress a single chunk. Chunks that did not shrink are stored as is.
+    pub fn decompress_chunk(self, chunk: &[u8], output_size: usize) -> Result<Vec<u8>> {
+        if chunk.len() == output_size {
+            return Ok(chunk.to_vec());
+        }
+
+        match self {
+            WofAlgorithm::Lzx => lzx::decompress(chunk, output_size),
+            _ => xpress_huffman::decompress(chunk, output_size),
+        }
+    }
+}
+
+impl fmt::Display for WofAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            WofAlgorithm::Xpress4k => "XPRESS4K",
+            WofAlgorithm::Lzx => "LZX",
+            WofAlgorithm::Xpress8k => "XPRESS8K",
+            WofAlgorithm::Xpress16k => "XPRESS16K",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// Define how the WOF file provider says the data of a file is compressed. A provider version or
+// algorithm this reader doesn't know is kept as is, so only reading the content fails.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WofCompression {
+    Algorithm(WofAlgorithm),
+    Unsupported { provider_version: u32, algorithm: u32 },
+}
+
+impl WofCompression {
+    // Detect a WOF file provider reparse point, returning None for any other reparse point
+    pub fn from_reparse_data(data: &[u8]) -> Result<Option<Self>> {
+        if data.len() < 4 || LittleEndian::read_u32(&data[REPARSE_TAG_OFFSET..]) != IO_REPARSE_TAG_WOF {
+            return Ok(None);
+        }
+        if data.len() < WOF_REPARSE_DATA_SIZE {
+            bail!("WOF reparse data of {} bytes is too short", data.len());
+        }
+
+        let wof_version = LittleEndian::read_u32(&data[WOF_VERSION_OFFSET..]);
+        let provider = LittleEndian::read_u32(&data[WOF_PROVIDER_OFFSET..]);
+        if wof_version != WOF_CURRENT_VERSION || provider != WOF_PROVIDER_FILE {
+            return Ok(None);
+        }
+
+        let provider_version = LittleEndian::read_u32(&data[FILE_PROVIDER_VERSION_OFFSET..]);
+        let algorithm = LittleEndian::read_u32(&data[FILE_PROVIDER_ALGORITHM_OFFSET..]);
+        let compression = match WofAlgorithm::from_u32(algorithm) {
+            Some(algorithm) if provider_version == WOF_CURRENT_VERSION => WofCompression::Algorithm(algorithm),
+            _ => WofCompression::Unsupported { provider_version, algorithm },
+        };
+        Ok(Some(compression))
+    }
+
+    // The algorithm to decompress the data with
+    pub fn algorithm(self) -> Result<WofAlgorithm> {
+        match self {
+            WofCompression::Algorithm(algorithm) => Ok(algorithm),
+            WofCompression::Unsupported { provider_version, algorithm } => {
+                bail!("Unsupported WOF file provider version {} or algorithm {}", provider_version, algorithm)
+            }
+        }
+    }
+}
+
+impl fmt::Display for WofCompression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WofCompression::Algorithm(algorithm) => write!(f, "{}", algorithm),
+            WofCompression::Unsupported { provider_version, algorithm } => {
+                write!(f, "UNSUPPORTED (version {}, algorithm {})", provider_version, algorithm)
+            }
+        }
+    }
+}
+
+// Decode the chunk offset table at the start of a WofCompressedData stream. It holds the end
+// of every chunk but the last, relative to the end of the table, as 32-bit values, or 64-bit
+// ones for files of 4 GiB and more. Returns the absolute start of every chunk followed by the
+// end of the last one. No chunk is stored in more bytes than it decompresses to.
+pub fn read_chunk_table(table: &[u8], uncompressed_size: u64, compressed_size: u64, algorithm: WofAlgorithm) -> Result<Vec<u64>> {
+    let entry_size = chunk_table_entry_size(uncompressed_size);
+    let table_size = chunk_table_size(uncompressed_size, algorithm);
+    if (table.len() as u64) < table_size || compressed_size < table_size {
+        bail!("WofCompressedData stream is too short for its chunk table of {} bytes", table_size);
+    }
+
+    let mut offsets = vec![table_size];
+    for entry in table[..table_size as usize].chunks_exact(entry_size) {
+        let offset = if entry_size == 8 { LittleEndian::read_u64(entry) } else { u64::from(LittleEndian::read_u32(entry)) };
+        let Some(offset) = table_size.checked_add(offset) else {
+            bail!("WofCompressedData chunk offset {} is out of range", offset);
+        };
+        offsets.push(offset);
+    }
+    offsets.push(compressed_size);
+
+    if offsets.windows(2).any(|pair| pair[0] > pair[1]) {
+        bail!("WofCompressedData chunk offsets are not in increasing order");
+    }
+    if let Some(index) = offsets.windows(2).position(|pair| pair[1] - pair[0] > algorithm.chunk_size()) {
+        bail!("WofCompressedData chunk {} is longer than the {} byte chunks of {}", index, algorithm.chunk_size(), algorithm);
+    }
+    Ok(offsets)
+}
+
+// Size in bytes of the chunk offset table
+pub fn chunk_table_size(uncompressed_size: u64, algorithm: WofAlgorithm) -> u64 {
+    let chunk_count = uncompressed_size.div_ceil(algorithm.chunk_size());
+    chunk_count.saturating_sub(1) * chunk_table_entry_size(uncompressed_size) as u64
+}
+
+fn chunk_table_entry_size(uncompressed_size: u64) -> usize {
+    if uncompressed_size > u64::from(u32::MAX) { 8 } else { 4 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::fake_wof_reparse_data;
+
+    #[test]
+    fn test_wof_reparse_detection() {
+        let mut data = fake_wof_reparse_data(3);
+        let compression = WofCompression::from_reparse_data(&data).unwrap().unwrap();
+        assert_eq!(compression.algorithm().unwrap(), WofAlgorithm::Xpress16k);
+        assert_eq!(compression.to_string(), "XPRESS16K");
+
+        // Backed by an external WIM, so there is no local data to decompress
+        LittleEndian::write_u32(&mut data[WOF_PROVIDER_OFFSET..], 1);
+        assert_eq!(WofCompression::from_reparse_data(&data).unwrap(), None);
+
+        // A symbolic link
+        LittleEndian::write_u32(&mut data[REPARSE_TAG_OFFSET..], 0xA000_000C);
+        assert_eq!(WofCompression::from_reparse_data(&data).unwrap(), None);
+    }
+
+    #[test]
+    fn test_unsupported_wof_algorithm_kept() {
+        let compression = WofCompression::from_reparse_data(&fake_wof_reparse_data(7)).unwrap().unwrap();
+        assert_eq!(compression, WofCompression::Unsupported { provider_version: 1, algorithm: 7 });
+        assert_eq!(compression.to_string(), "UNSUPPORTED (version 1, algorithm 7)");
+        assert!(compression.algorithm().is_err());
+    }
+
+    #[test]
+    fn test_chunk_table_decoding() {
+        // Three 4 KiB chunks: the table holds the ends of the first two
+        let mut table = Vec::new();
+        table.extend(1000u32.to_le_bytes());
+        table.extend(5096u32.to_le_bytes());
+
+        let offsets = read_chunk_table(&table, 10000, 8 + 6000, WofAlgorithm::Xpress4k).unwrap();
+        assert_eq!(offsets, vec![8, 1008, 5104, 6008]);
+        assert_eq!(chunk_table_size(4096, WofAlgorithm::Lzx), 0);
+        assert!(read_chunk_table(&table[..4], 10000, 6008, WofAlgorithm::Xpress4k).is_err());
+
+        // A last chunk running past the chunk size, and an offset past the end of the address space
+        assert!(read_chunk_table(&table, 10000, 8 + 5096 + 4097, WofAlgorithm::Xpress4k).is_err());
+        let table = vec![0xFF; 1 << 20];
+        let error = read_chunk_table(&table, (1 << 32) + 32768, u64::MAX, WofAlgorithm::Lzx).unwrap_err();
+        assert!(error.to_string().contains("out of range"));
+    }
+}
@@ -0,0 +1,170 @@
+use crate::huffman::HuffmanDecoder;
+use anyhow::{bail, Context, Result};
+use byteorder::{ByteOrder, LittleEndian};
+
+// Each block starts with the codeword lengths of its 512 symbols, packed two per byte
+const HUFFMAN_TABLE_SIZE: usize = 256;
+const SYMBOL_COUNT: usize = 512;
+const MAX_CODE_LENGTH: u8 = 15;
+
+// A new Huffman table is transmitted for every 64 KiB of output
+const BLOCK_SIZE: usize = 65536;
+
+// Symbols below 256 are literals, the rest encode a match
+const LITERAL_COUNT: u16 = 256;
+const MIN_MATCH_LENGTH: usize = 3;
+
+// Decompress data in the LZ77+Huffman format of [MS-XCA], which WOF calls XPRESS. Codewords are
+// read from 16-bit little-endian words, most significant bit first, while extended match lengths
+// are read as whole bytes from the same input position.
+pub fn decompress(input: &[u8], output_size: usize) -> Result<Vec<u8>> {
+    let mut output = Vec::with_capacity(output_size);
+    let mut position = 0;
+
+    while output.len() < output_size {
+        if position + HUFFMAN_TABLE_SIZE > input.len() {
+            bail!("XPRESS Huffman table at offset {} runs past the end of the input", position);
+        }
+        let lengths: Vec<u8> = input[position..position + HUFFMAN_TABLE_SIZE].iter()
+            .flat_map(|&byte| [byte & 0x0F, byte >> 4])
+            .collect();
+        debug_assert_eq!(lengths.len(), SYMBOL_COUNT);
+        let decoder = HuffmanDecoder::new(&lengths, MAX_CODE_LENGTH)
+            .with_context(|| format!("Invalid XPRESS Huffman table at offset {}", position))?;
+        position += HUFFMAN_TABLE_SIZE;
+
+        let mut next_bits = (u32::from(read_u16(input, position)) << 16) | u32::from(read_u16(input, position + 2));
+        position += 4;
+        let mut extra_bit_count: i32 = 16;
+        let block_end = (output.len() + BLOCK_SIZE).min(output_size);
+
+        while output.len() < block_end {
+            let (symbol, length) = match decoder.decode(next_bits >> (32 - MAX_CODE_LENGTH)) {
+                Some(decoded) => decoded,
+                None => bail!("Invalid XPRESS codeword at offset {}", position),
+            };
+            consume_bits(input, &mut position, &mut next_bits, &mut extra_bit_count, length);
+
+            if symbol < LITERAL_COUNT {
+                output.push(symbol as u8);
+                continue;
+            }
+
+            let symbol = symbol - LITERAL_COUNT;
+            let offset_bit_count = (symbol >> 4) as u8;
+            let mut match_length = usize::from(symbol & 0x0F);
+            if match_length == 15 {
+                match_length = usize::from(read_u8(input, position)?);
+                position += 1;
+                if match_length == 255 {
+                    match_length = usize::from(LittleEndian::read_u16(read_bytes(input, position, 2)?));
+                    position += 2;
+                    if match_length == 0 {
+                        match_length = LittleEndian::read_u32(read_bytes(input, position, 4)?) as usize;
+                        position += 4;
+                    }
+                    if match_length < 15 {
+                        bail!("Invalid XPRESS match length {}", match_length);
+                    }
+                    match_length -= 15;
+                }
+                match_length += 15;
+            }
+            // A corrupt extended length could ask for gigabytes, so stop at the expected size
+            match_length = (match_length + MIN_MATCH_LENGTH).min(output_size - output.len());
+
+            let offset_bits = if offset_bit_count == 0 { 0 } else { next_bits >> (32 - u32::from(offset_bit_count)) };
+            let offset = ((1 << offset_bit_count) | offset_bits) as usize;
+            consume_bits(input, &mut position, &mut next_bits, &mut extra_bit_count, offset_bit_count);
+
+            if offset > output.len() {
+                bail!("XPRESS match offset {} exceeds the {} bytes decompressed so far", offset, output.len());
+            }
+            // Copy byte by byte, as the source may overlap the bytes being written
+            let source = output.len() - offset;
+            for index in 0..match_length {
+                output.push(output[source + index]);
+            }
+        }
+    }
+
+    output.truncate(output_size);
+    Ok(output)
+}
+
+// Drop consumed bits from the front of the bit buffer, refilling it a word at a time
+fn consume_bits(input: &[u8], position: &mut usize, next_bits: &mut u32, extra_bit_count: &mut i32, count: u8) {
+    if count == 0 {
+        return;
+    }
+
+    *next_bits <<= count;
+    *extra_bit_count -= i32::from(count);
+    if *extra_bit_count < 0 {
+        *next_bits |= u32::from(read_u16(input, *position)) << -*extra_bit_count;
+        *extra_bit_count += 16;
+        *position += 2;
+    }
+}
+
+// Words past the end of the input read as zero, as the bit buffer runs ahead of the last codeword
+fn read_u16(input: &[u8], position: usize) -> u16 {
+    input.get(position..position + 2).map_or(0, LittleEndian::read_u16)
+}
+
+fn read_u8(input: &[u8], position: usize) -> Result<u8> {
+    Ok(read_bytes(input, position, 1)?[0])
+}
+
+fn read_bytes(input: &[u8], position: usize, count: usize) -> Result<&[u8]> {
+    match input.get(position..position + count) {
+        Some(bytes) => Ok(bytes),
+        None => bail!("XPRESS match length at offset {} runs past the end of the input", position),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Build a block with the given codeword lengths followed by the given bit stream words
+    fn fake_block(lengths: &[(usize, u8)], words: &[u16]) -> Vec<u8> {
+        let mut block = vec![0; HUFFMAN_TABLE_SIZE];
+        for &(symbol, length) in lengths {
+            block[symbol / 2] |= length << (4 * (symbol % 2));
+        }
+        for word in words {
+            block.extend(word.to_le_bytes());
+        }
+        block
+    }
+
+    #[test]
+    fn test_literals_and_matches() {
+        // 'a' is codeword 0 and a length 3 match at offset 1 is codeword 1
+        let input = fake_block(&[(b'a' as usize, 1), (0x100, 1)], &[0b0100_0000_0000_0000, 0]);
+        assert_eq!(decompress(&input, 4).unwrap(), b"aaaa");
+
+        // 'a' is 0, 'b' is 10 and a length 4 match with one offset bit is 11, followed by
+        // the offset bit 0 for an offset of 2
+        let input = fake_block(&[(b'a' as usize, 1), (b'b' as usize, 2), (0x111, 2)], &[0b0101_1000_0000_0000, 0]);
+        assert_eq!(decompress(&input, 6).unwrap(), b"ababab");
+    }
+
+    #[test]
+    fn test_invalid_input() {
+        // A match before any output
+        let input = fake_block(&[(b'a' as usize, 1), (0x100, 1)], &[0b1000_0000_0000_0000, 0]);
+        assert!(decompress(&input, 4).is_err());
+        // A truncated table
+        assert!(decompress(&[0x11; 100], 4).is_err());
+    }
+
+    #[test]
+    fn test_match_length_bounded_by_output() {
+        // 'a', then a match at offset 1 whose extended length is the largest 32-bit value
+        let mut input = fake_block(&[(b'a' as usize, 1), (0x10F, 1)], &[0b0100_0000_0000_0000, 0]);
+        input.extend([0xFF, 0x00, 0x00, 0xFF, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(decompress(&input, 16).unwrap(), vec![b'a'; 16]);
+    }
+}